@@ -1,6 +1,6 @@
 use cala::video::rgb::SRgb32;
 use cala::graphics::{
-    shader, Group, Shader, ShaderBuilder, ShapeBuilder,
+    shader, Group, Shader, ShapeBuilder,
     Transform, Canvas
 };
 use cala::window::{Frame, input};
@@ -60,7 +60,7 @@ impl State {
 
     fn input(&mut self, input: Input) {
         match input {
-            Input::Key(_mods, Key::Back, true) => std::process::exit(0),
+            Input::Key(_mods, Key::Back, true) => cala::graphics::quit(),
             input => println!("{:?}", input),
         }
     }
@@ -33,6 +33,13 @@
 //! When putting a shape into a group you may attach a transform and optionally
 //! texture coordinates to it.
 //!
+//! # Backends
+//! Drawing happens on the thread that calls
+//! [`draw_thread()`](fn.draw_thread.html), which opens a window and renders
+//! with the GPU.  On machines without a GPU or display, call
+//! [`draw_thread_software()`](fn.draw_thread_software.html) instead, which
+//! renders the same commands in software.
+//!
 //! # Coordinate System
 //! ![X goes from 0 to 1, Y goes from 0 to `Canvas.height()`](https://raw.githubusercontent.com/libcala/window/5205e59f0cd9f37a619f590e94218900afc2395b/res/coordinate_system.svg)
 //!
@@ -42,8 +49,7 @@
 //! use cala::*;
 //!
 //! use cala::graphics::{
-//!     color::SRgb32, shader, Group, Shader, ShaderBuilder, ShapeBuilder,
-//!     Transform,
+//!     color::SRgb32, shader, Group, Shader, ShapeBuilder, Transform,
 //! };
 //! use cala::input::{GameInput, Input, TextInput, UiInput};
 //!
@@ -124,14 +130,43 @@
 
 use std::{
     cell::RefCell,
+    collections::HashMap,
+    future::Future,
     mem::MaybeUninit,
+    pin::Pin,
+    rc::Rc,
     sync::{
-        atomic::{AtomicU32, Ordering},
-        Arc, Condvar, Mutex, Once,
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, Once,
     },
-    task::Waker,
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
+mod animation;
+mod atlas;
+mod config;
+mod effect;
+mod gl;
+mod glsl;
+mod json;
+mod mesh;
+mod pacing;
+mod particles;
+mod primitives;
+mod replay;
+pub mod scene;
+mod software;
+mod text;
+mod tilemap;
+mod transform;
+mod vector;
+mod xml;
+
+use glsl::CompileInternal;
+use replay::Recorder;
+use software::{SoftShape, Software};
+
 /// A 2D rectangular image.
 ///
 /// ---
@@ -161,14 +196,63 @@ pub(super) enum GpuCmd {
     Background(f32, f32, f32),
     Draw(u32, u32),
     DrawGraphic(u32, u32, u32),
+    /// Draw colored triangles with a shader (X, Y and RGBA of each vertex),
+    /// for primitives drawn on a `Canvas`.
+    Triangles(u32, Vec<f32>),
     SetCamera(Transform),
+    /// Map drawing into part of the canvas (`None` for all of it).
+    SetViewport(Option<Rect>),
+    /// Clip drawing to part of the canvas (`None` to stop clipping).
+    SetScissor(Option<Rect>),
+    /// Set how colors are blended.
+    SetBlend(Blend),
+    /// Enable or disable the depth test.
+    SetDepthTest(bool),
+    /// Enable or disable writing to the depth buffer.
+    SetDepthWrite(bool),
     SetTint(u32, [f32; 4]),
+    /// Capture the frame once it's finished.
+    Capture(Arc<Mutex<CaptureInternal>>),
+    /// Record the commands of this frame and the ones after it, for this many
+    /// frames in all.
+    Record(Arc<Mutex<replay::RecordingInternal>>, u32),
+    /// Replace the effects run over each finished frame.
+    SetEffects(Vec<effect::EffectInternal>),
+    /// Clear the render target to a color.
+    Clear(f32, f32, f32, f32),
+    /// Create a render target, rendering to a raster of the given size.
+    TargetId(u32, u16, u16),
+    /// Execute a command on a render target instead of the frame.
+    Target(u32, Box<GpuCmd>),
     RasterId(pix::Raster<pix::rgb::SRgba8>, u32),
-    ShaderId(ShaderBuilder, u32),
+    /// Copy a raster into a region of a texture.
+    RasterUpdate(u32, pix::Region, pix::Raster<pix::rgb::SRgba8>),
+    /// Resize a texture, keeping the pixels that still fit.
+    RasterResize(u32, u16, u16),
+    /// Create a shader, reporting how compiling went if it's being compiled
+    /// with `Shader::compile()`.
+    ShaderId(ShaderBuilder, u32, Option<Arc<Mutex<CompileInternal>>>),
     ShapeId(ShapeBuilder, u32, u32),
     GroupId(u32),
     GroupWrite(u32, u32, u32, Transform),
     GroupWriteTex(u32, u32, u32, Transform, ([f32; 2], [f32; 2])),
+    /// Remove a shape from a group, moving the ones after it down.
+    GroupRemove(u32, u32),
+    /// Swap two shapes in a group.
+    GroupSwap(u32, u32, u32),
+    /// Remove every shape from a group.
+    GroupClear(u32),
+    /// Replace every shape in a group with copies of one shape (group, shape,
+    /// shader of the shape, instances).
+    GroupInstances(u32, u32, u32, Vec<Instance>),
+    /// Free a texture (also used for render targets).
+    RasterDelete(u32),
+    /// Free a shader.
+    ShaderDelete(u32),
+    /// Free a shape.
+    ShapeDelete(u32),
+    /// Free a group.
+    GroupDelete(u32),
 }
 
 pub(super) struct FrameInternal {
@@ -176,20 +260,186 @@ pub(super) struct FrameInternal {
     pub(super) frame: Option<(std::time::Duration, f32, bool)>,
 }
 
+#[derive(Default)]
+pub(super) struct CaptureInternal {
+    raster: Option<pix::Raster<pix::rgb::SRgba8>>,
+    waker: Option<Waker>,
+}
+
+impl CaptureInternal {
+    // Hand the finished frame to the capture.
+    pub(super) fn finish(&mut self, raster: pix::Raster<pix::rgb::SRgba8>) {
+        self.raster = Some(raster);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
 type Location = Vec<(usize, usize)>;
 
+// A shape on the GPU, and its vertices as the software renderer lays them out
+// (for tinted copies, and starting recordings).
+struct ShapeInternal {
+    shape: window::Shape,
+    vertices: SoftShape,
+}
+
+impl ShapeInternal {
+    fn new(shader: &mut window::Shader, vertices: SoftShape) -> Rc<Self> {
+        let shape = window::ShapeBuilder::new(shader)
+            .vert(vertices.vertices())
+            .face(window::Transform::new())
+            .finish();
+        Rc::new(ShapeInternal { shape, vertices })
+    }
+}
+
+// A shape written into a group, with its transform and texture coordinates.
+type Write = (Rc<ShapeInternal>, Transform, ([f32; 2], [f32; 2]));
+
+// A group on the GPU, and the shapes written into it.
+struct GroupInternal {
+    group: window::Group,
+    // Where each shape ends in the group's buffers.
+    location: Location,
+    writes: Vec<Write>,
+    // Set when shapes are removed or moved.  The `window` crate can't shrink a
+    // group, so it's rebuilt from `writes` before it's drawn next.
+    dirty: bool,
+}
+
+impl GroupInternal {
+    fn new(group: window::Group) -> Self {
+        GroupInternal {
+            group,
+            location: Vec::new(),
+            writes: Vec::new(),
+            dirty: false,
+        }
+    }
+
+    // Write a shape at an index (which may be one past the end).
+    fn write(&mut self, id: u32, write: Write) {
+        let id = id as usize;
+        if id == self.writes.len() {
+            self.writes.push(write);
+        } else {
+            self.writes[id] = write;
+        }
+        if !self.dirty {
+            self.upload(id);
+        }
+    }
+
+    // Copy a shape into the group's buffers, after the shape before it.
+    fn upload(&mut self, id: usize) {
+        let location = if id == 0 {
+            (0, 0)
+        } else {
+            self.location[id - 1]
+        };
+        let (ref shape, transform, tex_coords) = self.writes[id];
+        let location = self.group.write_tex(
+            location,
+            &shape.shape,
+            &transform.into(),
+            tex_coords,
+        );
+        if id == self.location.len() {
+            self.location.push(location);
+        } else {
+            self.location[id] = location;
+        }
+    }
+
+    // Rebuild the group if shapes have been removed or moved.
+    fn rebuild(&mut self, window: &mut window::Window) {
+        if self.dirty {
+            self.group = window.group_new();
+            self.location.clear();
+            for id in 0..self.writes.len() {
+                self.upload(id);
+            }
+            self.dirty = false;
+        }
+    }
+}
+
+// Resources indexed by id.  Deleted resources leave an empty slot until their
+// id is reused.
+pub(super) struct Slots<T>(Vec<Option<T>>);
+
+impl<T> Slots<T> {
+    pub(super) fn new() -> Self {
+        Slots(Vec::new())
+    }
+
+    // Store a resource at a reused id, or the next new one.
+    pub(super) fn store(&mut self, id: u32, item: T) {
+        if id as usize == self.0.len() {
+            self.0.push(Some(item));
+        } else {
+            self.0[id as usize] = Some(item);
+        }
+    }
+
+    // Free a resource, returning it.
+    pub(super) fn delete(&mut self, id: u32) -> Option<T> {
+        self.0[id as usize].take()
+    }
+
+    // Leave empty slots for freed ids, up to `len` ids.
+    pub(super) fn reserve(&mut self, len: usize) {
+        if len > self.0.len() {
+            self.0.resize_with(len, || None);
+        }
+    }
+
+    // Iterate over resources that haven't been deleted.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &T> {
+        self.0.iter().flatten()
+    }
+}
+
+impl<T> std::ops::Index<u32> for Slots<T> {
+    type Output = T;
+
+    fn index(&self, id: u32) -> &T {
+        self.0[id as usize]
+            .as_ref()
+            .expect("Resource used after delete")
+    }
+}
+
+impl<T> std::ops::IndexMut<u32> for Slots<T> {
+    fn index_mut(&mut self, id: u32) -> &mut T {
+        self.0[id as usize]
+            .as_mut()
+            .expect("Resource used after delete")
+    }
+}
+
 pub(super) struct Internal {
     pub(super) cmds: Mutex<Vec<GpuCmd>>,
     pub(super) frame: Mutex<FrameInternal>,
     pub(super) pair: Arc<(Mutex<bool>, Condvar)>,
     raster_garbage: Mutex<Vec<u32>>,
-    rasters: RefCell<Vec<window::RasterId>>,
+    rasters: RefCell<Slots<gl::Texture>>,
     shader_garbage: Mutex<Vec<u32>>,
-    shaders: RefCell<Vec<window::Shader>>,
+    shaders: RefCell<Slots<gl::Program>>,
     shape_garbage: Mutex<Vec<u32>>,
-    shapes: RefCell<Vec<window::Shape>>,
+    shapes: RefCell<Slots<Rc<ShapeInternal>>>,
     group_garbage: Mutex<Vec<u32>>,
-    groups: RefCell<Vec<(window::Group, Location)>>,
+    groups: RefCell<Slots<GroupInternal>>,
+    software: RefCell<Software>,
+    state: RefCell<gl::State>,
+    recorders: RefCell<Vec<Recorder>>,
+    stats: Mutex<Stats>,
+    pacing: Mutex<Pacing>,
+    timer: RefCell<pacing::Timer>,
+    frame_stats: Mutex<FrameStats>,
+    post: RefCell<effect::Post>,
 }
 static mut INTERNAL: MaybeUninit<Internal> = MaybeUninit::uninit();
 static INIT: Once = Once::new();
@@ -197,6 +447,8 @@ static NEXT_RASTER_ID: AtomicU32 = AtomicU32::new(0);
 static NEXT_SHADER_ID: AtomicU32 = AtomicU32::new(0);
 static NEXT_SHAPE_ID: AtomicU32 = AtomicU32::new(0);
 static NEXT_GROUP_ID: AtomicU32 = AtomicU32::new(0);
+// Set by `quit()` to stop the graphics thread.
+static QUIT: AtomicBool = AtomicBool::new(false);
 
 impl Internal {
     // Get internal graphics data, lazily initializing if not used yet.
@@ -213,13 +465,21 @@ impl Internal {
                     }),
                     pair: Arc::new((Mutex::new(false), Condvar::new())),
                     raster_garbage: Mutex::new(Vec::new()),
-                    rasters: RefCell::new(Vec::new()),
+                    rasters: RefCell::new(Slots::new()),
                     shader_garbage: Mutex::new(Vec::new()),
-                    shaders: RefCell::new(Vec::new()),
+                    shaders: RefCell::new(Slots::new()),
                     shape_garbage: Mutex::new(Vec::new()),
-                    shapes: RefCell::new(Vec::new()),
+                    shapes: RefCell::new(Slots::new()),
                     group_garbage: Mutex::new(Vec::new()),
-                    groups: RefCell::new(Vec::new()),
+                    groups: RefCell::new(Slots::new()),
+                    software: RefCell::new(Software::new(0, 0)),
+                    state: RefCell::new(gl::State::new()),
+                    recorders: RefCell::new(Vec::new()),
+                    stats: Mutex::new(Stats::default()),
+                    pacing: Mutex::new(Pacing::default()),
+                    timer: RefCell::new(pacing::Timer::new()),
+                    frame_stats: Mutex::new(FrameStats::default()),
+                    post: RefCell::new(effect::Post::new()),
                 });
             });
             &*INTERNAL.as_ptr()
@@ -227,6 +487,11 @@ impl Internal {
     }
 }
 
+// Queue triangles to draw on the frame (for `Canvas::draw_triangles()`).
+pub(super) fn push_triangles(vertices: &[f32]) {
+    primitives::push(None, vertices);
+}
+
 /// `Raster` stored on the GPU.
 pub struct Texture(pub(super) u32);
 
@@ -248,22 +513,255 @@ impl Texture {
         lock.push(GpuCmd::RasterId(raster, id));
         Texture(id)
     }
+
+    /// Update a region of the `Texture` by copying a `Raster` to the GPU.
+    ///
+    /// The raster is copied to the top left corner of `region`, and clipped to
+    /// fit within both the region and the texture.  This is cheaper than
+    /// creating a new `Texture` every frame for video, lightmaps or glyphs.
+    pub fn update<P: pix::el::Pixel>(
+        &mut self,
+        region: pix::Region,
+        raster: &pix::Raster<P>,
+    ) where
+        pix::chan::Ch8: From<<P as pix::el::Pixel>::Chan>,
+    {
+        let raster = pix::Raster::<pix::rgb::SRgba8>::with_raster(raster);
+        let internal = Internal::new_lazy();
+        let mut lock = internal.cmds.lock().unwrap();
+        lock.push(GpuCmd::RasterUpdate(self.0, region, raster));
+    }
+
+    /// Change the size of the `Texture`.
+    ///
+    /// Pixels within both the old and new size are kept, and any new pixels
+    /// are transparent.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        let internal = Internal::new_lazy();
+        let mut lock = internal.cmds.lock().unwrap();
+        lock.push(GpuCmd::RasterResize(self.0, width, height));
+    }
 }
 
 impl Drop for Texture {
     fn drop(&mut self) {
-        // FIXME: Make GpuCmd
         let internal = Internal::new_lazy();
+        internal
+            .cmds
+            .lock()
+            .unwrap()
+            .push(GpuCmd::RasterDelete(self.0));
+        // Commands run in order, so the ID can be reused right away.
         internal.raster_garbage.lock().unwrap().push(self.0);
     }
 }
 
+/// A `Canvas` that renders into a `Texture`.
+///
+/// Unlike a frame, a render target keeps what's drawn on it until it's
+/// cleared.
+pub struct RenderTarget {
+    texture: Texture,
+    aspect: f32,
+    // When the target was last cleared.
+    cleared: Instant,
+    // Time between the last two clears.
+    elapsed: Duration,
+}
+
+impl RenderTarget {
+    /// Create a new `width` × `height` render target, cleared to transparent.
+    pub fn new(width: u16, height: u16) -> Self {
+        let internal = Internal::new_lazy();
+        let id = if let Some(id) = internal.raster_garbage.lock().unwrap().pop()
+        {
+            id
+        } else {
+            NEXT_RASTER_ID.fetch_add(1, Ordering::Relaxed)
+        };
+        let mut lock = internal.cmds.lock().unwrap();
+        lock.push(GpuCmd::TargetId(id, width, height));
+        RenderTarget {
+            texture: Texture(id),
+            aspect: f32::from(height) / f32::from(width),
+            cleared: Instant::now(),
+            elapsed: Duration::default(),
+        }
+    }
+
+    /// Clear the render target to a color, to start drawing a new image.
+    pub fn clear<P: pix::el::Pixel>(&mut self, color: P)
+    where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        use pix::chan::Channel;
+        use pix::el::Pixel;
+
+        let now = Instant::now();
+        self.elapsed = now - self.cleared;
+        self.cleared = now;
+        let color: pix::rgb::SRgba32 = color.convert();
+        self.push(GpuCmd::Clear(
+            color.one().to_f32(),
+            color.two().to_f32(),
+            color.three().to_f32(),
+            color.four().to_f32(),
+        ));
+    }
+
+    /// Get the texture that this render target renders into.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    fn push(&mut self, cmd: GpuCmd) {
+        let internal = Internal::new_lazy();
+        let mut cmds = internal.cmds.lock().unwrap();
+        cmds.push(GpuCmd::Target(self.texture.0, Box::new(cmd)));
+    }
+}
+
+impl Canvas for RenderTarget {
+    fn draw(&mut self, shader: &Shader, group: &Group) {
+        self.push(GpuCmd::Draw(shader.0, group.0));
+    }
+
+    fn set_camera(&mut self, camera: Transform) {
+        self.push(GpuCmd::SetCamera(camera));
+    }
+
+    fn set_viewport(&mut self, viewport: Option<Rect>) {
+        self.push(GpuCmd::SetViewport(viewport));
+    }
+
+    fn set_scissor(&mut self, scissor: Option<Rect>) {
+        self.push(GpuCmd::SetScissor(scissor));
+    }
+
+    fn set_blend(&mut self, blend: Blend) {
+        self.push(GpuCmd::SetBlend(blend));
+    }
+
+    fn set_depth_test(&mut self, test: bool) {
+        self.push(GpuCmd::SetDepthTest(test));
+    }
+
+    fn set_depth_write(&mut self, write: bool) {
+        self.push(GpuCmd::SetDepthWrite(write));
+    }
+
+    fn set_tint<P: pix::el::Pixel>(&mut self, shader: &Shader, tint: P)
+    where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        use pix::chan::Channel;
+        use pix::el::Pixel;
+
+        let color: pix::rgb::SRgba32 = tint.convert();
+        let red = color.one().to_f32();
+        let green = color.two().to_f32();
+        let blue = color.three().to_f32();
+        let alpha = color.four().to_f32();
+        self.push(GpuCmd::SetTint(shader.0, [red, green, blue, alpha]));
+    }
+
+    fn draw_graphic(
+        &mut self,
+        shader: &Shader,
+        group: &Group,
+        graphic: &Texture,
+    ) {
+        self.push(GpuCmd::DrawGraphic(shader.0, group.0, graphic.0));
+    }
+
+    fn draw_triangles(&mut self, vertices: &[f32]) {
+        primitives::push(Some(self.texture.0), vertices);
+    }
+
+    /// Returns the amount of time elapsed between the last two clears.
+    fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    fn height(&self) -> f32 {
+        self.aspect
+    }
+
+    /// Render targets never change size, so this always returns false.
+    fn resized(&self) -> bool {
+        false
+    }
+}
+
 /// A Shader.
-pub struct Shader(pub(super) u32);
+pub struct Shader(pub(super) u32, pub(super) VertexFormat);
+
+// How the vertices of a shader's shapes are laid out: position, then texture
+// coordinates, then color.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct VertexFormat {
+    // 2 or 3.
+    pub(super) dimensions: usize,
+    pub(super) graphic: bool,
+    // 0, 3, or 4 (with blending).
+    pub(super) components: usize,
+}
+
+impl VertexFormat {
+    // Get the layout for the features a shader is built with.
+    pub(super) fn new(
+        depth: bool,
+        graphic: bool,
+        gradient: bool,
+        blend: bool,
+    ) -> Self {
+        VertexFormat {
+            dimensions: if depth { 3 } else { 2 },
+            graphic,
+            components: match (gradient, blend) {
+                (false, _) => 0,
+                (true, false) => 3,
+                (true, true) => 4,
+            },
+        }
+    }
+
+    // Number of floats in a vertex.
+    pub(super) fn stride(&self) -> usize {
+        self.dimensions + self.components + if self.graphic { 2 } else { 0 }
+    }
+}
 
 impl Shader {
     /// Copy and send a shader program to the GPU.
+    ///
+    /// # Panics
+    /// The shader is compiled on the graphics thread, which panics if it
+    /// doesn't compile.  Use [`compile()`](#method.compile) for shaders that
+    /// might not (like ones loaded at runtime).
     pub fn new(builder: ShaderBuilder) -> Shader {
+        Self::create(builder, None)
+    }
+
+    /// Copy and send a shader program to the GPU, getting the compiler's
+    /// errors instead of panicking if it doesn't compile.
+    ///
+    /// The shader is compiled on the graphics thread once the current frame is
+    /// finished, so don't wait for it while holding a `Frame`.  The software
+    /// renderer doesn't run shader source, so there it always succeeds.
+    pub fn compile(builder: ShaderBuilder) -> ShaderCompile {
+        let internal = Arc::new(Mutex::new(CompileInternal::default()));
+        ShaderCompile {
+            shader: Some(Self::create(builder, Some(internal.clone()))),
+            internal,
+        }
+    }
+
+    fn create(
+        builder: ShaderBuilder,
+        compile: Option<Arc<Mutex<CompileInternal>>>,
+    ) -> Shader {
+        let format = builder.format();
         let internal = Internal::new_lazy();
         let id = if let Some(id) = internal.shader_garbage.lock().unwrap().pop()
         {
@@ -272,32 +770,52 @@ impl Shader {
             NEXT_SHADER_ID.fetch_add(1, Ordering::Relaxed)
         };
         let mut lock = internal.cmds.lock().unwrap();
-        lock.push(GpuCmd::ShaderId(builder, id));
-        Shader(id)
+        lock.push(GpuCmd::ShaderId(builder, id, compile));
+        Shader(id, format)
     }
 }
 
 impl Drop for Shader {
     fn drop(&mut self) {
-        // FIXME: Make GpuCmd
         let internal = Internal::new_lazy();
+        internal
+            .cmds
+            .lock()
+            .unwrap()
+            .push(GpuCmd::ShaderDelete(self.0));
+        // Commands run in order, so the ID can be reused right away.
         internal.shader_garbage.lock().unwrap().push(self.0);
     }
 }
 
 /// A Shape.
-pub struct Shape(u32);
+pub struct Shape(
+    u32,
+    /// Shader the shape was built for.
+    u32,
+    /// How the shader lays out the shape's vertices.
+    pub(super) VertexFormat,
+);
 
 impl Drop for Shape {
     fn drop(&mut self) {
-        // FIXME: Make GpuCmd
         let internal = Internal::new_lazy();
+        internal
+            .cmds
+            .lock()
+            .unwrap()
+            .push(GpuCmd::ShapeDelete(self.0));
+        // Commands run in order, so the ID can be reused right away.
         internal.shape_garbage.lock().unwrap().push(self.0);
     }
 }
 
 /// A Group.
-pub struct Group(pub(crate) u32);
+pub struct Group(
+    pub(crate) u32,
+    /// Number of shapes in the group.
+    u32,
+);
 
 impl Default for Group {
     fn default() -> Self {
@@ -317,17 +835,41 @@ impl Group {
         };
         let mut lock = internal.cmds.lock().unwrap();
         lock.push(GpuCmd::GroupId(id));
-        Group(id)
+        Group(id, 0)
+    }
+
+    /// Get the number of shapes in the group.
+    pub fn len(&self) -> u32 {
+        self.1
     }
 
-    /// Push a shape into the group.
+    /// Check if the group has no shapes.
+    pub fn is_empty(&self) -> bool {
+        self.1 == 0
+    }
+
+    // Account for a shape written at `id`.
+    fn written(&mut self, id: u32) {
+        assert!(id <= self.1, "Group write at {} past end {}", id, self.1);
+        if id == self.1 {
+            self.1 += 1;
+        }
+    }
+
+    /// Write a shape into the group at an index, replacing the shape there.
+    /// If `id` is [`len()`](#method.len), the shape is pushed onto the end.
+    ///
+    /// # Panics
+    /// If `id` is greater than `len()`.
     pub fn write(&mut self, id: u32, shape: &Shape, transform: &Transform) {
+        self.written(id);
         let internal = Internal::new_lazy();
         let mut lock = internal.cmds.lock().unwrap();
         lock.push(GpuCmd::GroupWrite(self.0, id, shape.0, *transform));
     }
 
-    /// Push a shape into the group.
+    /// Write a shape into the group with texture coordinates (offset and
+    /// scale), the same as [`write()`](#method.write).
     pub fn write_tex(
         &mut self,
         id: u32,
@@ -335,28 +877,214 @@ impl Group {
         transform: &Transform,
         tex_coords: ([f32; 2], [f32; 2]),
     ) {
+        self.written(id);
         let internal = Internal::new_lazy();
         let mut lock = internal.cmds.lock().unwrap();
         lock.push(GpuCmd::GroupWriteTex(
             self.0, id, shape.0, *transform, tex_coords,
         ));
     }
+
+    /// Remove the shape at an index, moving every shape after it down by one.
+    ///
+    /// To remove a shape without moving more than one other shape,
+    /// [`swap()`](#method.swap) it to the end first.
+    ///
+    /// # Panics
+    /// If `id` is out of bounds.
+    pub fn remove(&mut self, id: u32) {
+        assert!(id < self.1, "Group remove at {} out of bounds", id);
+        self.1 -= 1;
+        let internal = Internal::new_lazy();
+        let mut lock = internal.cmds.lock().unwrap();
+        lock.push(GpuCmd::GroupRemove(self.0, id));
+    }
+
+    /// Swap the shapes at two indices.
+    ///
+    /// # Panics
+    /// If either index is out of bounds.
+    pub fn swap(&mut self, a: u32, b: u32) {
+        assert!(a < self.1 && b < self.1, "Group swap out of bounds");
+        let internal = Internal::new_lazy();
+        let mut lock = internal.cmds.lock().unwrap();
+        lock.push(GpuCmd::GroupSwap(self.0, a, b));
+    }
+
+    /// Remove every shape from the group.
+    pub fn clear(&mut self) {
+        self.1 = 0;
+        let internal = Internal::new_lazy();
+        let mut lock = internal.cmds.lock().unwrap();
+        lock.push(GpuCmd::GroupClear(self.0));
+    }
 }
 
 impl Drop for Group {
     fn drop(&mut self) {
-        // FIXME: Make GpuCmd
         let internal = Internal::new_lazy();
+        internal
+            .cmds
+            .lock()
+            .unwrap()
+            .push(GpuCmd::GroupDelete(self.0));
+        // Commands run in order, so the ID can be reused right away.
         internal.group_garbage.lock().unwrap().push(self.0);
     }
 }
 
+/// One copy of a shape drawn by [`Instances`](struct.Instances.html).
+#[derive(Copy, Clone)]
+pub struct Instance {
+    /// Where the shape is drawn.
+    pub transform: Transform,
+    /// Texture coordinates (offset and scale), the same as
+    /// [`Group::write_tex()`](struct.Group.html#method.write_tex).
+    pub tex_coords: ([f32; 2], [f32; 2]),
+    /// RGBA color that the shape's vertex colors are multiplied by.  Only
+    /// shaders built with `gradient` have vertex colors, so this does nothing
+    /// for other shaders.
+    pub tint: [f32; 4],
+}
+
+impl Instance {
+    /// Create an instance drawn with `transform`, using the shape's texture
+    /// coordinates and colors as they are.
+    pub fn new(transform: Transform) -> Self {
+        Instance {
+            transform,
+            tex_coords: ([0.0, 0.0], [1.0, 1.0]),
+            tint: [1.0; 4],
+        }
+    }
+
+    /// Set the texture coordinates (offset and scale).
+    pub fn tex_coords(mut self, tex_coords: ([f32; 2], [f32; 2])) -> Self {
+        self.tex_coords = tex_coords;
+        self
+    }
+
+    /// Set the color that the shape's vertex colors are multiplied by.
+    pub fn tint<P: pix::el::Pixel>(mut self, tint: P) -> Self
+    where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        use pix::chan::Channel;
+        use pix::el::Pixel;
+
+        let color: pix::rgb::SRgba32 = tint.convert();
+        self.tint = [
+            color.one().to_f32(),
+            color.two().to_f32(),
+            color.three().to_f32(),
+            color.four().to_f32(),
+        ];
+        self
+    }
+}
+
+/// Many copies of one shape, drawn in a single call with
+/// [`Canvas::draw_instances()`](trait.Canvas.html#method.draw_instances).
+///
+/// Writing copies into a `Group` one at a time sends a command for each of
+/// them.  Instead, all of the copies are replaced at once with
+/// [`set()`](#method.set), which sends one command.
+///
+/// This isn't hardware instancing: `window` draws with an OpenGL ES 2.0
+/// context, which has no per-instance vertex attributes (divisors), and lays
+/// out vertex buffers itself.  The graphics thread instead transforms the
+/// vertices of every copy on the CPU and uploads them into a new buffer each
+/// time the copies are set, so the cost grows with the number of copies times
+/// the number of vertices in the shape.  Small shapes (like the quads of
+/// sprites and particles) are fine, but a large mesh copied many times is
+/// better drawn as a `Group` that's written once and moved with the camera.
+pub struct Instances {
+    group: Group,
+    shape: Shape,
+}
+
+impl Instances {
+    /// Create a new set of instances of `shape`, with no copies yet.
+    pub fn new(shape: Shape) -> Self {
+        Instances {
+            group: Group::new(),
+            shape,
+        }
+    }
+
+    /// Get the shape that is copied.
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    /// Get the number of copies.
+    pub fn len(&self) -> u32 {
+        self.group.len()
+    }
+
+    /// Check if there are no copies.
+    pub fn is_empty(&self) -> bool {
+        self.group.is_empty()
+    }
+
+    /// Replace every copy of the shape.
+    ///
+    /// Each different tint (other than white) makes a tinted copy of the
+    /// shape's vertices on the graphics thread every time this is called, so
+    /// keep the number of different tints small.
+    pub fn set(&mut self, instances: &[Instance]) {
+        self.group.1 = instances.len() as u32;
+        let internal = Internal::new_lazy();
+        let mut lock = internal.cmds.lock().unwrap();
+        lock.push(GpuCmd::GroupInstances(
+            self.group.0,
+            self.shape.0,
+            self.shape.1,
+            instances.to_vec(),
+        ));
+    }
+}
+
+/// A captured frame.
+///
+/// This is a future that resolves to the `Raster` of the frame after it has
+/// finished rendering.
+pub struct Capture(pub(super) Arc<Mutex<CaptureInternal>>);
+
+impl Future for Capture {
+    type Output = pix::Raster<pix::rgb::SRgba8>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut lock = self.0.lock().unwrap();
+        if let Some(raster) = lock.raster.take() {
+            Poll::Ready(raster)
+        } else {
+            lock.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
 static ASPECT: AtomicU32 = AtomicU32::new(0);
 
-// A function that is run on the graphics thread whenever
-fn async_runner(window: &mut window::Window, elapsed: std::time::Duration) {
-    // Get the aspect ratio
-    let aspect = window.aspect();
+// After `quit()`, frames are handed out straight away (with the last aspect
+// ratio), so that the async thread isn't left waiting for one.
+pub(super) fn quit_frame() -> Option<(Duration, f32, bool)> {
+    if QUIT.load(Ordering::SeqCst) {
+        let aspect =
+            f32::from_ne_bytes(ASPECT.load(Ordering::Relaxed).to_ne_bytes());
+        Some((Duration::default(), aspect, false))
+    } else {
+        None
+    }
+}
+
+// Hand the next frame to the async thread, and wait for it to finish writing
+// to the command buffer.  Returns the locked command buffer.
+fn sync_frame(
+    aspect: f32,
+    elapsed: Duration,
+) -> MutexGuard<'static, Vec<GpuCmd>> {
     // Check if the window has been resized.
     let new_aspect = u32::from_ne_bytes(aspect.to_ne_bytes());
     let old_aspect = ASPECT.swap(new_aspect, Ordering::Relaxed);
@@ -374,165 +1102,675 @@ fn async_runner(window: &mut window::Window, elapsed: std::time::Duration) {
     {
         let internal = Internal::new_lazy();
         let mut lock = internal.frame.lock().unwrap();
-        lock.waker.take().unwrap().wake();
         lock.frame = Some((elapsed, aspect, resized));
+        if let Some(waker) = lock.waker.take() {
+            waker.wake();
+        }
     }
 
-    // Wait for async thread to finish writing to the command buffer.
+    // Wait for async thread to finish writing to the command buffer (or to
+    // quit, when it won't).
     let mut started = lock.lock().unwrap();
-    while !*started {
+    while !*started && !QUIT.load(Ordering::SeqCst) {
         started = cvar.wait(started).unwrap();
     }
 
+    Internal::new_lazy().cmds.lock().unwrap()
+}
+
+// A function that is run on the graphics thread whenever
+fn async_runner(window: &mut window::Window, refresh: Duration) {
+    let pacing = *Internal::new_lazy().pacing.lock().unwrap();
+    let mut timer = Internal::new_lazy().timer.borrow_mut();
+    let elapsed = timer.start(&pacing);
+    Internal::new_lazy()
+        .state
+        .borrow_mut()
+        .set_vsync(pacing.vsync);
+    let aspect = window.aspect();
+    let mut lock = sync_frame(aspect, elapsed);
+    timer.record(&pacing, elapsed, refresh, &lock);
+
+    // Recordings start from the resources and state before the frame.
+    let mut recorders = Internal::new_lazy().recorders.borrow_mut();
+    if lock.iter().any(|cmd| matches!(cmd, GpuCmd::Record(..))) {
+        replay::start(&mut recorders, &lock, &snapshot(window));
+    }
+    replay::record(&mut recorders, aspect, &lock);
+
+    let captures: Vec<_> = lock
+        .iter()
+        .filter_map(|cmd| match cmd {
+            GpuCmd::Capture(capture) => Some(capture.clone()),
+            _ => None,
+        })
+        .collect();
+
     // Process commands in the command buffer.
-    let mut lock = Internal::new_lazy().cmds.lock().unwrap();
     for cmd in lock.drain(..) {
-        use GpuCmd::*;
-        match cmd {
-            Background(r, g, b) => window.background(r, g, b),
-            Draw(shader, group) => {
-                let shaders = Internal::new_lazy().shaders.borrow();
-                let groups = Internal::new_lazy().groups.borrow();
-                window
-                    .draw(&shaders[shader as usize], &groups[group as usize].0);
-            }
-            DrawGraphic(shader, group, raster) => {
-                let shaders = Internal::new_lazy().shaders.borrow();
-                let groups = Internal::new_lazy().groups.borrow();
-                window.draw_graphic(
-                    &shaders[shader as usize],
-                    &groups[group as usize].0,
-                    &Internal::new_lazy().rasters.borrow()[raster as usize],
-                );
-            }
-            SetCamera(camera) => {
-                window.camera(camera);
+        execute(window, cmd, None);
+    }
+    let mut state = Internal::new_lazy().state.borrow_mut();
+    state.reset(window);
+    Internal::new_lazy().post.borrow_mut().run(
+        window,
+        &mut state,
+        &mut Internal::new_lazy().shaders.borrow_mut(),
+        &Internal::new_lazy().rasters.borrow(),
+    );
+    if !captures.is_empty() {
+        let (width, height, pixels) = state.window_pixels();
+        let raster = frame_raster(width, height, pixels);
+        for capture in captures {
+            capture.lock().unwrap().finish(raster.clone());
+        }
+    }
+    state.collect();
+    if pacing.low_latency {
+        gl::finish();
+    }
+    *Internal::new_lazy().stats.lock().unwrap() = gpu_stats();
+    *Internal::new_lazy().frame_stats.lock().unwrap() = timer.stats();
+}
+
+// Execute a command on the GPU, while drawing on the window or a render
+// target.
+fn execute(window: &mut window::Window, cmd: GpuCmd, target: Option<u32>) {
+    use GpuCmd::*;
+    match cmd {
+        Background(r, g, b) => {
+            let mut state = Internal::new_lazy().state.borrow_mut();
+            state.set_background(window, [r, g, b]);
+        }
+        Draw(shader, group) => {
+            let shaders = Internal::new_lazy().shaders.borrow();
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            groups[group].rebuild(window);
+            window.draw(&shaders[shader].shader, &groups[group].group);
+        }
+        DrawGraphic(shader, group, raster) => {
+            // A render target can't be drawn onto itself.
+            if target == Some(raster) {
+                return;
             }
-            SetTint(shader, tint) => {
-                let shaders = Internal::new_lazy().shaders.borrow();
-                window.tint(&shaders[shader as usize], tint);
+            let shaders = Internal::new_lazy().shaders.borrow();
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            groups[group].rebuild(window);
+            window.draw_graphic(
+                &shaders[shader].shader,
+                &groups[group].group,
+                &Internal::new_lazy().rasters.borrow()[raster].raster,
+            );
+        }
+        Triangles(shader, vertices) => {
+            let mut shaders = Internal::new_lazy().shaders.borrow_mut();
+            let shader = &mut shaders[shader].shader;
+            let shape = window::ShapeBuilder::new(shader)
+                .vert(&vertices)
+                .face(window::Transform::new())
+                .finish();
+            let mut group = window.group_new();
+            group.write((0, 0), &shape, &window::Transform::new());
+            window.draw(shader, &group);
+        }
+        SetCamera(camera) => {
+            let mut state = Internal::new_lazy().state.borrow_mut();
+            state.set_camera(window, camera);
+        }
+        SetViewport(viewport) => {
+            let mut state = Internal::new_lazy().state.borrow_mut();
+            state.set_viewport(window, viewport);
+        }
+        SetScissor(scissor) => {
+            Internal::new_lazy().state.borrow_mut().set_scissor(scissor);
+        }
+        SetBlend(blend) => {
+            Internal::new_lazy().state.borrow_mut().set_blend(blend);
+        }
+        SetDepthTest(test) => {
+            Internal::new_lazy().state.borrow_mut().set_depth_test(test);
+        }
+        SetDepthWrite(write) => {
+            Internal::new_lazy()
+                .state
+                .borrow_mut()
+                .set_depth_write(write);
+        }
+        SetTint(shader, tint) => {
+            let mut shaders = Internal::new_lazy().shaders.borrow_mut();
+            shaders[shader].tint = tint;
+            window.tint(&shaders[shader].shader, tint);
+        }
+        // Handled by `async_runner()`.
+        Capture(_) | Record(..) => {}
+        SetEffects(effects) => {
+            Internal::new_lazy().post.borrow_mut().set_effects(effects);
+        }
+        Clear(r, g, b, a) => {
+            let mut state = Internal::new_lazy().state.borrow_mut();
+            state.clear(window, [r, g, b, a]);
+        }
+        TargetId(id, width, height) => {
+            let (width, height) = (u32::from(width), u32::from(height));
+            let pixels = vec![0; width as usize * height as usize * 4];
+            let texture = gl::texture_new(window, &pixels, width, height);
+            let mut state = Internal::new_lazy().state.borrow_mut();
+            state.target_new(id, &texture);
+            Internal::new_lazy().rasters.borrow_mut().store(id, texture);
+        }
+        Target(id, cmd) => {
+            let mut state = Internal::new_lazy().state.borrow_mut();
+            if state.begin_target(window, id) {
+                drop(state);
+                execute(window, *cmd, Some(id));
+                let mut state = Internal::new_lazy().state.borrow_mut();
+                state.end_target(window);
             }
-            RasterId(raster, id) => {
-                let gpu_raster = window.graphic(
-                    raster.as_u8_slice(),
-                    raster.width() as usize,
-                    raster.height() as usize,
-                );
-                let mut rasters = Internal::new_lazy().rasters.borrow_mut();
-                if id as usize == rasters.len() {
-                    rasters.push(gpu_raster);
-                } else {
-                    rasters[id as usize] = gpu_raster;
+        }
+        RasterUpdate(id, region, raster) => {
+            let mut rasters = Internal::new_lazy().rasters.borrow_mut();
+            let texture = &mut rasters[id];
+            let (width, height) = (texture.width, texture.height);
+            gl::texture_update(window, texture, &mut |pixels| {
+                if pixels.is_empty() {
+                    return;
                 }
-            }
-            ShaderId(shader, id) => {
-                let shader = window.shader_new(shader);
+                let mut texture =
+                    pix::Raster::<pix::rgb::SRgba8>::with_u8_buffer(
+                        width,
+                        height,
+                        pixels.to_vec(),
+                    );
+                texture.copy_raster(region, &raster, ());
+                pixels.copy_from_slice(texture.as_u8_slice());
+            });
+        }
+        RasterResize(id, width, height) => {
+            let (width, height) = (u32::from(width), u32::from(height));
+            let mut rasters = Internal::new_lazy().rasters.borrow_mut();
+            let old = texture_raster(window, &mut rasters[id]);
+            let mut raster =
+                pix::Raster::<pix::rgb::SRgba8>::with_clear(width, height);
+            raster.copy_raster((0, 0, width, height), &old, ());
+            // The window has no way to resize a graphic, so replace it.
+            let texture =
+                gl::texture_new(window, raster.as_u8_slice(), width, height);
+            let old = std::mem::replace(&mut rasters[id], texture);
+            Internal::new_lazy().state.borrow_mut().delete_texture(old);
+        }
+        RasterId(raster, id) => {
+            let texture = gl::texture_new(
+                window,
+                raster.as_u8_slice(),
+                raster.width(),
+                raster.height(),
+            );
+            Internal::new_lazy().rasters.borrow_mut().store(id, texture);
+        }
+        ShaderId(builder, id, compile) => {
+            // Check that it compiles first, as `window` panics if not.
+            let result = gl::check(&builder);
+            if result.is_ok() {
+                let shader = gl::program_new(window, builder);
                 let mut shaders = Internal::new_lazy().shaders.borrow_mut();
-                if id as usize == shaders.len() {
-                    shaders.push(shader);
-                } else {
-                    shaders[id as usize] = shader;
-                }
+                shaders.store(id, shader);
             }
-            ShapeId(shape_builder, id, shader) => {
-                let mut shapes = Internal::new_lazy().shapes.borrow_mut();
-                let mut shaders = Internal::new_lazy().shaders.borrow_mut();
-                let mut shape =
-                    window::ShapeBuilder::new(&mut shaders[shader as usize]);
-                for face in shape_builder.faces {
-                    if let Some(vertices) = face.vertices {
-                        shape = shape.vert(vertices.as_slice());
-                    }
-                    if let Some(transform) = face.transform {
-                        shape = shape.face(transform);
-                    }
-                }
-                if id as usize == shapes.len() {
-                    shapes.push(shape.finish());
-                } else {
-                    shapes[id as usize] = shape.finish();
+            match (compile, result) {
+                (Some(compile), result) => {
+                    compile.lock().unwrap().finish(result)
                 }
+                (None, Err(error)) => panic!("{}", error),
+                (None, Ok(())) => {}
             }
-            GroupId(id) => {
-                let mut groups = Internal::new_lazy().groups.borrow_mut();
-                if id as usize == groups.len() {
-                    groups.push((window.group_new(), Vec::new()));
+        }
+        ShapeId(builder, id, shader) => {
+            let mut shaders = Internal::new_lazy().shaders.borrow_mut();
+            let program = &mut shaders[shader];
+            let vertices = SoftShape::new(&builder, program.builder.format());
+            let shape = ShapeInternal::new(&mut program.shader, vertices);
+            Internal::new_lazy().shapes.borrow_mut().store(id, shape);
+        }
+        GroupId(id) => {
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            groups.store(id, GroupInternal::new(window.group_new()));
+        }
+        GroupWrite(group, id, shape, transform) => {
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            let shape = Internal::new_lazy().shapes.borrow()[shape].clone();
+            let tex_coords = ([0.0, 0.0], [1.0, 1.0]);
+            groups[group].write(id, (shape, transform, tex_coords));
+        }
+        GroupWriteTex(group, id, shape, transform, tex_coords) => {
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            let shape = Internal::new_lazy().shapes.borrow()[shape].clone();
+            groups[group].write(id, (shape, transform, tex_coords));
+        }
+        GroupRemove(group, id) => {
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            groups[group].writes.remove(id as usize);
+            groups[group].dirty = true;
+        }
+        GroupSwap(group, a, b) => {
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            groups[group].writes.swap(a as usize, b as usize);
+            groups[group].dirty = true;
+        }
+        GroupClear(group) => {
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            groups[group].writes.clear();
+            groups[group].dirty = true;
+        }
+        GroupInstances(group, shape, shader, instances) => {
+            let mut groups = Internal::new_lazy().groups.borrow_mut();
+            let shapes = Internal::new_lazy().shapes.borrow();
+            let mut shaders = Internal::new_lazy().shaders.borrow_mut();
+            // Tinted copies of the shape, shared between instances with the
+            // same tint.
+            let mut tinted = HashMap::new();
+            let mut writes = Vec::with_capacity(instances.len());
+            for instance in instances {
+                let shape = if instance.tint == [1.0; 4] {
+                    shapes[shape].clone()
                 } else {
-                    groups[id as usize] = (window.group_new(), Vec::new());
-                }
+                    let key = instance.tint.map(f32::to_bits);
+                    tinted
+                        .entry(key)
+                        .or_insert_with(|| {
+                            match shapes[shape].vertices.tinted(instance.tint) {
+                                Some(vertices) => ShapeInternal::new(
+                                    &mut shaders[shader].shader,
+                                    vertices,
+                                ),
+                                None => shapes[shape].clone(),
+                            }
+                        })
+                        .clone()
+                };
+                writes.push((shape, instance.transform, instance.tex_coords));
             }
-            GroupWrite(group, id, shape, transform) => {
-                let mut groups = Internal::new_lazy().groups.borrow_mut();
-                let shapes = Internal::new_lazy().shapes.borrow();
-                if id >= groups[group as usize].1.len() as u32 {
-                    let location = if id == 0 {
-                        (0, 0)
-                    } else {
-                        groups[group as usize].1[id as usize - 1]
-                    };
-                    let location = groups[group as usize].0.write(
-                        location,
-                        &shapes[shape as usize],
-                        &transform,
-                    );
-                    groups[group as usize].1.push(location);
-                } else {
-                    let location = if id == 0 {
-                        (0, 0)
-                    } else {
-                        groups[group as usize].1[id as usize - 1]
-                    };
-                    groups[group as usize].1[id as usize] = groups
-                        [group as usize]
-                        .0
-                        .write(location, &shapes[shape as usize], &transform);
-                }
+            groups[group].writes = writes;
+            groups[group].dirty = true;
+        }
+        RasterDelete(id) => {
+            let texture = Internal::new_lazy().rasters.borrow_mut().delete(id);
+            let mut state = Internal::new_lazy().state.borrow_mut();
+            state.target_delete(id);
+            if let Some(texture) = texture {
+                state.delete_texture(texture);
             }
-            GroupWriteTex(group, id, shape, transform, texcoords) => {
-                let mut groups = Internal::new_lazy().groups.borrow_mut();
-                let shapes = Internal::new_lazy().shapes.borrow();
-                if id >= groups[group as usize].1.len() as u32 {
-                    let location = if id == 0 {
-                        (0, 0)
-                    } else {
-                        groups[group as usize].1[id as usize - 1]
-                    };
-                    let location = groups[group as usize].0.write_tex(
-                        location,
-                        &shapes[shape as usize],
-                        &transform,
-                        texcoords,
-                    );
-                    groups[group as usize].1.push(location);
-                } else {
-                    let location = if id == 0 {
-                        (0, 0)
-                    } else {
-                        groups[group as usize].1[id as usize - 1]
-                    };
-                    groups[group as usize].1[id as usize] =
-                        groups[group as usize].0.write_tex(
-                            location,
-                            &shapes[shape as usize],
-                            &transform,
-                            texcoords,
-                        );
-                }
+            Internal::new_lazy().post.borrow_mut().raster_delete(id);
+        }
+        ShaderDelete(id) => {
+            let program = Internal::new_lazy().shaders.borrow_mut().delete(id);
+            if let Some(program) = program {
+                let mut state = Internal::new_lazy().state.borrow_mut();
+                state.delete_program(program);
             }
+            Internal::new_lazy().post.borrow_mut().shader_delete(id);
+        }
+        ShapeDelete(id) => {
+            Internal::new_lazy().shapes.borrow_mut().delete(id);
+        }
+        GroupDelete(id) => {
+            Internal::new_lazy().groups.borrow_mut().delete(id);
+        }
+    }
+}
+
+// Copy the pixels of a texture back from `window`, which keeps a copy of them.
+fn texture_raster(
+    window: &mut window::Window,
+    texture: &mut gl::Texture,
+) -> pix::Raster<pix::rgb::SRgba8> {
+    let (width, height) = (texture.width, texture.height);
+    let mut pixels = Vec::new();
+    gl::texture_update(window, texture, &mut |buffer| pixels = buffer.to_vec());
+    if pixels.is_empty() {
+        pix::Raster::with_clear(width, height)
+    } else {
+        pix::Raster::with_u8_buffer(width, height, pixels)
+    }
+}
+
+// Make a raster of a frame read back from the GPU, which is displayed opaque.
+fn frame_raster(
+    width: u32,
+    height: u32,
+    mut pixels: Vec<u8>,
+) -> pix::Raster<pix::rgb::SRgba8> {
+    if pixels.is_empty() {
+        return pix::Raster::with_clear(width, height);
+    }
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel[3] = 255;
+    }
+    pix::Raster::with_u8_buffer(width, height, pixels)
+}
+
+// Build a software renderer with the same resources and state as the GPU, to
+// start recordings from.  Depth buffers can't be read back from the GPU, so
+// render targets start with theirs cleared.
+fn snapshot(window: &mut window::Window) -> Software {
+    let internal = Internal::new_lazy();
+    let state = internal.state.borrow();
+    let mut rasters = internal.rasters.borrow_mut();
+    let shaders = internal.shaders.borrow();
+    let shapes = internal.shapes.borrow();
+    let groups = internal.groups.borrow();
+    let mut software = Software::new(0, 0);
+    software.reserve([
+        rasters.0.len(),
+        shaders.0.len(),
+        shapes.0.len(),
+        groups.0.len(),
+    ]);
+    for (id, texture) in rasters.0.iter_mut().enumerate() {
+        let (id, texture) = match texture {
+            Some(texture) => (id as u32, texture),
+            None => continue,
+        };
+        if let Some((width, height, pixels)) = state.target_pixels(id) {
+            let raster = if pixels.is_empty() {
+                pix::Raster::with_clear(width, height)
+            } else {
+                pix::Raster::with_u8_buffer(width, height, pixels)
+            };
+            let region = pix::Region::new(0, 0, width, height);
+            software.execute(&GpuCmd::TargetId(
+                id,
+                width as u16,
+                height as u16,
+            ));
+            software.execute(&GpuCmd::RasterUpdate(id, region, raster));
+        } else {
+            let raster = texture_raster(window, texture);
+            software.execute(&GpuCmd::RasterId(raster, id));
         }
     }
+    for (id, program) in shaders.0.iter().enumerate() {
+        if let Some(program) = program {
+            let id = id as u32;
+            let builder = program.builder.clone();
+            software.execute(&GpuCmd::ShaderId(builder, id, None));
+            software.execute(&GpuCmd::SetTint(id, program.tint));
+        }
+    }
+    for (id, shape) in shapes.0.iter().enumerate() {
+        if let Some(shape) = shape {
+            software.store_shape(id as u32, shape.vertices.clone());
+        }
+    }
+    for (id, group) in groups.0.iter().enumerate() {
+        if let Some(group) = group {
+            let writes = group.writes.iter().map(|(shape, transform, tex)| {
+                (&shape.vertices, transform, *tex)
+            });
+            software.store_group(id as u32, writes);
+        }
+    }
+    for cmd in state.commands() {
+        software.execute(&cmd);
+    }
+    let effects = internal.post.borrow().effects().to_vec();
+    software.execute(&GpuCmd::SetEffects(effects));
+    software
+}
+
+// Count the resources on the GPU, and how much memory they use, the same way
+// as the software renderer does.
+fn gpu_stats() -> Stats {
+    let internal = Internal::new_lazy();
+    Stats {
+        textures: Usage::of(internal.rasters.borrow().iter(), |texture| {
+            texture.width as usize * texture.height as usize * 4
+        }),
+        shaders: Usage::of(internal.shaders.borrow().iter(), |_| 0),
+        shapes: Usage::of(internal.shapes.borrow().iter(), |shape| {
+            shape.vertices.bytes()
+        }),
+        groups: Usage::of(internal.groups.borrow().iter(), |group| {
+            group
+                .writes
+                .iter()
+                .map(|write| write.0.vertices.bytes())
+                .sum()
+        }),
+    }
 }
 
-/// Run the infinite event loop.  You should only call this on the main thread.
+/// Run the event loop until [`quit()`](fn.quit.html) is called, and then exit
+/// the process.  You should only call this on the main thread.
 pub fn draw_thread() {
-    let fallback_window_title = env!("CARGO_PKG_NAME");
-    let mut window = window::Window::new(fallback_window_title, async_runner);
-    loop {
-        window.run();
+    draw_thread_with(WindowConfig::new());
+}
+
+/// Run the event loop until [`quit()`](fn.quit.html) is called (or the
+/// connection to the display is lost), opening a window with `config`, and
+/// then exit the process.  You should only call this on the main thread, in
+/// place of [`draw_thread()`](fn.draw_thread.html).
+///
+/// This doesn't return, because the `window` crate exits the process when its
+/// window is closed.  Save anything that needs saving before calling
+/// `quit()`.
+pub fn draw_thread_with(config: WindowConfig) {
+    let mut window = window::Window::new(config.get_title(), async_runner);
+    while !QUIT.load(Ordering::SeqCst) {
+        if !window.run() {
+            quit();
+        }
+    }
+    // Closes the window and exits.
+    drop(window);
+}
+
+/// Run the event loop without a window until [`quit()`](fn.quit.html) is
+/// called, rendering in software.
+///
+/// Frames are `width` × `height` pixels, and drawn as fast as they are
+/// requested.  This is useful for running graphics code on machines without a
+/// GPU or display (like CI servers).  You should only call this on the main
+/// thread, in place of [`draw_thread()`](fn.draw_thread.html).
+pub fn draw_thread_software(width: u16, height: u16) {
+    let mut software = Internal::new_lazy().software.borrow_mut();
+    let mut timer = Internal::new_lazy().timer.borrow_mut();
+    software.resize(width, height);
+    while !QUIT.load(Ordering::SeqCst) {
+        let pacing = *Internal::new_lazy().pacing.lock().unwrap();
+        let elapsed = timer.start(&pacing);
+        let aspect = software.aspect();
+        let mut lock = sync_frame(aspect, elapsed);
+        timer.record(&pacing, elapsed, Duration::default(), &lock);
+        software.begin(true);
+        software.record(aspect, &lock);
+        for cmd in lock.drain(..) {
+            software.execute(&cmd);
+            if let GpuCmd::ShaderId(_, _, Some(compile)) = cmd {
+                compile.lock().unwrap().finish(Ok(()))
+            }
+        }
+        software.post_process();
+        software.finish();
+        *Internal::new_lazy().stats.lock().unwrap() = software.stats();
+        *Internal::new_lazy().frame_stats.lock().unwrap() = timer.stats();
     }
 }
 
-pub use window::{shader, ShaderBuilder, Transform};
+/// How many of one kind of graphics resource are alive, and their size.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Number of live resources.
+    pub count: usize,
+    /// Bytes of pixel or vertex data held by the live resources.
+    pub bytes: usize,
+}
+
+impl Usage {
+    // Count resources, adding up their sizes.
+    pub(super) fn of<'a, T: 'a>(
+        items: impl Iterator<Item = &'a T>,
+        bytes: impl Fn(&T) -> usize,
+    ) -> Self {
+        items.fold(Usage::default(), |usage, item| Usage {
+            count: usage.count + 1,
+            bytes: usage.bytes + bytes(item),
+        })
+    }
+}
+
+/// Graphics resources that are alive, returned from [`stats()`](fn.stats.html).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// `Texture`s, including those of `RenderTarget`s.
+    pub textures: Usage,
+    /// `Shader`s.  Compiled shaders have no known size, so `bytes` is zero.
+    pub shaders: Usage,
+    /// `Shape`s.
+    pub shapes: Usage,
+    /// `Group`s.
+    pub groups: Usage,
+}
+
+/// Get the number and size of graphics resources that are alive.
+///
+/// Resources are freed on the graphics thread after their handles are dropped,
+/// so this is updated once per frame.  A count that keeps growing over a
+/// long-running session points to a leak.
+pub fn stats() -> Stats {
+    *Internal::new_lazy().stats.lock().unwrap()
+}
+
+/// Set how frames are paced.  This takes effect from the next frame.
+pub fn set_pacing(pacing: Pacing) {
+    *Internal::new_lazy().pacing.lock().unwrap() = pacing;
+}
+
+/// Get how frames are paced.
+pub fn pacing() -> Pacing {
+    *Internal::new_lazy().pacing.lock().unwrap()
+}
+
+/// Stop the graphics thread once the frame it's on is finished.
+/// [`draw_thread_software()`](fn.draw_thread_software.html) returns, and
+/// [`draw_thread()`](fn.draw_thread.html) closes the window and exits the
+/// process.
+///
+/// After this, [`Frame::new()`](../window/struct.Frame.html#method.new)
+/// resolves straight away, and nothing drawn on the frame is rendered.
+pub fn quit() {
+    QUIT.store(true, Ordering::SeqCst);
+    // Wake the graphics thread if it's waiting for a frame.
+    {
+        let (lock, cvar) = &*Internal::new_lazy().pair;
+        let _lock = lock.lock().unwrap();
+        cvar.notify_all();
+    }
+    // Wake the async thread if it's waiting for one.
+    if let Some(waker) = Internal::new_lazy().frame.lock().unwrap().waker.take()
+    {
+        waker.wake();
+    }
+}
+
+/// Get timing statistics of the last 120 frames.
+///
+/// Like [`stats()`](fn.stats.html), this is updated once per frame, so it can
+/// be checked (or logged) every few seconds to find hitches in the field.
+pub fn frame_stats() -> FrameStats {
+    *Internal::new_lazy().frame_stats.lock().unwrap()
+}
+
+/// Set the full-screen effects that are run over each finished frame, in
+/// order, before it's shown (replacing any set before).  This takes effect
+/// from the current frame, and lasts until it's called again; call it every
+/// frame to animate the effects' parameters.  An effect is removed once its
+/// shader or lookup table is dropped.
+///
+/// The software renderer (also used for captures and replays) can't run
+/// shader source, so it draws each effect as the frame multiplied by the
+/// effect's tint (if its shader has one).
+pub fn set_effects(effects: &[Effect<'_>]) {
+    let effects = effects.iter().map(Effect::internal).collect();
+    let internal = Internal::new_lazy();
+    let mut cmds = internal.cmds.lock().unwrap();
+    cmds.push(GpuCmd::SetEffects(effects));
+}
+
+pub use crate::shader;
+pub use animation::{AnimationError, Clip, Playback, SpriteSheet, Timeline};
+pub use atlas::{Atlas, AtlasBuilder, AtlasError};
+pub use config::WindowConfig;
+pub use effect::Effect;
+pub use fonterator::{normal_font, Font, TextAlign};
+pub use footile::{Path2D, PathOp};
+pub use glsl::{ShaderBuilder, ShaderCompile, ShaderError, ShaderStage};
+pub use mesh::{Mesh, MeshError};
+pub use pacing::{FrameStats, Pacing};
+pub use particles::{Emitter, Particles};
+pub use replay::{Recording, Replay};
+pub use text::Text;
+pub use tilemap::{Tilemap, TilemapError};
+pub use transform::{Ray, Transform};
+pub use vector::{VectorError, VectorGraphic};
+// Type of the shaders generated by `res`, for the `shader!()` macro.
+#[doc(hidden)]
+pub use window::ShaderBuilder as ResShaderBuilder;
+
+/// How colors drawn on a [`Canvas`](trait.Canvas.html) are combined with the
+/// colors already there.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Blend {
+    /// Draw over with transparency (the default).
+    #[default]
+    Alpha,
+    /// Draw over with transparency, for colors that are already multiplied
+    /// by their alpha.
+    Premultiplied,
+    /// Add to the colors already there, scaled by alpha (for light and fire).
+    Additive,
+    /// Multiply the colors already there, ignoring alpha (for shadows and
+    /// lighting overlays).
+    Multiply,
+    /// Replace the colors already there, ignoring alpha.
+    Opaque,
+}
+
+/// A rectangle on a [`Canvas`](trait.Canvas.html), in the same coordinates
+/// that are drawn in: 0 to 1 across, and 0 to `Canvas::height()` down.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Rect {
+    /// Left edge.
+    pub x: f32,
+    /// Top edge.
+    pub y: f32,
+    /// Width.
+    pub width: f32,
+    /// Height.
+    pub height: f32,
+}
+
+impl Rect {
+    /// Create a new rectangle.
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    // Transform applied after the camera while this is the viewport, so that
+    // its own coordinates (0 to 1, and 0 to its aspect ratio) fill it on a
+    // canvas with the aspect ratio `aspect`.
+    pub(super) fn camera(&self, aspect: f32) -> Transform {
+        let scale = aspect * self.width / self.height;
+        Transform::new().scale(1.0, scale, 1.0).translate(
+            0.0,
+            aspect * (1.0 - scale),
+            0.0,
+        )
+    }
+}
 
 // // // // // //
 
@@ -597,7 +1835,7 @@ impl ShapeBuilder {
         };
         let mut lock = internal.cmds.lock().unwrap();
         lock.push(GpuCmd::ShapeId(self, id, shader.0));
-        Shape(id)
+        Shape(id, shader.0, shader.1)
     }
 }
 
@@ -605,8 +1843,36 @@ impl ShapeBuilder {
 pub trait Canvas {
     /// Draw a group on the screen.
     fn draw(&mut self, shader: &Shader, group: &Group);
-    /// Set camera for shader.
+    /// Set the camera for everything drawn after this (until it's set again).
     fn set_camera(&mut self, camera: Transform);
+    /// Draw everything after this into part of the canvas, which then acts
+    /// like a whole canvas of its own: coordinates go from 0 to 1 across it,
+    /// and from 0 to its aspect ratio down, and drawing is clipped to it.  The
+    /// camera applies within the viewport, so split-screen views can each set
+    /// their own.  `None` goes back to the whole canvas, which also happens at
+    /// the end of a frame (or when a `RenderTarget` is cleared).
+    fn set_viewport(&mut self, viewport: Option<Rect>);
+    /// Clip everything drawn after this to part of the canvas, in canvas
+    /// coordinates (not affected by the viewport).  `None` stops clipping,
+    /// which also happens at the end of a frame (or when a `RenderTarget` is
+    /// cleared).
+    fn set_scissor(&mut self, scissor: Option<Rect>);
+    /// Set how colors drawn after this are blended with the canvas (default:
+    /// `Blend::Alpha`).  This only applies to shaders built with `blend`;
+    /// other shaders always draw opaque.  Goes back to the default at the end
+    /// of a frame (or when a `RenderTarget` is cleared).
+    fn set_blend(&mut self, blend: Blend);
+    /// Set whether shapes drawn after this are hidden behind what's already
+    /// closer to the camera (default: `true`).  This only applies to shaders
+    /// built with `depth`.  Goes back to the default at the end of a frame (or
+    /// when a `RenderTarget` is cleared).
+    fn set_depth_test(&mut self, test: bool);
+    /// Set whether shapes drawn after this hide what's drawn behind them later
+    /// (default: `true`).  Turn this off for transparent shapes, like
+    /// particles.  This only applies to shaders built with `depth`.  Goes back
+    /// to the default at the end of a frame (or when a `RenderTarget` is
+    /// cleared).
+    fn set_depth_write(&mut self, write: bool);
     /// Set tint for shader.
     fn set_tint<P: pix::el::Pixel>(&mut self, shader: &Shader, tint: P)
     where
@@ -618,6 +1884,99 @@ pub trait Canvas {
         group: &Group,
         graphic: &Texture,
     );
+    /// Draw every copy of a shape on the screen.
+    fn draw_instances(&mut self, shader: &Shader, instances: &Instances) {
+        self.draw(shader, &instances.group);
+    }
+    /// Draw every copy of a shape with a texture on the screen.
+    fn draw_instances_graphic(
+        &mut self,
+        shader: &Shader,
+        instances: &Instances,
+        graphic: &Texture,
+    ) {
+        self.draw_graphic(shader, &instances.group, graphic);
+    }
+    /// Draw triangles from a list of vertices, each an X, Y, and RGBA color
+    /// (6 floats), with the current camera, viewport, scissor and blend mode.
+    ///
+    /// Unlike groups, no `Shader` or `Shape` is needed, and triangles (and
+    /// the primitives drawn with them, like [`rect()`](#method.rect)) drawn
+    /// one after another are batched into a single draw.  With the default
+    /// camera, coordinates go from 0 to 1 across the canvas and from 0 to
+    /// [`height()`](#tymethod.height) down.
+    fn draw_triangles(&mut self, vertices: &[f32]);
+    /// Draw a rectangle, filled, or outlined with lines `stroke` wide.
+    fn rect<P: pix::el::Pixel>(
+        &mut self,
+        rect: Rect,
+        color: P,
+        stroke: Option<f32>,
+    ) where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        self.polygon(&primitives::rect(rect), color, stroke);
+    }
+    /// Draw a rectangle with corners rounded to `radius`, filled, or outlined
+    /// with lines `stroke` wide.
+    fn rounded_rect<P: pix::el::Pixel>(
+        &mut self,
+        rect: Rect,
+        radius: f32,
+        color: P,
+        stroke: Option<f32>,
+    ) where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        let points = primitives::rounded_rect(rect, radius);
+        self.polygon(&points, color, stroke);
+    }
+    /// Draw a circle, filled, or outlined with lines `stroke` wide.
+    fn circle<P: pix::el::Pixel>(
+        &mut self,
+        center: [f32; 2],
+        radius: f32,
+        color: P,
+        stroke: Option<f32>,
+    ) where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        self.polygon(&primitives::circle(center, radius), color, stroke);
+    }
+    /// Draw a polygon through `points` (which can be concave, but can't cross
+    /// itself), filled, or outlined with lines `stroke` wide.
+    fn polygon<P: pix::el::Pixel>(
+        &mut self,
+        points: &[[f32; 2]],
+        color: P,
+        stroke: Option<f32>,
+    ) where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        let color = primitives::rgba(color);
+        let mut vertices = Vec::new();
+        match stroke {
+            Some(width) => {
+                primitives::stroke(&mut vertices, points, width, color)
+            }
+            None => primitives::fill(&mut vertices, points, color),
+        }
+        self.draw_triangles(&vertices);
+    }
+    /// Draw a line from `a` to `b`, `width` wide.
+    fn line<P: pix::el::Pixel>(
+        &mut self,
+        a: [f32; 2],
+        b: [f32; 2],
+        color: P,
+        width: f32,
+    ) where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        let mut vertices = Vec::new();
+        primitives::line(&mut vertices, a, b, width, primitives::rgba(color));
+        self.draw_triangles(&vertices);
+    }
     /// Returns the amount of time elapsed since the previous frame.
     fn elapsed(&self) -> std::time::Duration;
     /// Return the aspect ratio (`height / width`) of the `Canvas`.
@@ -0,0 +1,563 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Sprite animation clips, timelines that play them, and loading them from
+//! Aseprite.
+
+use super::json::Json;
+use super::{Canvas, Group, Shape, Transform};
+use std::fmt;
+use std::time::Duration;
+
+// How long a frame lasts if Aseprite doesn't say (same as Aseprite).
+const DEFAULT_DURATION: u64 = 100;
+
+/// Error from loading a sprite sheet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnimationError {
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl AnimationError {
+    fn new(message: impl Into<String>) -> Self {
+        AnimationError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for AnimationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AnimationError {}
+
+/// What a [`Timeline`](struct.Timeline.html) does after the last frame of a
+/// clip.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Playback {
+    /// Start over from the first frame (the default).
+    #[default]
+    Loop,
+    /// Play backwards to the first frame, and then forwards again.
+    PingPong,
+    /// Stay on the last frame.
+    Once,
+}
+
+// A frame of a clip.
+#[derive(Clone, Debug, PartialEq)]
+struct Keyframe {
+    tex_coords: ([f32; 2], [f32; 2]),
+    duration: Duration,
+    events: Vec<String>,
+}
+
+/// An animation: frames of a texture (usually from an
+/// [`Atlas`](struct.Atlas.html) or a [`SpriteSheet`](struct.SpriteSheet.html)),
+/// each shown for its own duration.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Clip {
+    frames: Vec<Keyframe>,
+    playback: Playback,
+}
+
+impl Clip {
+    /// Create a clip with no frames.
+    pub fn new(playback: Playback) -> Self {
+        Clip {
+            frames: Vec::new(),
+            playback,
+        }
+    }
+
+    /// Add a frame, with texture coordinates (like from
+    /// [`Atlas::get()`](struct.Atlas.html#method.get)) shown for `duration`.
+    pub fn frame(
+        mut self,
+        tex_coords: ([f32; 2], [f32; 2]),
+        duration: Duration,
+    ) -> Self {
+        self.frames.push(Keyframe {
+            tex_coords,
+            duration,
+            events: Vec::new(),
+        });
+        self
+    }
+
+    /// Add an event, fired each time the timeline gets to `frame` (like a
+    /// footstep sound).
+    ///
+    /// # Panics
+    /// If the clip doesn't have that frame.
+    pub fn event(mut self, frame: usize, name: &str) -> Self {
+        self.frames[frame].events.push(name.to_string());
+        self
+    }
+
+    /// Get what happens after the last frame.
+    pub fn playback(&self) -> Playback {
+        self.playback
+    }
+
+    /// Set what happens after the last frame.
+    pub fn set_playback(&mut self, playback: Playback) {
+        self.playback = playback;
+    }
+
+    /// Get the number of frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Check if the clip has no frames.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Get the texture coordinates of a frame.
+    pub fn tex_coords(&self, frame: usize) -> Option<([f32; 2], [f32; 2])> {
+        Some(self.frames.get(frame)?.tex_coords)
+    }
+
+    /// Get how long a frame is shown for.
+    pub fn frame_duration(&self, frame: usize) -> Option<Duration> {
+        Some(self.frames.get(frame)?.duration)
+    }
+
+    /// Get how long it takes to play every frame once.
+    pub fn duration(&self) -> Duration {
+        self.frames.iter().map(|frame| frame.duration).sum()
+    }
+}
+
+/// Named clips loaded from a sprite sheet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpriteSheet {
+    frames: Clip,
+    clips: Vec<(String, Clip)>,
+}
+
+impl SpriteSheet {
+    /// Load a sprite sheet exported from Aseprite as JSON (either as a hash
+    /// or an array), with the sheet's image loaded separately as a
+    /// `Texture`.
+    ///
+    /// Each tag becomes a clip, played forwards, in reverse or ping-ponged
+    /// like its direction.  Tags that repeat once are played
+    /// [`Once`](enum.Playback.html#variant.Once), and every other tag loops.
+    /// Frames can't be rotated, and shouldn't be trimmed (since trimmed frames
+    /// are stretched to fill the shape they're drawn on).
+    pub fn load_aseprite(text: &str) -> Result<Self, AnimationError> {
+        let sheet = Json::parse(text).map_err(AnimationError::new)?;
+        let meta = sheet
+            .get("meta")
+            .ok_or_else(|| AnimationError::new("sheet has no meta"))?;
+        let size = |key| {
+            meta.get("size")
+                .and_then(|size| size.get(key))
+                .and_then(Json::as_f32)
+                .filter(|size| *size > 0.0)
+                .ok_or_else(|| AnimationError::new("sheet has no size"))
+        };
+        let (width, height) = (size("w")?, size("h")?);
+
+        // Frames are either an array, or an object keyed by filename (in
+        // order).
+        let frames: Vec<&Json> = match sheet.get("frames") {
+            Some(Json::Array(frames)) => frames.iter().collect(),
+            Some(Json::Object(frames)) => {
+                frames.iter().map(|(_, frame)| frame).collect()
+            }
+            _ => return Err(AnimationError::new("sheet has no frames")),
+        };
+        let mut all = Clip::new(Playback::Loop);
+        for (index, frame) in frames.into_iter().enumerate() {
+            if frame.get("rotated").and_then(Json::as_bool) == Some(true) {
+                return Err(AnimationError::new(format!(
+                    "frame {} is rotated, which isn't supported",
+                    index
+                )));
+            }
+            let rect = |key| {
+                frame
+                    .get("frame")
+                    .and_then(|rect| rect.get(key))
+                    .and_then(Json::as_f32)
+                    .ok_or_else(|| {
+                        AnimationError::new(format!(
+                            "frame {} has no {}",
+                            index, key
+                        ))
+                    })
+            };
+            let tex_coords = (
+                [rect("x")? / width, rect("y")? / height],
+                [rect("w")? / width, rect("h")? / height],
+            );
+            let duration = frame
+                .get("duration")
+                .and_then(Json::as_usize)
+                .map(|duration| duration as u64)
+                .unwrap_or(DEFAULT_DURATION);
+            all = all.frame(tex_coords, Duration::from_millis(duration));
+        }
+
+        let mut clips = Vec::new();
+        for tag in meta.get("frameTags").into_iter().flat_map(Json::iter) {
+            let name = tag
+                .get("name")
+                .and_then(Json::as_str)
+                .ok_or_else(|| AnimationError::new("tag has no name"))?;
+            let index = |key| {
+                tag.get(key)
+                    .and_then(Json::as_usize)
+                    .filter(|index| *index < all.len())
+                    .ok_or_else(|| {
+                        AnimationError::new(format!(
+                            "tag {} has no valid {}",
+                            name, key
+                        ))
+                    })
+            };
+            let (from, to) = (index("from")?, index("to")?);
+            if from > to {
+                return Err(AnimationError::new(format!(
+                    "tag {} ends before it starts",
+                    name
+                )));
+            }
+            let direction = tag
+                .get("direction")
+                .and_then(Json::as_str)
+                .unwrap_or("forward");
+            let (reverse, pingpong) = match direction {
+                "forward" => (false, false),
+                "reverse" => (true, false),
+                "pingpong" => (false, true),
+                "pingpong_reverse" => (true, true),
+                _ => {
+                    return Err(AnimationError::new(format!(
+                        "tag {} has unknown direction {}",
+                        name, direction
+                    )))
+                }
+            };
+            // Newer versions of Aseprite write the repeat count as a string.
+            let repeat = tag.get("repeat").and_then(|repeat| {
+                repeat.as_usize().or_else(|| repeat.as_str()?.parse().ok())
+            });
+            let playback = match (repeat, pingpong) {
+                (Some(1), _) => Playback::Once,
+                (_, true) => Playback::PingPong,
+                (_, false) => Playback::Loop,
+            };
+            let mut frames = all.frames[from..=to].to_vec();
+            if reverse {
+                frames.reverse();
+            }
+            clips.push((name.to_string(), Clip { frames, playback }));
+        }
+
+        Ok(SpriteSheet { frames: all, clips })
+    }
+
+    /// Get a clip by the name of its tag.
+    pub fn clip(&self, name: &str) -> Option<&Clip> {
+        self.clips
+            .iter()
+            .find(|(clip, _)| clip == name)
+            .map(|(_, clip)| clip)
+    }
+
+    /// Get the names of the clips, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.clips.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Get a looping clip of every frame in the sheet (for sheets without
+    /// tags).
+    pub fn frames(&self) -> &Clip {
+        &self.frames
+    }
+}
+
+/// Plays a [`Clip`](struct.Clip.html), keeping track of which frame to show.
+///
+/// Call [`update()`](#method.update) once per frame, and then
+/// [`write()`](#method.write) the current frame into a `Group`.
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    clip: Clip,
+    frame: usize,
+    // Time spent on the current frame.
+    time: Duration,
+    // Whether a ping-pong clip is playing backwards.
+    backwards: bool,
+    finished: bool,
+    // Whether the events of the first frame have been fired.
+    started: bool,
+    events: Vec<String>,
+}
+
+impl Timeline {
+    /// Create a timeline, playing `clip` from the start.
+    pub fn new(clip: &Clip) -> Self {
+        Timeline {
+            clip: clip.clone(),
+            frame: 0,
+            time: Duration::default(),
+            backwards: false,
+            finished: false,
+            started: false,
+            events: Vec::new(),
+        }
+    }
+
+    /// Switch to playing another clip, from the start.
+    pub fn play(&mut self, clip: &Clip) {
+        *self = Self::new(clip);
+    }
+
+    /// Play the current clip again from the start.
+    pub fn restart(&mut self) {
+        self.frame = 0;
+        self.time = Duration::default();
+        self.backwards = false;
+        self.finished = false;
+        self.started = false;
+        self.events.clear();
+    }
+
+    /// Get the clip that's playing.
+    pub fn clip(&self) -> &Clip {
+        &self.clip
+    }
+
+    /// Get the index of the frame that's showing.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Check if a clip that plays [`Once`](enum.Playback.html#variant.Once)
+    /// has reached the end of its last frame.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Get the texture coordinates of the frame that's showing (or the whole
+    /// texture if the clip has no frames).
+    pub fn tex_coords(&self) -> ([f32; 2], [f32; 2]) {
+        self.clip
+            .tex_coords(self.frame)
+            .unwrap_or(([0.0, 0.0], [1.0, 1.0]))
+    }
+
+    /// Get the events of the frames that were reached during the last update,
+    /// in order.
+    pub fn events(&self) -> impl Iterator<Item = &str> {
+        self.events.iter().map(|event| event.as_str())
+    }
+
+    /// Advance by the time since the last frame of `canvas`
+    /// ([`Canvas::elapsed()`](trait.Canvas.html#tymethod.elapsed)).
+    pub fn update<C: Canvas>(&mut self, canvas: &C) {
+        self.step(canvas.elapsed());
+    }
+
+    /// Advance by `elapsed` time (like a fixed time step, or none while
+    /// paused).
+    pub fn step(&mut self, elapsed: Duration) {
+        self.events.clear();
+        if self.clip.is_empty() {
+            return;
+        }
+        if !self.started {
+            self.started = true;
+            self.enter(self.frame);
+        }
+        if self.finished {
+            return;
+        }
+        // A clip that takes no time would never get to the end.
+        if self.clip.duration() == Duration::default() {
+            return;
+        }
+        self.time += elapsed;
+        loop {
+            let duration = self.clip.frames[self.frame].duration;
+            if self.time < duration {
+                break;
+            }
+            self.time -= duration;
+            let last = self.clip.len() - 1;
+            let next = match self.clip.playback {
+                Playback::PingPong => {
+                    if self.backwards && self.frame == 0
+                        || !self.backwards && self.frame == last
+                    {
+                        self.backwards = !self.backwards;
+                    }
+                    if self.backwards {
+                        self.frame.saturating_sub(1)
+                    } else {
+                        (self.frame + 1).min(last)
+                    }
+                }
+                _ if self.frame < last => self.frame + 1,
+                Playback::Loop => 0,
+                Playback::Once => {
+                    self.finished = true;
+                    self.time = Duration::default();
+                    break;
+                }
+            };
+            self.enter(next);
+        }
+    }
+
+    /// Write the frame that's showing into a group, the same as
+    /// [`Group::write_tex()`](struct.Group.html#method.write_tex).
+    pub fn write(
+        &self,
+        group: &mut Group,
+        id: u32,
+        shape: &Shape,
+        transform: &Transform,
+    ) {
+        group.write_tex(id, shape, transform, self.tex_coords());
+    }
+
+    // Show a frame, firing its events.
+    fn enter(&mut self, frame: usize) {
+        self.frame = frame;
+        let events = &self.clip.frames[frame].events;
+        self.events.extend(events.iter().cloned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three frames of a 64 × 16 sheet, in Aseprite's hash format.
+    const SHEET: &str = r#"{
+        "frames": {
+            "hero 0.aseprite": {
+                "frame": { "x": 0, "y": 0, "w": 16, "h": 16 },
+                "rotated": false,
+                "trimmed": false,
+                "duration": 100
+            },
+            "hero 1.aseprite": {
+                "frame": { "x": 16, "y": 0, "w": 16, "h": 16 },
+                "duration": 200
+            },
+            "hero 2.aseprite": {
+                "frame": { "x": 32, "y": 0, "w": 16, "h": 16 }
+            }
+        },
+        "meta": {
+            "size": { "w": 64, "h": 16 },
+            "frameTags": [
+                { "name": "walk", "from": 0, "to": 2, "direction": "pingpong" },
+                { "name": "die", "from": 1, "to": 2, "direction": "reverse",
+                  "repeat": "1" }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn aseprite() {
+        let sheet = SpriteSheet::load_aseprite(SHEET).unwrap();
+        let frames = sheet.frames();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames.playback(), Playback::Loop);
+        assert_eq!(frames.tex_coords(1), Some(([0.25, 0.0], [0.25, 1.0])));
+        assert_eq!(frames.frame_duration(1), Some(Duration::from_millis(200)));
+        // Frames without a duration get the default.
+        assert_eq!(frames.frame_duration(2), Some(Duration::from_millis(100)));
+
+        assert_eq!(sheet.names().collect::<Vec<_>>(), ["walk", "die"]);
+        let walk = sheet.clip("walk").unwrap();
+        assert_eq!(walk.playback(), Playback::PingPong);
+        assert_eq!(walk.len(), 3);
+        let die = sheet.clip("die").unwrap();
+        assert_eq!(die.playback(), Playback::Once);
+        assert_eq!(die.tex_coords(0), frames.tex_coords(2));
+        assert_eq!(die.tex_coords(1), frames.tex_coords(1));
+        assert_eq!(sheet.clip("missing"), None);
+    }
+
+    #[test]
+    fn aseprite_array() {
+        let sheet = SpriteSheet::load_aseprite(
+            r#"{
+                "frames": [
+                    { "filename": "0", "frame": { "x": 0, "y": 8, "w": 8, "h": 8 } }
+                ],
+                "meta": { "size": { "w": 8, "h": 16 } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            sheet.frames().tex_coords(0),
+            Some(([0.0, 0.5], [1.0, 0.5]))
+        );
+        assert_eq!(sheet.names().count(), 0);
+    }
+
+    #[test]
+    fn aseprite_errors() {
+        let error = |text: &str| SpriteSheet::load_aseprite(text).unwrap_err();
+        let message = |text: &str| error(text).message;
+        assert_eq!(message("{}"), "sheet has no meta");
+        assert_eq!(error("[").to_string(), "unexpected end of JSON (line 1)");
+        assert_eq!(message(r#"{"meta": {}}"#), "sheet has no size");
+        let meta = r#""meta": {"size": {"w": 8, "h": 8}"#;
+        assert_eq!(message(&format!("{{{}}}}}", meta)), "sheet has no frames");
+        let frame = r#"{"frame": {"x": 0, "y": 0, "w": 8, "h": 8}}"#;
+        let rotated =
+            r#"{"frame": {"x": 0, "y": 0, "w": 8, "h": 8}, "rotated": true}"#;
+        let sheet = |frame: &str, tags: &str| {
+            format!(
+                r#"{{"frames": [{}], {}, "frameTags": [{}]}}}}"#,
+                frame, meta, tags
+            )
+        };
+        assert_eq!(
+            message(&sheet(rotated, "")),
+            "frame 0 is rotated, which isn't supported"
+        );
+        assert_eq!(
+            message(&sheet(r#"{"frame": {"x": 0}}"#, "")),
+            "frame 0 has no y"
+        );
+        assert_eq!(
+            message(&sheet(frame, r#"{"from": 0, "to": 0}"#)),
+            "tag has no name"
+        );
+        assert_eq!(
+            message(&sheet(frame, r#"{"name": "a", "from": 0, "to": 1}"#)),
+            "tag a has no valid to"
+        );
+        assert_eq!(
+            message(&sheet(
+                frame,
+                r#"{"name": "a", "from": 0, "to": 0, "direction": "up"}"#
+            )),
+            "tag a has unknown direction up"
+        );
+    }
+}
@@ -0,0 +1,462 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Packing many rasters into one texture.
+
+use super::Texture;
+use pix::{rgb::SRgba8, Raster, Region};
+use std::collections::HashMap;
+use std::fmt;
+
+// Smallest size of an atlas, in pixels.
+const ATLAS_MIN: u32 = 64;
+// Largest size of an atlas, in pixels.
+const ATLAS_MAX: u32 = 4096;
+// Gap between sprites, in pixels.
+const PADDING: u32 = 1;
+
+/// Error from adding rasters to an [`Atlas`](struct.Atlas.html) that don't
+/// fit in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AtlasError {
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl AtlasError {
+    fn new() -> Self {
+        AtlasError {
+            message: format!("rasters don't fit in a {0}×{0} atlas", ATLAS_MAX),
+        }
+    }
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+// A raster in the atlas.
+struct Sprite {
+    raster: Raster<SRgba8>,
+    region: Region,
+}
+
+// Skyline rectangle packer: the top edge of the packed area, as segments of
+// (x, y, width) from left to right.
+#[derive(Clone, Debug, PartialEq)]
+struct Skyline {
+    width: u32,
+    height: u32,
+    segments: Vec<(u32, u32, u32)>,
+}
+
+impl Skyline {
+    fn new(width: u32, height: u32) -> Self {
+        Skyline {
+            width,
+            height,
+            segments: vec![(0, 0, width)],
+        }
+    }
+
+    // Make more space, keeping everything that's already packed in place.
+    fn grow(&mut self, width: u32, height: u32) {
+        if width > self.width {
+            self.segments.push((self.width, 0, width - self.width));
+        }
+        self.width = width;
+        self.height = height;
+    }
+
+    // Find the highest spot (lowest y) that fits a rectangle, leftmost first.
+    // Nothing changes if it doesn't fit.
+    fn pack(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(u32, u32, usize)> = None;
+        for (i, &(x, _, _)) in self.segments.iter().enumerate() {
+            if x + width > self.width {
+                break;
+            }
+            let mut y = 0;
+            let mut covered = 0;
+            for &(_, top, span) in self.segments[i..].iter() {
+                y = y.max(top);
+                covered += span;
+                if covered >= width {
+                    break;
+                }
+            }
+            if y + height <= self.height
+                && best.map(|(_, best_y, _)| y < best_y).unwrap_or(true)
+            {
+                best = Some((x, y, i));
+            }
+        }
+        let (x, y, i) = best?;
+
+        // Raise the skyline over the new rectangle.
+        let right = x + width;
+        let mut end = i;
+        while end < self.segments.len() {
+            let (sx, sy, span) = self.segments[end];
+            if sx + span > right {
+                self.segments[end] = (right, sy, sx + span - right);
+                break;
+            }
+            end += 1;
+        }
+        self.segments.splice(i..end, Some((x, y + height, width)));
+        self.segments.dedup_by(|b, a| {
+            if a.1 == b.1 {
+                a.2 += b.2;
+                true
+            } else {
+                false
+            }
+        });
+        Some((x, y))
+    }
+}
+
+/// Builder for an [`Atlas`](struct.Atlas.html).
+#[derive(Default)]
+pub struct AtlasBuilder {
+    sprites: Vec<(String, Raster<SRgba8>)>,
+}
+
+impl AtlasBuilder {
+    /// Create a new `AtlasBuilder`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a named raster to the atlas.
+    pub fn sprite<P: pix::el::Pixel>(
+        mut self,
+        name: &str,
+        raster: &Raster<P>,
+    ) -> Self
+    where
+        pix::chan::Ch8: From<<P as pix::el::Pixel>::Chan>,
+    {
+        self.sprites
+            .push((name.to_string(), Raster::with_raster(raster)));
+        self
+    }
+
+    /// Pack the rasters into the smallest atlas they fit in, and copy it to
+    /// the GPU.  Fails if they don't fit in the largest atlas (4096×4096).
+    pub fn finish(self) -> Result<Atlas, AtlasError> {
+        let area: u64 = self
+            .sprites
+            .iter()
+            .map(|(_, r)| {
+                u64::from(r.width() + PADDING) * u64::from(r.height() + PADDING)
+            })
+            .sum();
+        if area > u64::from(ATLAS_MAX) * u64::from(ATLAS_MAX) {
+            return Err(AtlasError::new());
+        }
+        let side = ((area as f64).sqrt().ceil() as u32)
+            .next_power_of_two()
+            .clamp(ATLAS_MIN, ATLAS_MAX);
+        let mut atlas = Atlas::with_size(side);
+        atlas.sprites = self
+            .sprites
+            .into_iter()
+            .map(|(name, raster)| {
+                let region = Region::new(0, 0, 0, 0);
+                (name, Sprite { raster, region })
+            })
+            .collect();
+        atlas.repack(side, side)?;
+        Ok(atlas)
+    }
+}
+
+/// Many named rasters packed into one `Texture`.
+///
+/// Use [`get()`](#method.get) to look up the texture coordinates of a raster,
+/// which can be passed straight to
+/// [`Group::write_tex()`](struct.Group.html#method.write_tex).
+pub struct Atlas {
+    texture: Texture,
+    skyline: Skyline,
+    sprites: HashMap<String, Sprite>,
+}
+
+impl Atlas {
+    // Create an empty `side` × `side` atlas.
+    pub(super) fn with_size(side: u32) -> Self {
+        Atlas {
+            texture: Texture::new(&Raster::<SRgba8>::with_clear(side, side)),
+            skyline: Skyline::new(side, side),
+            sprites: HashMap::new(),
+        }
+    }
+
+    /// Get the texture coordinates of a named raster.
+    pub fn get(&self, name: &str) -> Option<([f32; 2], [f32; 2])> {
+        let region = self.region(name)?;
+        let (width, height) =
+            (self.skyline.width as f32, self.skyline.height as f32);
+        Some((
+            [region.left() as f32 / width, region.top() as f32 / height],
+            [
+                region.width() as f32 / width,
+                region.height() as f32 / height,
+            ],
+        ))
+    }
+
+    /// Get where a named raster is in the texture, in pixels.
+    pub fn region(&self, name: &str) -> Option<Region> {
+        Some(self.sprites.get(name)?.region)
+    }
+
+    /// Get the `Texture` that the rasters are packed into.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Add (or replace) a named raster.
+    ///
+    /// Returns `true` if the atlas had to grow or be repacked, which changes
+    /// the texture coordinates of every raster, so any that are in use need to
+    /// be looked up again.  Fails if it doesn't fit even after repacking,
+    /// leaving the atlas as it was.
+    pub fn add<P: pix::el::Pixel>(
+        &mut self,
+        name: &str,
+        raster: &Raster<P>,
+    ) -> Result<bool, AtlasError>
+    where
+        pix::chan::Ch8: From<<P as pix::el::Pixel>::Chan>,
+    {
+        let raster = Raster::<SRgba8>::with_raster(raster);
+
+        // Rasters that are the same size are replaced in place.
+        if let Some(sprite) = self.sprites.get_mut(name) {
+            if sprite.region.width() == raster.width()
+                && sprite.region.height() == raster.height()
+            {
+                self.texture.update::<SRgba8>(sprite.region, &raster);
+                sprite.raster = raster;
+                return Ok(false);
+            }
+        }
+
+        // Grow a copy of the skyline, so nothing changes unless it fits.
+        let (width, height) = (raster.width(), raster.height());
+        let mut skyline = self.skyline.clone();
+        loop {
+            if let Some((x, y)) =
+                skyline.pack(width + PADDING, height + PADDING)
+            {
+                let grown = skyline.width != self.skyline.width
+                    || skyline.height != self.skyline.height;
+                if grown {
+                    let (width, height) = (skyline.width, skyline.height);
+                    self.texture.resize(width as u16, height as u16);
+                }
+                self.skyline = skyline;
+                let region = Region::new(x as i32, y as i32, width, height);
+                self.texture.update::<SRgba8>(region, &raster);
+                self.sprites
+                    .insert(name.to_string(), Sprite { raster, region });
+                return Ok(grown);
+            }
+            // Grow the shorter side, until it's as big as it can be.
+            let (width, height) = (skyline.width, skyline.height);
+            if width <= height && width < ATLAS_MAX {
+                skyline.grow(width * 2, height);
+            } else if height < ATLAS_MAX {
+                skyline.grow(width, height * 2);
+            } else {
+                break;
+            }
+        }
+
+        // Out of space, so repack everything to reclaim gaps.
+        let region = Region::new(0, 0, 0, 0);
+        let old = self
+            .sprites
+            .insert(name.to_string(), Sprite { raster, region });
+        if let Err(error) = self.repack(ATLAS_MAX, ATLAS_MAX) {
+            match old {
+                Some(old) => self.sprites.insert(name.to_string(), old),
+                None => self.sprites.remove(name),
+            };
+            return Err(error);
+        }
+        Ok(true)
+    }
+
+    /// Remove a named raster.  Its space is reclaimed the next time the atlas
+    /// is repacked.
+    pub fn remove(&mut self, name: &str) {
+        self.sprites.remove(name);
+    }
+
+    /// Remove every raster, reclaiming all of the space (the texture stays
+    /// the same size).
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+        self.skyline = Skyline::new(self.skyline.width, self.skyline.height);
+    }
+
+    // Pack every raster from scratch (tallest first), doubling the size until
+    // they fit, and copy the new atlas to the GPU.  Nothing changes if they
+    // don't fit.
+    fn repack(&mut self, width: u32, height: u32) -> Result<(), AtlasError> {
+        let mut names: Vec<&String> = self.sprites.keys().collect();
+        names.sort_by_key(|name| {
+            let raster = &self.sprites[*name].raster;
+            (std::cmp::Reverse(raster.height()), *name)
+        });
+        let (mut width, mut height) = (width, height);
+        let (skyline, regions) = 'pack: loop {
+            let mut skyline = Skyline::new(width, height);
+            let mut regions = Vec::with_capacity(names.len());
+            for name in names.iter() {
+                let raster = &self.sprites[*name].raster;
+                let (w, h) = (raster.width(), raster.height());
+                if let Some((x, y)) = skyline.pack(w + PADDING, h + PADDING) {
+                    regions.push(Region::new(x as i32, y as i32, w, h));
+                } else if width < ATLAS_MAX || height < ATLAS_MAX {
+                    if width <= height {
+                        width *= 2;
+                    } else {
+                        height *= 2;
+                    }
+                    continue 'pack;
+                } else {
+                    return Err(AtlasError::new());
+                }
+            }
+            break (skyline, regions);
+        };
+        let names: Vec<String> = names.into_iter().cloned().collect();
+        for (name, region) in names.iter().zip(regions) {
+            self.sprites.get_mut(name).unwrap().region = region;
+        }
+        self.skyline = skyline;
+
+        let mut raster = Raster::<SRgba8>::with_clear(width, height);
+        for sprite in self.sprites.values() {
+            raster.copy_raster(sprite.region, &sprite.raster, ());
+        }
+        self.texture.resize(width as u16, height as u16);
+        self.texture.update(raster.region(), &raster);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skyline_fit() {
+        let mut skyline = Skyline::new(8, 8);
+        assert_eq!(skyline.pack(2, 6), Some((0, 0)));
+        assert_eq!(skyline.pack(6, 2), Some((2, 0)));
+        // The highest spot, not the first one.
+        assert_eq!(skyline.pack(2, 2), Some((2, 2)));
+        assert_eq!(skyline.pack(4, 4), Some((4, 2)));
+        assert_eq!(skyline.segments, [(0, 6, 2), (2, 4, 2), (4, 6, 4)]);
+        assert_eq!(skyline.pack(2, 2), Some((2, 4)));
+        assert_eq!(skyline.segments, [(0, 6, 8)]);
+    }
+
+    #[test]
+    fn skyline_fail() {
+        let mut skyline = Skyline::new(8, 8);
+        assert_eq!(skyline.pack(8, 6), Some((0, 0)));
+        let before = skyline.clone();
+        assert_eq!(skyline.pack(9, 1), None);
+        assert_eq!(skyline.pack(1, 3), None);
+        assert_eq!(skyline, before);
+    }
+
+    #[test]
+    fn skyline_grow() {
+        let mut skyline = Skyline::new(4, 4);
+        assert_eq!(skyline.pack(4, 4), Some((0, 0)));
+        assert_eq!(skyline.pack(4, 4), None);
+        // What's packed stays where it is.
+        skyline.grow(8, 4);
+        assert_eq!(skyline.pack(4, 4), Some((4, 0)));
+        skyline.grow(8, 8);
+        assert_eq!(skyline.pack(8, 4), Some((0, 4)));
+        assert_eq!(skyline.pack(1, 1), None);
+    }
+
+    #[test]
+    fn atlas() {
+        // Fills the largest atlas.
+        let full = Raster::<SRgba8>::with_clear(ATLAS_MAX - 1, ATLAS_MAX - 1);
+        let wide = Raster::<SRgba8>::with_clear(ATLAS_MAX + 1, 1);
+        let dot = Raster::<SRgba8>::with_clear(1, 1);
+        let mut atlas = Atlas::with_size(ATLAS_MIN);
+        assert_eq!(atlas.add("a", &full), Ok(true));
+        assert_eq!(atlas.skyline.width, ATLAS_MAX);
+
+        // A failed add leaves the atlas as it was.
+        let skyline = atlas.skyline.clone();
+        let region = atlas.region("a");
+        assert_eq!(atlas.add("b", &dot), Err(AtlasError::new()));
+        assert_eq!(atlas.add("a", &wide), Err(AtlasError::new()));
+        assert_eq!(atlas.skyline, skyline);
+        assert_eq!(atlas.region("b"), None);
+        assert_eq!(atlas.region("a"), region);
+
+        // Removed rasters make room once the atlas is repacked.
+        atlas.remove("a");
+        assert_eq!(atlas.region("a"), None);
+        assert_eq!(atlas.add("b", &dot), Ok(true));
+        assert_eq!(atlas.region("b"), Some(Region::new(0, 0, 1, 1)));
+
+        // Same-size rasters are replaced in place.
+        assert_eq!(atlas.add("b", &dot), Ok(false));
+        assert_eq!(atlas.region("b"), Some(Region::new(0, 0, 1, 1)));
+    }
+
+    #[test]
+    fn grow() {
+        let small = Raster::<SRgba8>::with_clear(40, 40);
+        let mut atlas = Atlas::with_size(ATLAS_MIN);
+        assert_eq!(atlas.add("a", &small), Ok(false));
+        let a = atlas.get("a").unwrap();
+        assert_eq!(atlas.add("b", &small), Ok(true));
+        assert_eq!(atlas.skyline.width, ATLAS_MIN * 2);
+        // Texture coordinates change when the atlas grows.
+        assert_ne!(atlas.get("a"), Some(a));
+        assert_eq!(atlas.region("b"), Some(Region::new(41, 0, 40, 40)));
+    }
+
+    #[test]
+    fn finish() {
+        let raster = Raster::<SRgba8>::with_clear(30, 30);
+        let atlas = AtlasBuilder::new()
+            .sprite("a", &raster)
+            .sprite("b", &raster)
+            .finish()
+            .unwrap();
+        assert_eq!(atlas.skyline.width, ATLAS_MIN);
+        // Too wide for the largest atlas.
+        let wide = Raster::<SRgba8>::with_clear(ATLAS_MAX + 1, 1);
+        let error = AtlasBuilder::new().sprite("a", &wide).finish().err();
+        assert_eq!(error, Some(AtlasError::new()));
+    }
+}
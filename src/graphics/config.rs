@@ -0,0 +1,50 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Window configuration.
+
+/// Builder for the settings of the window opened by
+/// [`draw_thread_with()`](fn.draw_thread_with.html).
+///
+/// Only the title can be set, and only when the window opens.  The `window`
+/// crate has no way to set the window's size, minimum size, fullscreen or
+/// borderless mode, whether it's resizable, or its icon, and no way to change
+/// anything once the window is open, so there are no settings for those (its
+/// Wayland backend toggles fullscreen itself when F11 is pressed).
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    title: String,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            title: env!("CARGO_PKG_NAME").to_string(),
+        }
+    }
+}
+
+impl WindowConfig {
+    /// Create a new `WindowConfig`, titled with the name of the crate.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the title.
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    // Get the title.
+    pub(super) fn get_title(&self) -> &str {
+        &self.title
+    }
+}
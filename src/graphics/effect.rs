@@ -0,0 +1,202 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Full-screen post-processing effects.
+
+use super::{gl, Shader, Slots, Texture, VertexFormat};
+use std::collections::HashMap;
+
+/// A full-screen effect, run over the finished frame by
+/// [`set_effects()`](fn.set_effects.html).
+///
+/// The effect's shader draws a rectangle over the whole frame, with a copy of
+/// the frame as its texture (`uniform sampler2D tex;`), so it must be built
+/// with `graphic` and without `depth`.  Texture coordinates go from 0 to 1
+/// across and down the frame.  Parameters (like a vignette's strength, or a
+/// blur's radius) are passed in the shader's tint, so shaders that need them
+/// should be built with `tint`.
+///
+/// A second texture, like a color grading lookup table, can be read from
+/// `uniform sampler2D lut;` in the fragment shader.
+#[derive(Copy, Clone)]
+pub struct Effect<'a> {
+    shader: &'a Shader,
+    tint: [f32; 4],
+    lut: Option<&'a Texture>,
+}
+
+impl<'a> Effect<'a> {
+    /// Create an effect drawn with `shader`.
+    pub fn new(shader: &'a Shader) -> Self {
+        assert!(
+            shader.1.graphic && shader.1.dimensions == 2,
+            "Effect shaders need graphic, and can't have depth"
+        );
+        Effect {
+            shader,
+            tint: [1.0; 4],
+            lut: None,
+        }
+    }
+
+    /// Set the shader's tint, for the effect's parameters.
+    pub fn tint<P: pix::el::Pixel>(mut self, tint: P) -> Self
+    where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        use pix::chan::Channel;
+        use pix::el::Pixel;
+
+        let color: pix::rgb::SRgba32 = tint.convert();
+        self.tint = [
+            color.one().to_f32(),
+            color.two().to_f32(),
+            color.three().to_f32(),
+            color.four().to_f32(),
+        ];
+        self
+    }
+
+    /// Set the texture read from `uniform sampler2D lut;`.
+    pub fn lut(mut self, lut: &'a Texture) -> Self {
+        self.lut = Some(lut);
+        self
+    }
+
+    pub(super) fn internal(&self) -> EffectInternal {
+        EffectInternal {
+            shader: self.shader.0,
+            format: self.shader.1,
+            tint: self.tint,
+            lut: self.lut.map(|lut| lut.0),
+        }
+    }
+}
+
+// An effect, on the graphics thread.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct EffectInternal {
+    pub(super) shader: u32,
+    pub(super) format: VertexFormat,
+    pub(super) tint: [f32; 4],
+    pub(super) lut: Option<u32>,
+}
+
+// Runs effects on the GPU.
+pub(super) struct Post {
+    // Effects run over the finished frame.
+    effects: Vec<EffectInternal>,
+    // Copy of the frame, and its size in pixels.
+    frame: Option<(gl::Texture, i32, i32)>,
+    // Rectangles covering the frame, for each shader used by an effect.
+    quads: HashMap<u32, window::Group>,
+    // Group with nothing in it, drawn to bind textures.
+    empty: Option<window::Group>,
+}
+
+impl Post {
+    pub(super) fn new() -> Self {
+        Post {
+            effects: Vec::new(),
+            frame: None,
+            quads: HashMap::new(),
+            empty: None,
+        }
+    }
+
+    pub(super) fn set_effects(&mut self, effects: Vec<EffectInternal>) {
+        self.effects = effects;
+    }
+
+    // Get the effects, for starting recordings.
+    pub(super) fn effects(&self) -> &[EffectInternal] {
+        &self.effects
+    }
+
+    // Drop effects that use a lookup table that has been freed.
+    pub(super) fn raster_delete(&mut self, raster: u32) {
+        self.effects.retain(|effect| effect.lut != Some(raster));
+    }
+
+    // Drop effects that use a shader that has been freed, and forget its
+    // rectangle.
+    pub(super) fn shader_delete(&mut self, shader: u32) {
+        self.effects.retain(|effect| effect.shader != shader);
+        self.quads.remove(&shader);
+    }
+
+    // Run effects over the window, in order, after the frame's commands.
+    pub(super) fn run(
+        &mut self,
+        window: &mut window::Window,
+        state: &mut gl::State,
+        shaders: &mut Slots<gl::Program>,
+        rasters: &Slots<gl::Texture>,
+    ) {
+        if self.effects.is_empty() {
+            return;
+        }
+        let (width, height) = state.size();
+        if !matches!(self.frame, Some((_, w, h)) if w == width && h == height) {
+            // The window has no way to resize a graphic, so replace it.
+            let pixels = vec![0; width as usize * height as usize * 4];
+            let texture =
+                gl::texture_new(window, &pixels, width as u32, height as u32);
+            if let Some((old, _, _)) =
+                self.frame.replace((texture, width, height))
+            {
+                state.delete_texture(old);
+            }
+        }
+        let frame = &self.frame.as_ref().unwrap().0;
+        let empty = self.empty.get_or_insert_with(|| window.group_new());
+
+        state.begin_effects(window);
+        for effect in self.effects.iter() {
+            let quad = self.quads.entry(effect.shader).or_insert_with(|| {
+                let shader = &mut shaders[effect.shader].shader;
+                let vertices = quad(effect.format);
+                let shape = window::ShapeBuilder::new(shader)
+                    .vert(&vertices)
+                    .face(window::Transform::new())
+                    .finish();
+                let mut group = window.group_new();
+                group.write_tex(
+                    (0, 0),
+                    &shape,
+                    &window::Transform::new(),
+                    ([0.0, 0.0], [1.0, 1.0]),
+                );
+                group
+            });
+            let program = &mut shaders[effect.shader];
+            program.tint = effect.tint;
+            let shader = &program.shader;
+            window.tint(shader, effect.tint);
+            let lut = effect.lut.map(|lut| &rasters[lut]);
+            state.effect_input(window, shader, empty, frame, lut);
+            window.draw_graphic(shader, quad, &frame.raster);
+        }
+        state.end_effects(window);
+    }
+}
+
+// Two triangles covering the frame, laid out for a shader.  The frame is
+// copied bottom row first, so texture coordinates are flipped.
+fn quad(format: VertexFormat) -> Vec<f32> {
+    let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+    let mut vertices = Vec::new();
+    for &corner in [0, 1, 2, 2, 3, 0].iter() {
+        let (x, y) = corners[corner];
+        vertices.extend_from_slice(&[x, y, x, 1.0 - y]);
+        vertices.resize(vertices.len() + format.components, 1.0);
+    }
+    vertices
+}
@@ -0,0 +1,940 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! OpenGL state that the `window` crate doesn't expose.
+//!
+//! This is the only module that calls OpenGL or EGL directly; everything else
+//! goes through `window`, or through the functions and `State` here.  They're
+//! called on the drawing thread while `window`'s context is current.
+//!
+//! `window` caches some of the state, and skips calls that it thinks wouldn't
+//! change it, so the invariant kept here is that the cache is never wrong once
+//! control goes back to `window`:
+//!
+//! - The texture bound to unit 0 and the program in use: `window` leaves new
+//!   ones bound without noting it, so `texture_new()`, `texture_update()` and
+//!   `program_new()` bind the ones it thinks are bound again before returning.
+//!   Unit 1 is only used by `State::effect_input()`, which goes back to unit 0.
+//! - Deleting: `window` never deletes textures or programs, and would skip
+//!   binding a new one that reuses the name of the one it thinks is bound, so
+//!   `State::collect()` only deletes them at the end of a frame, and never the
+//!   one that's bound or in use.
+//!
+//! The rest of the state that's changed here (the framebuffer, viewport,
+//! scissor rectangle, blending, depth test and write, and front face) is set by
+//! `window` once, and never again, so `State` owns it, and `State::reset()`
+//! puts `window`'s settings back at the end of every frame, before the buffers
+//! are swapped.  The swap interval isn't set by `window` at all.
+
+use super::transform::coords;
+use super::{
+    Blend, GpuCmd, Rect, ShaderBuilder, ShaderError, ShaderStage, Transform,
+};
+use std::collections::HashMap;
+
+const GL_ALWAYS: u32 = 0x0207;
+const GL_CCW: u32 = 0x0901;
+const GL_CLAMP_TO_EDGE: i32 = 0x812F;
+const GL_COLOR_ATTACHMENT0: u32 = 0x8CE0;
+const GL_COLOR_BUFFER_BIT: u32 = 0x4000;
+const GL_COMPILE_STATUS: u32 = 0x8B81;
+const GL_CURRENT_PROGRAM: u32 = 0x8B8D;
+const GL_CW: u32 = 0x0900;
+const GL_DEPTH_ATTACHMENT: u32 = 0x8D00;
+const GL_DEPTH_BUFFER_BIT: u32 = 0x0100;
+const GL_DEPTH_COMPONENT16: u32 = 0x81A5;
+const GL_DST_ALPHA: u32 = 0x0304;
+const GL_DST_COLOR: u32 = 0x0306;
+const GL_FRAGMENT_SHADER: u32 = 0x8B30;
+const GL_FRAMEBUFFER: u32 = 0x8D40;
+const GL_INFO_LOG_LENGTH: u32 = 0x8B84;
+const GL_LESS: u32 = 0x0201;
+const GL_LINEAR: i32 = 0x2601;
+const GL_LINK_STATUS: u32 = 0x8B82;
+const GL_NEAREST: i32 = 0x2600;
+const GL_ONE: u32 = 1;
+const GL_ONE_MINUS_SRC_ALPHA: u32 = 0x0303;
+const GL_RENDERBUFFER: u32 = 0x8D41;
+const GL_RGBA: u32 = 0x1908;
+const GL_SCISSOR_TEST: u32 = 0x0C11;
+const GL_SRC_ALPHA: u32 = 0x0302;
+const GL_TEXTURE0: u32 = 0x84C0;
+const GL_TEXTURE_2D: u32 = 0x0DE1;
+const GL_TEXTURE_BINDING_2D: u32 = 0x8069;
+const GL_TEXTURE_MAG_FILTER: u32 = 0x2800;
+const GL_TEXTURE_MIN_FILTER: u32 = 0x2801;
+const GL_TEXTURE_WRAP_S: u32 = 0x2802;
+const GL_TEXTURE_WRAP_T: u32 = 0x2803;
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_VERTEX_SHADER: u32 = 0x8B31;
+const GL_VIEWPORT: u32 = 0x0BA2;
+const GL_ZERO: u32 = 0;
+
+// The same library that `window` links to.
+#[link(name = "GLESv2")]
+extern "C" {
+    fn glActiveTexture(texture: u32);
+    fn glAttachShader(program: u32, shader: u32);
+    fn glBindAttribLocation(program: u32, index: u32, name: *const u8);
+    fn glBindFramebuffer(target: u32, framebuffer: u32);
+    fn glBindRenderbuffer(target: u32, renderbuffer: u32);
+    fn glBindTexture(target: u32, texture: u32);
+    fn glBlendFuncSeparate(src_rgb: u32, dst_rgb: u32, src_a: u32, dst_a: u32);
+    fn glClear(mask: u32);
+    fn glClearColor(red: f32, green: f32, blue: f32, alpha: f32);
+    fn glCompileShader(shader: u32);
+    fn glCopyTexSubImage2D(
+        target: u32,
+        level: i32,
+        x_offset: i32,
+        y_offset: i32,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+    );
+    fn glCreateProgram() -> u32;
+    fn glCreateShader(kind: u32) -> u32;
+    fn glDeleteFramebuffers(n: i32, framebuffers: *const u32);
+    fn glDeleteProgram(program: u32);
+    fn glDeleteRenderbuffers(n: i32, renderbuffers: *const u32);
+    fn glDeleteShader(shader: u32);
+    fn glDeleteTextures(n: i32, textures: *const u32);
+    fn glDepthFunc(func: u32);
+    fn glDepthMask(flag: u8);
+    fn glDisable(cap: u32);
+    fn glEnable(cap: u32);
+    fn glFinish();
+    fn glFramebufferRenderbuffer(
+        target: u32,
+        attachment: u32,
+        renderbuffer_target: u32,
+        renderbuffer: u32,
+    );
+    fn glFramebufferTexture2D(
+        target: u32,
+        attachment: u32,
+        texture_target: u32,
+        texture: u32,
+        level: i32,
+    );
+    fn glFrontFace(mode: u32);
+    fn glGenFramebuffers(n: i32, framebuffers: *mut u32);
+    fn glGenRenderbuffers(n: i32, renderbuffers: *mut u32);
+    fn glGetAttachedShaders(
+        program: u32,
+        max_count: i32,
+        count: *mut i32,
+        shaders: *mut u32,
+    );
+    fn glGetIntegerv(pname: u32, data: *mut i32);
+    fn glGetProgramInfoLog(
+        program: u32,
+        size: i32,
+        length: *mut i32,
+        log: *mut u8,
+    );
+    fn glGetProgramiv(program: u32, pname: u32, param: *mut i32);
+    fn glGetShaderInfoLog(
+        shader: u32,
+        size: i32,
+        length: *mut i32,
+        log: *mut u8,
+    );
+    fn glGetShaderiv(shader: u32, pname: u32, param: *mut i32);
+    fn glGetUniformLocation(program: u32, name: *const u8) -> i32;
+    fn glLinkProgram(program: u32);
+    fn glReadPixels(
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        format: u32,
+        kind: u32,
+        pixels: *mut u8,
+    );
+    fn glRenderbufferStorage(target: u32, format: u32, width: i32, height: i32);
+    fn glScissor(x: i32, y: i32, width: i32, height: i32);
+    fn glShaderSource(
+        shader: u32,
+        count: i32,
+        string: *const *const u8,
+        length: *const i32,
+    );
+    fn glTexParameteri(target: u32, pname: u32, param: i32);
+    fn glUniform1i(location: i32, value: i32);
+    fn glUseProgram(program: u32);
+    fn glViewport(x: i32, y: i32, width: i32, height: i32);
+}
+
+// Also the same library that `window` links to.
+#[link(name = "EGL")]
+extern "C" {
+    fn eglGetCurrentDisplay() -> *mut std::ffi::c_void;
+    fn eglSwapInterval(display: *mut std::ffi::c_void, interval: i32) -> u32;
+}
+
+// Wait for the GPU to finish drawing.
+pub(super) fn finish() {
+    unsafe { glFinish() };
+}
+
+// Select the texture unit that textures are bound to.
+fn active_texture(unit: u32) {
+    unsafe { glActiveTexture(GL_TEXTURE0 + unit) };
+}
+
+// Copy the window into the bound texture, which must be at least as big, and
+// sample it smoothly without mipmaps (which aren't updated by the copy).
+fn copy_frame(width: i32, height: i32) {
+    unsafe {
+        glCopyTexSubImage2D(GL_TEXTURE_2D, 0, 0, 0, 0, 0, width, height);
+        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MIN_FILTER, GL_LINEAR);
+        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MAG_FILTER, GL_LINEAR);
+        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_S, GL_CLAMP_TO_EDGE);
+        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_T, GL_CLAMP_TO_EDGE);
+    }
+}
+
+// A texture created by `window`, with its OpenGL name for deleting it, and
+// its size in pixels.
+pub(super) struct Texture {
+    pub(super) raster: window::RasterId,
+    name: u32,
+    pub(super) width: u32,
+    pub(super) height: u32,
+}
+
+// A shader program created by `window`, with its OpenGL name for deleting it.
+// The builder and the tint are kept for starting recordings.
+pub(super) struct Program {
+    pub(super) shader: window::Shader,
+    name: u32,
+    pub(super) builder: ShaderBuilder,
+    pub(super) tint: [f32; 4],
+}
+
+// Create a texture.  `window` leaves the new texture bound without noting it,
+// so the texture that it thinks is bound is bound again.
+pub(super) fn texture_new(
+    window: &mut window::Window,
+    pixels: &[u8],
+    width: u32,
+    height: u32,
+) -> Texture {
+    let bound = get(GL_TEXTURE_BINDING_2D);
+    let raster = window.graphic(pixels, width as usize, height as usize);
+    let name = get(GL_TEXTURE_BINDING_2D);
+    unsafe { glBindTexture(GL_TEXTURE_2D, bound) };
+    Texture {
+        raster,
+        name,
+        width,
+        height,
+    }
+}
+
+// Change the pixels of a texture, through the copy that `window` keeps of
+// them, binding the texture `window` thinks is bound again afterwards.
+pub(super) fn texture_update(
+    window: &mut window::Window,
+    texture: &mut Texture,
+    update: &mut dyn FnMut(&mut [u8]),
+) {
+    let bound = get(GL_TEXTURE_BINDING_2D);
+    window.update_graphic(&mut texture.raster, &mut |buffer, _| update(buffer));
+    unsafe { glBindTexture(GL_TEXTURE_2D, bound) };
+}
+
+// Create a shader program.  `window` starts using the new program without
+// noting it, so the program that it thinks is in use is used again.
+pub(super) fn program_new(
+    window: &mut window::Window,
+    builder: ShaderBuilder,
+) -> Program {
+    let current = get(GL_CURRENT_PROGRAM);
+    let shader = builder.create(window);
+    let name = get(GL_CURRENT_PROGRAM);
+    unsafe { glUseProgram(current) };
+    Program {
+        shader,
+        name,
+        builder,
+        // Uniforms start out zeroed.
+        tint: [0.0; 4],
+    }
+}
+
+// Read the pixels of the framebuffer that's bound, bottom row first.
+fn read_pixels(width: i32, height: i32) -> Vec<u8> {
+    let mut pixels =
+        vec![0; width.max(0) as usize * height.max(0) as usize * 4];
+    unsafe {
+        glReadPixels(
+            0,
+            0,
+            width,
+            height,
+            GL_RGBA,
+            GL_UNSIGNED_BYTE,
+            pixels.as_mut_ptr(),
+        )
+    };
+    pixels
+}
+
+fn get(name: u32) -> u32 {
+    let mut value = 0;
+    unsafe { glGetIntegerv(name, &mut value) };
+    value as u32
+}
+
+// Point a sampler uniform (a nul-terminated name) of the shader program in use
+// at a texture unit, if the program has it.
+fn set_sampler(name: &[u8], unit: u32) {
+    let mut program = 0;
+    unsafe {
+        glGetIntegerv(GL_CURRENT_PROGRAM, &mut program);
+        let location = glGetUniformLocation(program as u32, name.as_ptr());
+        if location >= 0 {
+            glUniform1i(location, unit as i32);
+        }
+    }
+}
+
+// Compile and link a shader program the same way `window` does (which panics
+// if it fails), to get the errors.  The program is deleted afterwards, leaving
+// the program in use alone.
+pub(super) fn check(builder: &ShaderBuilder) -> Result<(), ShaderError> {
+    let vertex = compile(GL_VERTEX_SHADER, &builder.vertex);
+    let fragment = compile(GL_FRAGMENT_SHADER, &builder.fragment);
+    let (vertex, fragment) = match (vertex, fragment) {
+        (Ok(vertex), Ok(fragment)) => (vertex, fragment),
+        (vertex, fragment) => {
+            let mut error = None;
+            for shader in [fragment, vertex] {
+                match shader {
+                    Ok(shader) => unsafe { glDeleteShader(shader) },
+                    Err(e) => error = Some(e),
+                }
+            }
+            return Err(error.unwrap());
+        }
+    };
+    unsafe {
+        let program = glCreateProgram();
+        glAttachShader(program, fragment);
+        glAttachShader(program, vertex);
+        // Same attribute locations as `window`.
+        glBindAttribLocation(program, 0, b"pos\0".as_ptr());
+        if builder.graphic {
+            glBindAttribLocation(program, 1, b"texpos\0".as_ptr());
+        }
+        if builder.gradient {
+            glBindAttribLocation(program, 2, b"col\0".as_ptr());
+        }
+        glLinkProgram(program);
+        let mut status = 0;
+        glGetProgramiv(program, GL_LINK_STATUS, &mut status);
+        let result = if status == 0 {
+            let mut length = 0;
+            glGetProgramiv(program, GL_INFO_LOG_LENGTH, &mut length);
+            let mut log = vec![0; length.max(1) as usize];
+            glGetProgramInfoLog(
+                program,
+                log.len() as i32,
+                &mut length,
+                log.as_mut_ptr(),
+            );
+            log.truncate(length as usize);
+            Err(String::from_utf8_lossy(&log).into_owned())
+        } else {
+            // `window` requires these uniforms (unless they're unused).
+            let uniform =
+                |name: &[u8]| glGetUniformLocation(program, name.as_ptr()) >= 0;
+            if !uniform(b"cam\0") {
+                Err("no active `uniform mat4 cam;`".to_string())
+            } else if builder.tint && !uniform(b"tint\0") {
+                Err("no active `uniform vec4 tint;`".to_string())
+            } else {
+                Ok(())
+            }
+        };
+        glDeleteProgram(program);
+        glDeleteShader(vertex);
+        glDeleteShader(fragment);
+        result.map_err(|log| ShaderError {
+            stage: ShaderStage::Link,
+            log,
+        })
+    }
+}
+
+// Compile one stage of a shader program.
+fn compile(kind: u32, source: &str) -> Result<u32, ShaderError> {
+    unsafe {
+        let shader = glCreateShader(kind);
+        let length = source.len() as i32;
+        glShaderSource(shader, 1, &source.as_ptr(), &length);
+        glCompileShader(shader);
+        let mut status = 0;
+        glGetShaderiv(shader, GL_COMPILE_STATUS, &mut status);
+        if status != 0 {
+            return Ok(shader);
+        }
+        let mut length = 0;
+        glGetShaderiv(shader, GL_INFO_LOG_LENGTH, &mut length);
+        let mut log = vec![0; length.max(1) as usize];
+        glGetShaderInfoLog(
+            shader,
+            log.len() as i32,
+            &mut length,
+            log.as_mut_ptr(),
+        );
+        log.truncate(length as usize);
+        glDeleteShader(shader);
+        Err(ShaderError {
+            stage: if kind == GL_VERTEX_SHADER {
+                ShaderStage::Vertex
+            } else {
+                ShaderStage::Fragment
+            },
+            log: String::from_utf8_lossy(&log).into_owned(),
+        })
+    }
+}
+
+// Camera, viewport, scissor rectangle, blending and depth state of the frame
+// or a render target.
+#[derive(Copy, Clone)]
+struct Surface {
+    // Camera set by the user, before the viewport is applied.
+    camera: Transform,
+    viewport: Option<Rect>,
+    scissor: Option<Rect>,
+    blend: Blend,
+    depth_test: bool,
+    depth_write: bool,
+}
+
+impl Surface {
+    fn new() -> Self {
+        Surface {
+            camera: Transform::new(),
+            viewport: None,
+            scissor: None,
+            blend: Blend::Alpha,
+            depth_test: true,
+            depth_write: true,
+        }
+    }
+}
+
+// The framebuffer a render target's texture is drawn into, with its depth
+// buffer, and the target's state while it isn't being drawn on.
+struct Target {
+    framebuffer: u32,
+    depth: u32,
+    width: i32,
+    height: i32,
+    surface: Surface,
+}
+
+// State of the window or render target being drawn on, and of the others.
+pub(super) struct State {
+    surface: Surface,
+    // Size of the window in pixels, once it's needed for a frame.
+    size: Option<(i32, i32)>,
+    // Render target being drawn on instead of the window.
+    target: Option<u32>,
+    // Render targets, keyed by the ID of their texture.
+    targets: HashMap<u32, Target>,
+    // Color `window` clears the window to.
+    background: [f32; 3],
+    // Whether swapping buffers waits for the display to refresh, once set.
+    vsync: Option<bool>,
+    // Textures and programs that have been freed, but not deleted yet.
+    textures: Vec<u32>,
+    programs: Vec<u32>,
+}
+
+impl State {
+    pub(super) fn new() -> Self {
+        State {
+            surface: Surface::new(),
+            size: None,
+            target: None,
+            targets: HashMap::new(),
+            // Same default as `window`.
+            background: [0.0, 0.0, 1.0],
+            vsync: None,
+            textures: Vec::new(),
+            programs: Vec::new(),
+        }
+    }
+
+    // Delete a texture, once it's not bound.
+    pub(super) fn delete_texture(&mut self, texture: Texture) {
+        self.textures.push(texture.name);
+    }
+
+    // Delete a shader program, once it's not in use.
+    pub(super) fn delete_program(&mut self, program: Program) {
+        self.programs.push(program.name);
+    }
+
+    // Delete the textures and programs that have been freed, at the end of a
+    // frame.  `window` skips binding a texture or program that it thinks is
+    // already bound, so the one that's bound is kept until it isn't (or a new
+    // one with the same name would never be bound).
+    pub(super) fn collect(&mut self) {
+        let bound = get(GL_TEXTURE_BINDING_2D);
+        self.textures.retain(|texture| {
+            if *texture == bound {
+                return true;
+            }
+            unsafe { glDeleteTextures(1, texture) };
+            false
+        });
+        let current = get(GL_CURRENT_PROGRAM);
+        self.programs.retain(|program| {
+            if *program == current {
+                return true;
+            }
+            // Delete the shaders `window` compiled along with the program.
+            let mut shaders = [0; 2];
+            let mut count = 0;
+            unsafe {
+                glGetAttachedShaders(
+                    *program,
+                    2,
+                    &mut count,
+                    shaders.as_mut_ptr(),
+                );
+                glDeleteProgram(*program);
+                for shader in shaders.iter().take(count as usize) {
+                    glDeleteShader(*shader);
+                }
+            }
+            false
+        });
+    }
+
+    // Make a texture into a render target, with a framebuffer to draw into it
+    // and a depth buffer, both cleared.
+    pub(super) fn target_new(&mut self, id: u32, texture: &Texture) {
+        let mut target = Target {
+            framebuffer: 0,
+            depth: 0,
+            width: texture.width as i32,
+            height: texture.height as i32,
+            surface: Surface::new(),
+        };
+        unsafe {
+            // Nothing drawn into the texture is mipmapped.
+            let bound = get(GL_TEXTURE_BINDING_2D);
+            glBindTexture(GL_TEXTURE_2D, texture.name);
+            glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MIN_FILTER, GL_NEAREST);
+            glBindTexture(GL_TEXTURE_2D, bound);
+
+            glGenRenderbuffers(1, &mut target.depth);
+            glBindRenderbuffer(GL_RENDERBUFFER, target.depth);
+            glRenderbufferStorage(
+                GL_RENDERBUFFER,
+                GL_DEPTH_COMPONENT16,
+                target.width,
+                target.height,
+            );
+            glGenFramebuffers(1, &mut target.framebuffer);
+            glBindFramebuffer(GL_FRAMEBUFFER, target.framebuffer);
+            glFramebufferTexture2D(
+                GL_FRAMEBUFFER,
+                GL_COLOR_ATTACHMENT0,
+                GL_TEXTURE_2D,
+                texture.name,
+                0,
+            );
+            glFramebufferRenderbuffer(
+                GL_FRAMEBUFFER,
+                GL_DEPTH_ATTACHMENT,
+                GL_RENDERBUFFER,
+                target.depth,
+            );
+            self.clear_buffers([0.0; 4]);
+            glBindFramebuffer(GL_FRAMEBUFFER, 0);
+        }
+        self.targets.insert(id, target);
+    }
+
+    // Delete a render target's framebuffer and depth buffer, when its texture
+    // is freed.
+    pub(super) fn target_delete(&mut self, id: u32) {
+        if let Some(target) = self.targets.remove(&id) {
+            unsafe {
+                glDeleteFramebuffers(1, &target.framebuffer);
+                glDeleteRenderbuffers(1, &target.depth);
+            }
+        }
+    }
+
+    // Start drawing on a render target instead of the window, returning false
+    // if there's no render target with that ID.
+    pub(super) fn begin_target(
+        &mut self,
+        window: &mut window::Window,
+        id: u32,
+    ) -> bool {
+        // Get the window's size while its viewport is still set.
+        self.size();
+        let target = if let Some(target) = self.targets.get_mut(&id) {
+            target
+        } else {
+            return false;
+        };
+        std::mem::swap(&mut self.surface, &mut target.surface);
+        unsafe {
+            glBindFramebuffer(GL_FRAMEBUFFER, target.framebuffer);
+            // Render targets are drawn upside down (see `update_camera()`),
+            // which turns the triangles around.
+            glFrontFace(GL_CW);
+        }
+        self.target = Some(id);
+        self.apply(window);
+        true
+    }
+
+    // Go back to drawing on the window.
+    pub(super) fn end_target(&mut self, window: &mut window::Window) {
+        if let Some(id) = self.target.take() {
+            let target = self.targets.get_mut(&id).unwrap();
+            std::mem::swap(&mut self.surface, &mut target.surface);
+            unsafe {
+                glBindFramebuffer(GL_FRAMEBUFFER, 0);
+                glFrontFace(GL_CCW);
+            }
+            self.apply(window);
+        }
+    }
+
+    // Read the pixels of a render target, top row first, binding the
+    // framebuffer being drawn on again afterwards.
+    pub(super) fn target_pixels(&self, id: u32) -> Option<(u32, u32, Vec<u8>)> {
+        let target = self.targets.get(&id)?;
+        let drawing = self
+            .target
+            .and_then(|id| self.targets.get(&id))
+            .map_or(0, |target| target.framebuffer);
+        unsafe { glBindFramebuffer(GL_FRAMEBUFFER, target.framebuffer) };
+        let pixels = read_pixels(target.width, target.height);
+        unsafe { glBindFramebuffer(GL_FRAMEBUFFER, drawing) };
+        Some((target.width as u32, target.height as u32, pixels))
+    }
+
+    // Read the pixels of the window, top row first, at the end of a frame.
+    pub(super) fn window_pixels(&mut self) -> (u32, u32, Vec<u8>) {
+        let (width, height) = self.size();
+        let pixels = read_pixels(width, height);
+        let row = width.max(0) as usize * 4;
+        let mut flipped = Vec::with_capacity(pixels.len());
+        if row != 0 {
+            for line in pixels.chunks_exact(row).rev() {
+                flipped.extend_from_slice(line);
+            }
+        }
+        (width.max(0) as u32, height.max(0) as u32, flipped)
+    }
+
+    // Commands that give a renderer the same state as the GPU between frames:
+    // the background, the camera, and the state of each render target.
+    pub(super) fn commands(&self) -> Vec<GpuCmd> {
+        let [r, g, b] = self.background;
+        let mut cmds = vec![
+            GpuCmd::Background(r, g, b),
+            GpuCmd::SetCamera(self.surface.camera),
+        ];
+        for (id, target) in self.targets.iter() {
+            let surface = &target.surface;
+            for cmd in [
+                GpuCmd::SetCamera(surface.camera),
+                GpuCmd::SetViewport(surface.viewport),
+                GpuCmd::SetScissor(surface.scissor),
+                GpuCmd::SetBlend(surface.blend),
+                GpuCmd::SetDepthTest(surface.depth_test),
+                GpuCmd::SetDepthWrite(surface.depth_write),
+            ] {
+                cmds.push(GpuCmd::Target(*id, Box::new(cmd)));
+            }
+        }
+        cmds
+    }
+
+    // This isn't reset at the end of the frame, so it's only changed when it
+    // needs to be.
+    pub(super) fn set_vsync(&mut self, vsync: bool) {
+        if self.vsync != Some(vsync) {
+            self.vsync = Some(vsync);
+            unsafe { eglSwapInterval(eglGetCurrentDisplay(), vsync.into()) };
+        }
+    }
+
+    pub(super) fn set_background(
+        &mut self,
+        window: &mut window::Window,
+        background: [f32; 3],
+    ) {
+        self.background = background;
+        let [r, g, b] = background;
+        window.background(r, g, b);
+    }
+
+    pub(super) fn set_camera(
+        &mut self,
+        window: &mut window::Window,
+        camera: Transform,
+    ) {
+        self.surface.camera = camera;
+        self.update_camera(window);
+    }
+
+    pub(super) fn set_viewport(
+        &mut self,
+        window: &mut window::Window,
+        viewport: Option<Rect>,
+    ) {
+        let (width, height) = self.size();
+        self.surface.viewport = viewport;
+        let (x, y, w, h) = self.pixels(viewport, width, height);
+        unsafe { glViewport(x, y, w, h) };
+        self.update_camera(window);
+    }
+
+    pub(super) fn set_scissor(&mut self, scissor: Option<Rect>) {
+        let (width, height) = self.size();
+        self.surface.scissor = scissor;
+        if scissor.is_some() {
+            let (x, y, w, h) = self.pixels(scissor, width, height);
+            unsafe {
+                glEnable(GL_SCISSOR_TEST);
+                glScissor(x, y, w, h);
+            }
+        } else {
+            unsafe { glDisable(GL_SCISSOR_TEST) };
+        }
+    }
+
+    pub(super) fn set_blend(&mut self, blend: Blend) {
+        self.surface.blend = blend;
+        // Alpha is blended the same way as `window` does for `Blend::Alpha`.
+        let [src_rgb, dst_rgb, src_a, dst_a] = match blend {
+            Blend::Alpha => [
+                GL_SRC_ALPHA,
+                GL_ONE_MINUS_SRC_ALPHA,
+                GL_SRC_ALPHA,
+                GL_DST_ALPHA,
+            ],
+            Blend::Premultiplied => [
+                GL_ONE,
+                GL_ONE_MINUS_SRC_ALPHA,
+                GL_ONE,
+                GL_ONE_MINUS_SRC_ALPHA,
+            ],
+            Blend::Additive => [GL_SRC_ALPHA, GL_ONE, GL_ZERO, GL_ONE],
+            Blend::Multiply => [GL_DST_COLOR, GL_ZERO, GL_ZERO, GL_ONE],
+            Blend::Opaque => [GL_ONE, GL_ZERO, GL_ONE, GL_ZERO],
+        };
+        unsafe { glBlendFuncSeparate(src_rgb, dst_rgb, src_a, dst_a) };
+    }
+
+    // `window` turns the depth test on and off for each shader, so instead of
+    // disabling it, make it always pass.
+    pub(super) fn set_depth_test(&mut self, test: bool) {
+        self.surface.depth_test = test;
+        unsafe { glDepthFunc(if test { GL_LESS } else { GL_ALWAYS }) };
+    }
+
+    pub(super) fn set_depth_write(&mut self, write: bool) {
+        self.surface.depth_write = write;
+        unsafe { glDepthMask(write.into()) };
+    }
+
+    // Go back to the defaults, and clear what's being drawn on (and its depth
+    // buffer) to a color.
+    pub(super) fn clear(
+        &mut self,
+        window: &mut window::Window,
+        color: [f32; 4],
+    ) {
+        self.defaults(window);
+        self.clear_buffers(color);
+    }
+
+    // Clear what's being drawn on, regardless of the scissor rectangle and
+    // depth writes, and then clear to the background again.
+    fn clear_buffers(&self, color: [f32; 4]) {
+        let [r, g, b, a] = color;
+        let [red, green, blue] = self.background;
+        unsafe {
+            glDisable(GL_SCISSOR_TEST);
+            glDepthMask(1);
+            glClearColor(r, g, b, a);
+            glClear(GL_COLOR_BUFFER_BIT | GL_DEPTH_BUFFER_BIT);
+            glClearColor(red, green, blue, 1.0);
+            glDepthMask(self.surface.depth_write.into());
+            if self.surface.scissor.is_some() {
+                glEnable(GL_SCISSOR_TEST);
+            }
+        }
+    }
+
+    // Go back to the defaults (and drawing on the whole window), at the end of
+    // a frame.
+    pub(super) fn reset(&mut self, window: &mut window::Window) {
+        self.defaults(window);
+        self.size = None;
+    }
+
+    // Go back to the defaults for what's being drawn on.
+    fn defaults(&mut self, window: &mut window::Window) {
+        if self.surface.viewport.is_some() {
+            self.set_viewport(window, None);
+        }
+        if self.surface.scissor.is_some() {
+            self.set_scissor(None);
+        }
+        if self.surface.blend != Blend::Alpha {
+            self.set_blend(Blend::Alpha);
+        }
+        if !self.surface.depth_test {
+            self.set_depth_test(true);
+        }
+        if !self.surface.depth_write {
+            self.set_depth_write(true);
+        }
+    }
+
+    // Set all of the state, after switching between the window and a render
+    // target.
+    fn apply(&mut self, window: &mut window::Window) {
+        let surface = self.surface;
+        self.set_viewport(window, surface.viewport);
+        self.set_scissor(surface.scissor);
+        self.set_blend(surface.blend);
+        self.set_depth_test(surface.depth_test);
+        self.set_depth_write(surface.depth_write);
+    }
+
+    // Draw over the whole window in its own coordinates (0 to 1 both ways),
+    // replacing what's there, for post-processing after `reset()`.
+    pub(super) fn begin_effects(&mut self, window: &mut window::Window) {
+        let aspect = window.aspect();
+        window.camera(Rect::new(0.0, 0.0, 1.0, 1.0).camera(aspect).into());
+        self.set_blend(Blend::Opaque);
+        self.set_depth_test(false);
+    }
+
+    // Copy the window into `frame` for an effect's shader to sample, with
+    // `lut` on the second texture unit as the `lut` sampler.  `window` only
+    // binds a texture by drawing with it, so each is drawn with `empty`.
+    pub(super) fn effect_input(
+        &mut self,
+        window: &mut window::Window,
+        shader: &window::Shader,
+        empty: &window::Group,
+        frame: &Texture,
+        lut: Option<&Texture>,
+    ) {
+        let (width, height) = self.size();
+        window.draw_graphic(shader, empty, &frame.raster);
+        copy_frame(width, height);
+        if let Some(lut) = lut {
+            // The frame is bound, so `window` binds the lookup table (on the
+            // second unit), and then the frame again.
+            active_texture(1);
+            window.draw_graphic(shader, empty, &lut.raster);
+            active_texture(0);
+            set_sampler(b"lut\0", 1);
+        }
+    }
+
+    // Go back to the camera and defaults after post-processing.
+    pub(super) fn end_effects(&mut self, window: &mut window::Window) {
+        self.update_camera(window);
+        self.reset(window);
+    }
+
+    // Get the size in pixels of the render target being drawn on, or of the
+    // window from the viewport `window` sets when it's resized (before it's
+    // changed).
+    pub(super) fn size(&mut self) -> (i32, i32) {
+        if let Some(target) = self.target.and_then(|id| self.targets.get(&id)) {
+            return (target.width, target.height);
+        }
+        *self.size.get_or_insert_with(|| {
+            let mut viewport = [0; 4];
+            unsafe { glGetIntegerv(GL_VIEWPORT, viewport.as_mut_ptr()) };
+            (viewport[2], viewport[3])
+        })
+    }
+
+    fn update_camera(&mut self, window: &mut window::Window) {
+        let aspect = window.aspect();
+        let (width, height) = self.size();
+        let height = if self.target.is_some() {
+            height as f32 / width as f32
+        } else {
+            aspect
+        };
+        let camera = if let Some(viewport) = self.surface.viewport {
+            self.surface.camera * viewport.camera(height)
+        } else {
+            self.surface.camera
+        };
+        if self.target.is_none() {
+            window.camera(camera.into());
+            return;
+        }
+        // `window` draws in its own coordinates, so undo them and use the
+        // render target's instead.  Textures start with the top row, but
+        // framebuffers with the bottom row, so it's also drawn upside down.
+        let window_coords = coords(aspect).inverse().unwrap_or_default();
+        window.camera(
+            (window_coords
+                * coords(height)
+                * camera
+                * Transform::new().scale(1.0, -aspect / height, 1.0))
+            .into(),
+        );
+    }
+
+    // Convert a rectangle to OpenGL's pixel coordinates (Y up), or to the
+    // pixel coordinates of an upside down render target (Y down).
+    fn pixels(
+        &self,
+        rect: Option<Rect>,
+        width: i32,
+        height: i32,
+    ) -> (i32, i32, i32, i32) {
+        if let Some(rect) = rect {
+            let scale = width as f32;
+            let x = (rect.x * scale).round() as i32;
+            let top = (rect.y * scale).round() as i32;
+            let w = (rect.width * scale).round() as i32;
+            let h = (rect.height * scale).round() as i32;
+            let y = if self.target.is_some() {
+                top
+            } else {
+                height - top - h
+            };
+            (x, y, w.max(0), h.max(0))
+        } else {
+            (0, 0, width, height)
+        }
+    }
+}
@@ -0,0 +1,319 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Shader source, and compiling it at runtime.
+
+use super::{Shader, VertexFormat};
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// Load a shader generated by `res` in the build script, as a
+/// [`ShaderBuilder`](graphics/struct.ShaderBuilder.html).
+#[macro_export]
+macro_rules! shader {
+    ($shadername: literal) => {{
+        // The generated file is a `window::ShaderBuilder` literal.
+        #[allow(unused_imports)]
+        use $crate::graphics::ResShaderBuilder as ShaderBuilder;
+        $crate::graphics::ShaderBuilder::from(include!(concat!(
+            env!("OUT_DIR"),
+            "/res/",
+            $shadername,
+            ".rs"
+        )))
+    }};
+}
+
+/// Which part of a shader program a [`ShaderError`](struct.ShaderError.html)
+/// is about.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShaderStage {
+    /// The vertex shader.
+    Vertex,
+    /// The fragment shader.
+    Fragment,
+    /// Linking the vertex and fragment shaders together.
+    Link,
+}
+
+/// Error from compiling a shader, from
+/// [`Shader::compile()`](struct.Shader.html#method.compile).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderError {
+    /// Which stage failed.
+    pub stage: ShaderStage,
+    /// What the GLSL compiler (or linker) said, as it said it.
+    pub log: String,
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stage = match self.stage {
+            ShaderStage::Vertex => "compiling vertex shader",
+            ShaderStage::Fragment => "compiling fragment shader",
+            ShaderStage::Link => "linking shader",
+        };
+        write!(f, "{}: {}", stage, self.log.trim_end())
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+/// GLSL source for a shader, and the inputs that it takes.
+///
+/// Shaders generated by `res` are loaded with the
+/// [`shader!()`](macro.shader.html) macro, and other source can be built at
+/// runtime with [`new()`](#method.new) or [`load()`](#method.load).
+///
+/// The source is written for OpenGL ES 2.0 (GLSL ES 1.00), the same as the
+/// shaders generated by `res`.  The vertex shader gets these inputs:
+///
+///  - `uniform mat4 cam;` - Camera and projection (always required)
+///  - `attribute vec2 pos;` - Position (`vec3` with `depth()`)
+///  - `attribute vec2 texpos;` - Texture coordinates (with `graphic()`)
+///  - `attribute vec3 col;` - Vertex color (with `gradient()`, `vec4` with
+///    `blend()`)
+///
+/// With `tint()`, either stage must read `uniform vec4 tint;`, and with
+/// `graphic()` the fragment shader can read `uniform sampler2D tex;`.  The
+/// software renderer uses the same inputs to draw, but ignores the source.
+#[derive(Clone, Debug)]
+pub struct ShaderBuilder {
+    pub(super) vertex: String,
+    pub(super) fragment: String,
+    pub(super) tint: bool,
+    pub(super) gradient: bool,
+    pub(super) graphic: bool,
+    pub(super) depth: bool,
+    pub(super) blend: bool,
+}
+
+impl ShaderBuilder {
+    /// Create a shader from the text of a vertex and fragment shader.
+    pub fn new(vertex: &str, fragment: &str) -> Self {
+        ShaderBuilder {
+            vertex: vertex.to_string(),
+            fragment: fragment.to_string(),
+            tint: false,
+            gradient: false,
+            graphic: false,
+            depth: false,
+            blend: false,
+        }
+    }
+
+    /// Load a shader from a vertex and fragment shader file.
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(
+        vertex: P,
+        fragment: Q,
+    ) -> std::io::Result<Self> {
+        Ok(Self::new(
+            &std::fs::read_to_string(vertex)?,
+            &std::fs::read_to_string(fragment)?,
+        ))
+    }
+
+    /// Shapes drawn with the shader have a tint.
+    pub fn tint(mut self) -> Self {
+        self.tint = true;
+        self
+    }
+
+    /// Vertices have colors.
+    pub fn gradient(mut self) -> Self {
+        self.gradient = true;
+        self
+    }
+
+    /// A texture is drawn with the shader.
+    pub fn graphic(mut self) -> Self {
+        self.graphic = true;
+        self
+    }
+
+    /// Vertices have a Z coordinate, and are depth tested.
+    pub fn depth(mut self) -> Self {
+        self.depth = true;
+        self
+    }
+
+    /// Blend with transparency.
+    pub fn blend(mut self) -> Self {
+        self.blend = true;
+        self
+    }
+
+    // How the vertices of the shader's shapes are laid out.
+    pub(super) fn format(&self) -> VertexFormat {
+        VertexFormat::new(self.depth, self.graphic, self.gradient, self.blend)
+    }
+
+    // Create the shader in `window`.
+    pub(super) fn create(&self, window: &mut window::Window) -> window::Shader {
+        window.shader_new(self.clone().into())
+    }
+}
+
+/// Convert to `window`'s shader builder, for creating the shader with
+/// `window` directly.
+///
+/// `window` takes the source as `&'static str`, so the source is leaked: this
+/// costs the size of the source each time, and is meant to be done once per
+/// shader program.
+impl From<ShaderBuilder> for window::ShaderBuilder {
+    fn from(builder: ShaderBuilder) -> Self {
+        let source = |text: String| -> &'static str {
+            Box::leak(format!("{}\0", text).into_boxed_str())
+        };
+        window::ShaderBuilder {
+            tint: builder.tint,
+            gradient: builder.gradient,
+            graphic: builder.graphic,
+            depth: builder.depth,
+            blend: builder.blend,
+            opengl_frag: source(builder.fragment),
+            opengl_vert: source(builder.vertex),
+        }
+    }
+}
+
+/// Convert from `window`'s shader builder, which is what `res` generates (see
+/// [`shader!()`](../macro.shader.html)).
+impl From<window::ShaderBuilder> for ShaderBuilder {
+    fn from(builder: window::ShaderBuilder) -> Self {
+        let source = |text: &str| text.trim_end_matches('\0').to_string();
+        ShaderBuilder {
+            vertex: source(builder.opengl_vert),
+            fragment: source(builder.opengl_frag),
+            tint: builder.tint,
+            gradient: builder.gradient,
+            graphic: builder.graphic,
+            depth: builder.depth,
+            blend: builder.blend,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CompileInternal {
+    result: Option<Result<(), ShaderError>>,
+    waker: Option<Waker>,
+}
+
+impl CompileInternal {
+    // Report how compiling went, on the graphics thread.
+    pub(super) fn finish(&mut self, result: Result<(), ShaderError>) {
+        self.result = Some(result);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A shader being compiled, from
+/// [`Shader::compile()`](struct.Shader.html#method.compile).
+///
+/// This is a future that resolves to the `Shader`, or the compiler's errors,
+/// once the graphics thread has compiled it.
+pub struct ShaderCompile {
+    pub(super) shader: Option<Shader>,
+    pub(super) internal: Arc<Mutex<CompileInternal>>,
+}
+
+impl Future for ShaderCompile {
+    type Output = Result<Shader, ShaderError>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        let result = {
+            let mut lock = self.internal.lock().unwrap();
+            if lock.result.is_none() {
+                lock.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            lock.result.take().unwrap()
+        };
+        // A shader that failed is dropped, freeing its ID.
+        let shader = self.shader.take().expect("Polled after completion");
+        Poll::Ready(result.map(|()| shader))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_res() {
+        let builder = ShaderBuilder::from(window::ShaderBuilder {
+            tint: true,
+            gradient: false,
+            graphic: true,
+            depth: false,
+            blend: true,
+            opengl_frag: "void main() {}\0",
+            opengl_vert: "void main() { gl_Position = vec4(0.0); }\0",
+        });
+        assert_eq!(builder.vertex, "void main() { gl_Position = vec4(0.0); }");
+        assert_eq!(builder.fragment, "void main() {}");
+        assert!(builder.tint && builder.graphic && builder.blend);
+        assert!(!builder.gradient && !builder.depth);
+    }
+
+    #[test]
+    fn to_window() {
+        let builder = ShaderBuilder::new("void main() {}", "").depth().tint();
+        let converted = window::ShaderBuilder::from(builder);
+        assert_eq!(converted.opengl_vert, "void main() {}\0");
+        assert_eq!(converted.opengl_frag, "\0");
+        assert!(converted.depth && converted.tint);
+        assert!(!converted.gradient && !converted.graphic && !converted.blend);
+        let builder = ShaderBuilder::from(converted);
+        assert_eq!(
+            (builder.vertex.as_str(), builder.fragment.as_str()),
+            ("void main() {}", "")
+        );
+    }
+
+    #[test]
+    fn format() {
+        let format = ShaderBuilder::new("", "").format();
+        assert_eq!((format.dimensions, format.components), (2, 0));
+        assert_eq!(format.stride(), 2);
+        let format = ShaderBuilder::new("", "").depth().gradient().format();
+        assert_eq!((format.dimensions, format.components), (3, 3));
+        assert_eq!(format.stride(), 6);
+        let builder = ShaderBuilder::new("", "").graphic().gradient().blend();
+        assert_eq!(builder.format().stride(), 8);
+    }
+
+    #[test]
+    fn error_message() {
+        let error = ShaderError {
+            stage: ShaderStage::Fragment,
+            log: "0:1(1): error: syntax error\n".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "compiling fragment shader: 0:1(1): error: syntax error"
+        );
+        let error = ShaderError {
+            stage: ShaderStage::Link,
+            log: String::new(),
+        };
+        assert_eq!(error.to_string(), "linking shader: ");
+    }
+}
@@ -0,0 +1,360 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Minimal JSON reader, for the asset formats that use it.
+
+// How deep arrays and objects can be nested, so that reading doesn't overflow
+// the stack.
+const MAX_DEPTH: usize = 128;
+
+// A JSON value.
+#[derive(Clone, Debug, PartialEq)]
+pub(super) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    // Parse a JSON document, returning an error message if it's invalid.
+    pub(super) fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            index: 0,
+            depth: 0,
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.index != parser.text.len() {
+            return Err(parser.error("unexpected data after JSON value"));
+        }
+        Ok(value)
+    }
+
+    // Get a member of an object.
+    pub(super) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Json::Number(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_f32(&self) -> Option<f32> {
+        self.as_f64().map(|number| number as f32)
+    }
+
+    pub(super) fn as_usize(&self) -> Option<usize> {
+        self.as_f64()
+            .filter(|number| *number >= 0.0 && number.fract() == 0.0)
+            .map(|number| number as usize)
+    }
+
+    pub(super) fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Json::Bool(boolean) => Some(boolean),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    // Iterate over the items of an array (nothing if it's not an array).
+    pub(super) fn iter(&self) -> impl Iterator<Item = &Json> {
+        self.as_array().unwrap_or(&[]).iter()
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    index: usize,
+    // How many arrays and objects the parser is inside.
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.index.min(self.text.len())]
+            .iter()
+            .filter(|c| **c == b'\n')
+            .count()
+            + 1;
+        format!("{} (line {})", message, line)
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.index += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.index).cloned()
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        if self.text[self.index..].starts_with(word.as_bytes()) {
+            self.index += word.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", word)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.peek() {
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'{') => self.nested(Self::object),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of JSON")),
+        }
+    }
+
+    // Parse an array or object with `parse`, one level deeper.
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<Json, String>,
+    ) -> Result<Json, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("JSON is nested too deep"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.index += 1;
+        let mut array = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b']') {
+            self.index += 1;
+            return Ok(Json::Array(array));
+        }
+        loop {
+            array.push(self.value()?);
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.index += 1,
+                Some(b']') => {
+                    self.index += 1;
+                    return Ok(Json::Array(array));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.index += 1;
+        let mut members = Vec::new();
+        self.whitespace();
+        if self.peek() == Some(b'}') {
+            self.index += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a key"));
+            }
+            let key = self.string()?;
+            self.whitespace();
+            self.expect(":")?;
+            members.push((key, self.value()?));
+            self.whitespace();
+            match self.peek() {
+                Some(b',') => self.index += 1,
+                Some(b'}') => {
+                    self.index += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.index;
+        while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') =
+            self.peek()
+        {
+            self.index += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.index])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Json::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        // Skip the opening quote.
+        self.index += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unclosed string"))?;
+            self.index += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self
+                        .peek()
+                        .ok_or_else(|| self.error("unclosed string"))?;
+                    self.index += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(
+                        c.encode_utf8(&mut buffer).as_bytes(),
+                    );
+                }
+                c => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    // Parse the hex digits of a `\u` escape (and the low half of a surrogate
+    // pair, if there is one).
+    fn unicode(&mut self) -> Result<char, String> {
+        let high = self.hex()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            self.expect("\\u")?;
+            let low = self.hex()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("invalid escape"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or_else(|| self.error("invalid escape"))
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let digits = self
+            .text
+            .get(self.index..self.index + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid escape"))?;
+        self.index += 4;
+        Ok(digits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values() {
+        let json = Json::parse(
+            r#" {
+                "frames": [1, -2.5, 3e2],
+                "loop": true,
+                "tag": null,
+                "name": "a\"b\\c\n\u00e9\ud83d\ude00"
+            } "#,
+        )
+        .unwrap();
+        let frames: Vec<f64> = json
+            .get("frames")
+            .unwrap()
+            .iter()
+            .flat_map(Json::as_f64)
+            .collect();
+        assert_eq!(frames, [1.0, -2.5, 300.0]);
+        assert_eq!(json.get("loop").and_then(Json::as_bool), Some(true));
+        assert_eq!(json.get("tag"), Some(&Json::Null));
+        assert_eq!(
+            json.get("name").and_then(Json::as_str),
+            Some("a\"b\\c\né😀")
+        );
+        assert_eq!(json.get("missing"), None);
+        assert_eq!(Json::parse("[]"), Ok(Json::Array(Vec::new())));
+        assert_eq!(Json::parse("{}"), Ok(Json::Object(Vec::new())));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Json::Number(3.0).as_usize(), Some(3));
+        assert_eq!(Json::Number(3.5).as_usize(), None);
+        assert_eq!(Json::Number(-1.0).as_usize(), None);
+        assert_eq!(Json::Bool(true).as_f64(), None);
+        assert_eq!(Json::Null.iter().count(), 0);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text| Json::parse(text).unwrap_err();
+        assert_eq!(error("{\n\"a\": }"), "unexpected character (line 2)");
+        assert_eq!(error("[1, 2"), "expected `,` or `]` (line 1)");
+        assert_eq!(error("{\"a\" 1}"), "expected `:` (line 1)");
+        assert_eq!(error("{1: 2}"), "expected a key (line 1)");
+        assert_eq!(error("\"abc"), "unclosed string (line 1)");
+        assert_eq!(error("\"\\q\""), "invalid escape (line 1)");
+        assert_eq!(error("-"), "invalid number (line 1)");
+        assert_eq!(error("nul"), "expected `null` (line 1)");
+        assert_eq!(error("1 2"), "unexpected data after JSON value (line 1)");
+        assert_eq!(error(""), "unexpected end of JSON (line 1)");
+        // Surrogates that aren't in pairs.
+        assert_eq!(error("\"\\ud800\\u0041\""), "invalid escape (line 1)");
+        assert_eq!(error("\"\\udc00\""), "invalid escape (line 1)");
+        assert_eq!(error("\"\\ud800\""), "expected `\\u` (line 1)");
+        // Nesting that would overflow the stack.
+        let deep = "[".repeat(100_000);
+        assert_eq!(error(&deep), "JSON is nested too deep (line 1)");
+        let deep = "{\"a\":".repeat(MAX_DEPTH) + "1" + &"}".repeat(MAX_DEPTH);
+        assert!(Json::parse(&deep).is_ok());
+    }
+}
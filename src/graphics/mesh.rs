@@ -0,0 +1,857 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Loading meshes from Wavefront OBJ and glTF 2.0 files.
+
+use super::json::Json;
+use super::{Shader, ShapeBuilder, Transform};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+
+// Most elements an accessor without a buffer view (all zeros) can have, since
+// there's no data in the file to limit it.
+const MAX_ZEROS: usize = 1 << 20;
+
+/// Error from loading a mesh.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MeshError {
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl MeshError {
+    fn new(message: impl Into<String>) -> Self {
+        MeshError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+// A corner of a triangle.
+#[derive(Copy, Clone, Debug)]
+struct Vertex {
+    position: [f32; 3],
+    tex_coord: [f32; 2],
+    color: [f32; 4],
+}
+
+/// Triangles loaded from a 3D model file.
+///
+/// Models are converted from the Y-up coordinates that OBJ and glTF use to
+/// cala's Y-down coordinates (by turning them upside down, which keeps faces
+/// facing the same way).  Turn them into shapes with
+/// [`shape_builder()`](#method.shape_builder).
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    name: String,
+    // Triangle list.
+    vertices: Vec<Vertex>,
+}
+
+impl Mesh {
+    /// Load the objects (`o`) and groups (`g`) in a Wavefront OBJ file as
+    /// separate meshes.
+    ///
+    /// Faces with more than three sides are split into triangles.  Vertex
+    /// colors are read from the `v x y z r g b` extension; materials are
+    /// ignored.
+    pub fn load_obj<R: Read>(reader: R) -> Result<Vec<Mesh>, MeshError> {
+        let mut positions = Vec::new();
+        let mut colors = Vec::new();
+        let mut tex_coords = Vec::new();
+        let mut meshes = Vec::new();
+        let mut mesh = Mesh {
+            name: String::new(),
+            vertices: Vec::new(),
+        };
+        for (number, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line.map_err(|e| MeshError::new(e.to_string()))?;
+            let error = |message: &str| {
+                MeshError::new(format!("line {}: {}", number + 1, message))
+            };
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+            let words: Vec<&str> = words.collect();
+            let numbers = || -> Result<Vec<f32>, MeshError> {
+                words
+                    .iter()
+                    .map(|word| word.parse().map_err(|_| error("bad number")))
+                    .collect()
+            };
+            match keyword {
+                "v" => {
+                    let v = numbers()?;
+                    if v.len() < 3 {
+                        return Err(error("vertex needs 3 coordinates"));
+                    }
+                    positions.push(flip([v[0], v[1], v[2]]));
+                    colors.push(if v.len() >= 6 {
+                        [v[3], v[4], v[5], 1.0]
+                    } else {
+                        [1.0; 4]
+                    });
+                }
+                "vt" => {
+                    let vt = numbers()?;
+                    let u = vt.first().cloned().unwrap_or(0.0);
+                    let v = vt.get(1).cloned().unwrap_or(0.0);
+                    // OBJ puts the origin at the bottom.
+                    tex_coords.push([u, 1.0 - v]);
+                }
+                "f" => {
+                    let mut corners = Vec::new();
+                    for word in words.iter() {
+                        let mut indices = word.split('/');
+                        let index = |index: Option<&str>, len: usize| {
+                            resolve(index.unwrap_or(""), len)
+                                .map_err(|_| error("bad face index"))
+                        };
+                        let position = index(indices.next(), positions.len())?
+                            .ok_or_else(|| error("face needs a vertex"))?;
+                        let tex_coord =
+                            index(indices.next(), tex_coords.len())?;
+                        corners.push(Vertex {
+                            position: positions[position],
+                            tex_coord: tex_coord
+                                .map(|i| tex_coords[i])
+                                .unwrap_or([0.0; 2]),
+                            color: colors[position],
+                        });
+                    }
+                    if corners.len() < 3 {
+                        return Err(error("face needs 3 vertices"));
+                    }
+                    for i in 2..corners.len() {
+                        mesh.vertices.push(corners[0]);
+                        mesh.vertices.push(corners[i - 1]);
+                        mesh.vertices.push(corners[i]);
+                    }
+                }
+                "o" | "g" => {
+                    let name = words.join(" ");
+                    if mesh.vertices.is_empty() {
+                        mesh.name = name;
+                    } else {
+                        meshes.push(std::mem::replace(
+                            &mut mesh,
+                            Mesh {
+                                name,
+                                vertices: Vec::new(),
+                            },
+                        ));
+                    }
+                }
+                // Normals, materials, smoothing, lines and points.
+                _ => {}
+            }
+        }
+        if !mesh.vertices.is_empty() {
+            meshes.push(mesh);
+        }
+        Ok(meshes)
+    }
+
+    /// Load the meshes in a glTF 2.0 file, either `.gltf` with buffers
+    /// embedded as `data:` URIs, or binary `.glb`.
+    ///
+    /// Each mesh in the default scene becomes a `Mesh`, with its node's
+    /// transform applied (if the file has no scenes, each mesh is loaded once
+    /// as is).  Triangles are read from the `POSITION`, `TEXCOORD_0` and
+    /// `COLOR_0` attributes; if there are no vertex colors, the material's
+    /// base color is used.
+    pub fn load_gltf(bytes: &[u8]) -> Result<Vec<Mesh>, MeshError> {
+        Gltf::new(bytes)?.meshes()
+    }
+
+    /// Get the name of the mesh (the object, group, node or mesh name from
+    /// the file).
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the number of triangles.
+    pub fn triangles(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    /// Create a `ShapeBuilder` with the mesh's vertices laid out for
+    /// `shader`: positions (with Z if it has depth), then texture coordinates
+    /// (if it has a graphic), then colors (with alpha if it blends).  Finish it
+    /// with the same shader.
+    pub fn shape_builder(&self, shader: &Shader) -> ShapeBuilder {
+        let format = shader.1;
+        let mut vertices = Vec::new();
+        for vertex in self.vertices.iter() {
+            vertices.extend_from_slice(&vertex.position[..format.dimensions]);
+            if format.graphic {
+                vertices.extend_from_slice(&vertex.tex_coord);
+            }
+            vertices.extend_from_slice(&vertex.color[..format.components]);
+        }
+        ShapeBuilder::new().vert(&vertices).face(Transform::new())
+    }
+}
+
+// Convert from Y-up to Y-down, by turning upside down around the X axis.
+fn flip([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [x, -y, -z]
+}
+
+// Resolve a 1-based (or negative, relative to the end) OBJ index.  Empty
+// indices are `None`.
+fn resolve(index: &str, len: usize) -> Result<Option<usize>, ()> {
+    if index.is_empty() {
+        return Ok(None);
+    }
+    let index: isize = index.parse().map_err(|_| ())?;
+    let index = if index < 0 {
+        len as isize + index
+    } else {
+        index - 1
+    };
+    if index < 0 || index as usize >= len {
+        return Err(());
+    }
+    Ok(Some(index as usize))
+}
+
+// A parsed glTF file.
+struct Gltf {
+    json: Json,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Gltf {
+    fn new(bytes: &[u8]) -> Result<Self, MeshError> {
+        let (json, binary) = if bytes.starts_with(b"glTF") {
+            glb(bytes)?
+        } else {
+            (bytes, None)
+        };
+        let json = std::str::from_utf8(json)
+            .map_err(|_| MeshError::new("glTF JSON isn't UTF-8"))?;
+        let json = Json::parse(json).map_err(MeshError::new)?;
+
+        let mut buffers = Vec::new();
+        for (i, buffer) in json
+            .get("buffers")
+            .into_iter()
+            .flat_map(Json::iter)
+            .enumerate()
+        {
+            let data = match buffer.get("uri").and_then(Json::as_str) {
+                Some(uri) => {
+                    let data = uri
+                        .strip_prefix("data:")
+                        .and_then(|uri| uri.split_once(";base64,"))
+                        .ok_or_else(|| {
+                            MeshError::new(
+                                "only embedded buffers are supported",
+                            )
+                        })?
+                        .1;
+                    base64(data)
+                        .ok_or_else(|| MeshError::new("bad base64 in buffer"))?
+                }
+                None if i == 0 => binary
+                    .ok_or_else(|| MeshError::new("missing GLB buffer"))?
+                    .to_vec(),
+                None => return Err(MeshError::new("buffer has no data")),
+            };
+            buffers.push(data);
+        }
+        Ok(Gltf { json, buffers })
+    }
+
+    fn get(&self, kind: &str, index: usize) -> Result<&Json, MeshError> {
+        self.json
+            .get(kind)
+            .and_then(Json::as_array)
+            .and_then(|items| items.get(index))
+            .ok_or_else(|| {
+                MeshError::new(format!("missing {} {}", kind, index))
+            })
+    }
+
+    // Load every mesh in the default scene.
+    fn meshes(&self) -> Result<Vec<Mesh>, MeshError> {
+        let mut meshes = Vec::new();
+        let scene =
+            self.json.get("scene").and_then(Json::as_usize).unwrap_or(0);
+        if let Ok(scene) = self.get("scenes", scene) {
+            for node in scene.get("nodes").into_iter().flat_map(Json::iter) {
+                let node = node
+                    .as_usize()
+                    .ok_or_else(|| MeshError::new("bad node index"))?;
+                self.node(node, Transform::new(), &mut meshes, 0)?;
+            }
+        } else {
+            let count = self
+                .json
+                .get("meshes")
+                .into_iter()
+                .flat_map(Json::iter)
+                .count();
+            for mesh in 0..count {
+                meshes.push(self.mesh(mesh, Transform::new(), None)?);
+            }
+        }
+        Ok(meshes)
+    }
+
+    // Load the meshes of a node and its children.
+    fn node(
+        &self,
+        index: usize,
+        parent: Transform,
+        meshes: &mut Vec<Mesh>,
+        depth: usize,
+    ) -> Result<(), MeshError> {
+        if depth > 64 {
+            return Err(MeshError::new("nodes are nested too deep"));
+        }
+        let node = self.get("nodes", index)?;
+        let transform = node_matrix(node) * parent;
+        if let Some(mesh) = node.get("mesh").and_then(Json::as_usize) {
+            let name = node.get("name").and_then(Json::as_str);
+            meshes.push(self.mesh(mesh, transform, name)?);
+        }
+        for child in node.get("children").into_iter().flat_map(Json::iter) {
+            let child = child
+                .as_usize()
+                .ok_or_else(|| MeshError::new("bad node index"))?;
+            self.node(child, transform, meshes, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    // Load the triangles of every primitive in a mesh.
+    fn mesh(
+        &self,
+        index: usize,
+        transform: Transform,
+        name: Option<&str>,
+    ) -> Result<Mesh, MeshError> {
+        let mesh = self.get("meshes", index)?;
+        let name = name
+            .or_else(|| mesh.get("name").and_then(Json::as_str))
+            .unwrap_or("")
+            .to_string();
+        let mut vertices = Vec::new();
+        for primitive in mesh.get("primitives").into_iter().flat_map(Json::iter)
+        {
+            // Only triangle lists.
+            let mode = primitive.get("mode").and_then(Json::as_usize);
+            if mode.unwrap_or(4) != 4 {
+                continue;
+            }
+            let attributes = primitive
+                .get("attributes")
+                .ok_or_else(|| MeshError::new("primitive has no attributes"))?;
+            let attribute = |name| -> Result<Option<Vec<Vec<f32>>>, MeshError> {
+                match attributes.get(name).and_then(Json::as_usize) {
+                    Some(accessor) => self.accessor(accessor).map(Some),
+                    None => Ok(None),
+                }
+            };
+            let positions = attribute("POSITION")?
+                .ok_or_else(|| MeshError::new("primitive has no positions"))?;
+            let tex_coords = attribute("TEXCOORD_0")?;
+            let colors = attribute("COLOR_0")?;
+            let base_color = self.base_color(primitive)?;
+            let indices: Vec<usize> =
+                match primitive.get("indices").and_then(Json::as_usize) {
+                    Some(accessor) => self
+                        .accessor(accessor)?
+                        .iter()
+                        .map(|index| index[0] as usize)
+                        .collect(),
+                    None => (0..positions.len()).collect(),
+                };
+            for index in indices.iter().take(indices.len() / 3 * 3) {
+                let position = positions
+                    .get(*index)
+                    .ok_or_else(|| MeshError::new("index out of bounds"))?;
+                let position = [position[0], position[1], position[2]];
+                let tex_coord = tex_coords
+                    .as_ref()
+                    .and_then(|tex_coords| tex_coords.get(*index))
+                    .map(|uv| [uv[0], uv[1]])
+                    .unwrap_or([0.0; 2]);
+                let color = colors
+                    .as_ref()
+                    .and_then(|colors| colors.get(*index))
+                    .map(|c| {
+                        [c[0], c[1], c[2], c.get(3).cloned().unwrap_or(1.0)]
+                    })
+                    .unwrap_or(base_color);
+                vertices.push(Vertex {
+                    position: flip(transform * position),
+                    tex_coord,
+                    color,
+                });
+            }
+        }
+        // A mirroring transform turns faces inside out, so flip them back.
+        if transform.determinant() < 0.0 {
+            for triangle in vertices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+        Ok(Mesh { name, vertices })
+    }
+
+    // Get the base color of a primitive's material.
+    fn base_color(&self, primitive: &Json) -> Result<[f32; 4], MeshError> {
+        let mut color = [1.0; 4];
+        if let Some(material) =
+            primitive.get("material").and_then(Json::as_usize)
+        {
+            let factor = self
+                .get("materials", material)?
+                .get("pbrMetallicRoughness")
+                .and_then(|pbr| pbr.get("baseColorFactor"));
+            for (c, value) in color
+                .iter_mut()
+                .zip(factor.into_iter().flat_map(Json::iter))
+            {
+                *c = value.as_f32().unwrap_or(1.0);
+            }
+        }
+        Ok(color)
+    }
+
+    // Read every element of an accessor, as floats (normalized integers are
+    // converted to 0 to 1, or -1 to 1).
+    fn accessor(&self, index: usize) -> Result<Vec<Vec<f32>>, MeshError> {
+        let accessor = self.get("accessors", index)?;
+        let count = accessor
+            .get("count")
+            .and_then(Json::as_usize)
+            .ok_or_else(|| MeshError::new("accessor has no count"))?;
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            _ => return Err(MeshError::new("unsupported accessor type")),
+        };
+        let component = accessor
+            .get("componentType")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        let normalized = accessor
+            .get("normalized")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        let (size, read): (usize, fn(&[u8]) -> f32) = match component {
+            5120 => (1, |b| f32::from(b[0] as i8)),
+            5121 => (1, |b| f32::from(b[0])),
+            5122 => (2, |b| f32::from(i16::from_le_bytes([b[0], b[1]]))),
+            5123 => (2, |b| f32::from(u16::from_le_bytes([b[0], b[1]]))),
+            5125 => {
+                (4, |b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32)
+            }
+            5126 => (4, |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            _ => return Err(MeshError::new("unsupported component type")),
+        };
+        let scale = match (normalized, component) {
+            (false, _) => 1.0,
+            (true, 5120) => 1.0 / 127.0,
+            (true, 5121) => 1.0 / 255.0,
+            (true, 5122) => 1.0 / 32767.0,
+            (true, 5123) => 1.0 / 65535.0,
+            (true, _) => 1.0,
+        };
+
+        // Accessors without a buffer view are all zeros.
+        let view = match accessor.get("bufferView").and_then(Json::as_usize) {
+            Some(view) => self.get("bufferViews", view)?,
+            None if count > MAX_ZEROS => {
+                return Err(MeshError::new("accessor has too many elements"))
+            }
+            None => return Ok(vec![vec![0.0; components]; count]),
+        };
+        let buffer = view
+            .get("buffer")
+            .and_then(Json::as_usize)
+            .and_then(|buffer| self.buffers.get(buffer))
+            .ok_or_else(|| MeshError::new("missing buffer"))?;
+        let out_of_bounds = || MeshError::new("accessor out of bounds");
+        let start = view.get("byteOffset").and_then(Json::as_usize);
+        let start = start.unwrap_or(0);
+        let length = view
+            .get("byteLength")
+            .and_then(Json::as_usize)
+            .ok_or_else(|| MeshError::new("buffer view has no length"))?;
+        let buffer = start
+            .checked_add(length)
+            .and_then(|end| buffer.get(start..end))
+            .ok_or_else(|| MeshError::new("buffer view out of bounds"))?;
+        let offset = accessor
+            .get("byteOffset")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        let stride = view
+            .get("byteStride")
+            .and_then(Json::as_usize)
+            .unwrap_or(size * components);
+        if stride < size * components {
+            return Err(MeshError::new("byte stride is too small"));
+        }
+        // Check that the last element fits before allocating for them all.
+        if count != 0 {
+            (count - 1)
+                .checked_mul(stride)
+                .and_then(|end| end.checked_add(offset + size * components))
+                .filter(|end| *end <= buffer.len())
+                .ok_or_else(out_of_bounds)?;
+        }
+
+        let mut elements = Vec::with_capacity(count);
+        for i in 0..count {
+            let start = offset + i * stride;
+            let bytes = buffer
+                .get(start..start + size * components)
+                .ok_or_else(out_of_bounds)?;
+            let element = bytes
+                .chunks_exact(size)
+                .map(|b| (read(b) * scale).max(-1.0))
+                .collect();
+            elements.push(element);
+        }
+        Ok(elements)
+    }
+}
+
+// Split a GLB file into its JSON and binary chunks.
+fn glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), MeshError> {
+    let u32_at = |i: usize| -> Result<usize, MeshError> {
+        bytes
+            .get(i..i + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(|| MeshError::new("truncated GLB"))
+    };
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset < bytes.len().min(u32_at(8)?) {
+        let length = u32_at(offset)?;
+        let kind = u32_at(offset + 4)?;
+        let data = bytes
+            .get(offset + 8..offset + 8 + length)
+            .ok_or_else(|| MeshError::new("truncated GLB"))?;
+        chunks.push((kind, data));
+        offset += 8 + length;
+    }
+    // Chunk types are "JSON" and "BIN\0".
+    let json = chunks
+        .iter()
+        .find(|(kind, _)| *kind == 0x4E4F_534A)
+        .ok_or_else(|| MeshError::new("GLB has no JSON"))?
+        .1;
+    let binary = chunks
+        .iter()
+        .find(|(kind, _)| *kind == 0x004E_4942)
+        .map(|(_, data)| *data);
+    Ok((json, binary))
+}
+
+// Decode base64 (standard alphabet, padding optional).
+pub(super) fn base64(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        bits = bits << 6 | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+// Get the local transform of a node (a matrix, or translation, rotation and
+// scale).
+fn node_matrix(node: &Json) -> Transform {
+    let numbers = |key, default: &[f32]| -> Vec<f32> {
+        match node.get(key).and_then(Json::as_array) {
+            Some(values) => values.iter().filter_map(Json::as_f32).collect(),
+            None => default.to_vec(),
+        }
+    };
+    let matrix = numbers("matrix", &[]);
+    if matrix.len() == 16 {
+        let mut out = Transform::new().mat4();
+        for (i, value) in matrix.iter().enumerate() {
+            out[i / 4][i % 4] = *value;
+        }
+        return Transform::from_mat4(out);
+    }
+    let t = numbers("translation", &[0.0, 0.0, 0.0]);
+    let r = numbers("rotation", &[0.0, 0.0, 0.0, 1.0]);
+    let s = numbers("scale", &[1.0, 1.0, 1.0]);
+    if t.len() != 3 || r.len() != 4 || s.len() != 3 {
+        return Transform::new();
+    }
+    let (x, y, z, w) = (r[0], r[1], r[2], r[3]);
+    Transform::from_mat4([
+        [
+            (1.0 - 2.0 * (y * y + z * z)) * s[0],
+            (2.0 * (x * y + z * w)) * s[0],
+            (2.0 * (x * z - y * w)) * s[0],
+            0.0,
+        ],
+        [
+            (2.0 * (x * y - z * w)) * s[1],
+            (1.0 - 2.0 * (x * x + z * z)) * s[1],
+            (2.0 * (y * z + x * w)) * s[1],
+            0.0,
+        ],
+        [
+            (2.0 * (x * z + y * w)) * s[2],
+            (2.0 * (y * z - x * w)) * s[2],
+            (1.0 - 2.0 * (x * x + y * y)) * s[2],
+            0.0,
+        ],
+        [t[0], t[1], t[2], 1.0],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OBJ: &str = "\
+# A square and a triangle.
+v 0 0 0
+v 1 0 0 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 1
+o square
+f 1/1 2 3/2 4
+g triangle
+f -4 -3 -2
+";
+
+    // A red triangle, moved 1 along X by its node.
+    const GLTF: &str = r#"{
+        "scenes": [{"nodes": [0]}],
+        "nodes": [{"mesh": 0, "name": "node", "translation": [1, 0, 0]}],
+        "meshes": [{"primitives": [
+            {"attributes": {"POSITION": 0}, "indices": 1, "material": 0}
+        ]}],
+        "materials": [
+            {"pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1]}}
+        ],
+        "buffers": [{"byteLength": 42, "uri": "URI"}],
+        "bufferViews": [
+            {"buffer": 0, "byteLength": 36},
+            {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3,
+                "type": "VEC3"},
+            {"bufferView": 1, "componentType": 5123, "count": 3,
+                "type": "SCALAR"}
+        ]
+    }"#;
+
+    // The positions and indices of the triangle.
+    fn buffer() -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0].iter() {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in [0u16, 1, 2].iter() {
+            buffer.extend_from_slice(&index.to_le_bytes());
+        }
+        buffer
+    }
+
+    // Encode base64 (standard alphabet, with padding).
+    fn encode(bytes: &[u8]) -> String {
+        let table = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz\
+            0123456789+/";
+        let mut out = String::new();
+        for chunk in bytes.chunks(3) {
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (i, b)| bits | u32::from(*b) << (16 - i * 8));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(
+                        table[(bits >> (18 - i * 6)) as usize & 63].into(),
+                    );
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    // A glTF file with the triangle, after replacing text in the JSON.
+    fn gltf(from: &str, to: &str) -> Vec<u8> {
+        let uri = format!("data:application/octet-stream;base64,{}", {
+            encode(&buffer())
+        });
+        GLTF.replace("URI", &uri).replace(from, to).into_bytes()
+    }
+
+    fn positions(mesh: &Mesh) -> Vec<[f32; 3]> {
+        mesh.vertices.iter().map(|v| v.position).collect()
+    }
+
+    #[test]
+    fn obj() {
+        let meshes = Mesh::load_obj(OBJ.as_bytes()).unwrap();
+        assert_eq!(meshes.len(), 2);
+        let (square, triangle) = (&meshes[0], &meshes[1]);
+        assert_eq!(square.name(), "square");
+        assert_eq!(square.triangles(), 2);
+        assert_eq!(
+            positions(square),
+            [
+                [0.0, -0.0, -0.0],
+                [1.0, -0.0, -0.0],
+                [1.0, -1.0, -0.0],
+                [0.0, -0.0, -0.0],
+                [1.0, -1.0, -0.0],
+                [0.0, -1.0, -0.0],
+            ]
+        );
+        // Texture coordinates start at the top, and colors default to white.
+        assert_eq!(square.vertices[0].tex_coord, [0.0, 1.0]);
+        assert_eq!(square.vertices[1].tex_coord, [0.0, 0.0]);
+        assert_eq!(square.vertices[2].tex_coord, [1.0, 0.0]);
+        assert_eq!(square.vertices[0].color, [1.0; 4]);
+        assert_eq!(square.vertices[1].color, [1.0, 0.0, 0.0, 1.0]);
+        // Negative indices count back from the last vertex.
+        assert_eq!(triangle.name(), "triangle");
+        assert_eq!(positions(triangle), positions(square)[..3]);
+    }
+
+    #[test]
+    fn obj_errors() {
+        let error = |obj: &str| Mesh::load_obj(obj.as_bytes()).unwrap_err();
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+        assert_eq!(
+            error(&format!("{}f 1 2 4", obj)).message,
+            "line 4: bad face index"
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2", obj)).message,
+            "line 4: face needs 3 vertices"
+        );
+        assert_eq!(
+            error("v 1 2").message,
+            "line 1: vertex needs 3 coordinates"
+        );
+        assert_eq!(error("v 1 2 x").message, "line 1: bad number");
+        assert!(Mesh::load_obj("# nothing".as_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn gltf_embedded() {
+        let meshes = Mesh::load_gltf(&gltf("", "")).unwrap();
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].name(), "node");
+        assert_eq!(
+            positions(&meshes[0]),
+            [[1.0, -0.0, -0.0], [2.0, -0.0, -0.0], [1.0, -1.0, -0.0]]
+        );
+        assert_eq!(meshes[0].vertices[0].color, [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn gltf_binary() {
+        // Chunks are padded to 4 bytes.
+        let mut json = GLTF.replace(r#", "uri": "URI""#, "").into_bytes();
+        json.resize((json.len() + 3) & !3, b' ');
+        let mut bin = buffer();
+        bin.resize((bin.len() + 3) & !3, 0);
+        let mut glb = b"glTF".to_vec();
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        for (kind, data) in [(b"JSON", &json), (b"BIN\0", &bin)].iter() {
+            glb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            glb.extend_from_slice(*kind);
+            glb.extend_from_slice(data);
+        }
+        let meshes = Mesh::load_gltf(&glb).unwrap();
+        assert_eq!(positions(&meshes[0])[1], [2.0, -0.0, -0.0]);
+    }
+
+    #[test]
+    fn gltf_errors() {
+        let error = |from, to| Mesh::load_gltf(&gltf(from, to)).unwrap_err();
+        let count = r#"5126, "count": 3"#;
+        assert_eq!(
+            error(count, r#"5126, "count": 4"#).message,
+            "accessor out of bounds"
+        );
+        // Checked before allocating anything for the elements.
+        assert_eq!(
+            error(count, r#"5126, "count": 1e15"#).message,
+            "accessor out of bounds"
+        );
+        assert_eq!(
+            error(r#""bufferView": 0, "#, r#""count": 1e15, "#).message,
+            "accessor has too many elements"
+        );
+        assert_eq!(
+            error(r#""byteLength": 36"#, r#""byteLength": 43"#).message,
+            "buffer view out of bounds"
+        );
+        assert_eq!(
+            error(
+                r#""byteLength": 36"#,
+                r#""byteLength": 36, "byteStride": 8"#
+            )
+            .message,
+            "byte stride is too small"
+        );
+        assert_eq!(
+            error(count, r#"5126, "count": 2"#).message,
+            "index out of bounds"
+        );
+        let deep = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+        assert_eq!(
+            error(r#"[{"nodes": [0]}]"#, &deep).message,
+            "JSON is nested too deep (line 2)"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Frame pacing, and timing statistics of recent frames.
+
+use super::GpuCmd;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Number of frames that statistics are kept for (2 seconds at 60 FPS).
+const WINDOW: usize = 120;
+
+/// How frames are paced by the graphics thread, set with
+/// [`set_pacing()`](fn.set_pacing.html).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pacing {
+    /// Wait for the display to refresh before showing each frame, to avoid
+    /// tearing (default: `true`).  Some platforms (like Wayland) always
+    /// request frames at the display's refresh rate, so turning this off only
+    /// stops showing a frame from waiting.  Has no effect when rendering in
+    /// software.
+    pub vsync: bool,
+    /// Most frames to draw per second, or `None` for no limit (the default).
+    /// The graphics thread sleeps before requesting a frame that would come
+    /// too soon.
+    pub max_fps: Option<f32>,
+    /// Wait for the GPU to finish drawing each frame before it's shown, so
+    /// that the CPU can't get frames ahead of the GPU, and input is never
+    /// more than a frame old (default: `false`).  This lowers latency at the
+    /// cost of some throughput.
+    pub low_latency: bool,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Pacing {
+            vsync: true,
+            max_fps: None,
+            low_latency: false,
+        }
+    }
+}
+
+/// Timing of recent frames, returned from
+/// [`frame_stats()`](fn.frame_stats.html).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Number of frames the statistics are from (the last 120, or fewer
+    /// right after starting).
+    pub frames: usize,
+    /// Average time between frames.
+    pub average: Duration,
+    /// Median time between frames.
+    pub median: Duration,
+    /// Time between frames that 95% of frames are faster than.
+    pub p95: Duration,
+    /// Time between frames that 99% of frames are faster than.
+    pub p99: Duration,
+    /// Longest time between frames.
+    pub max: Duration,
+    /// Frames that came late: more than one and a half times the expected
+    /// time after the frame before (the display's refresh interval or the
+    /// `max_fps` interval, whichever is longer, or else the median).
+    pub dropped: usize,
+    /// Commands sent to the graphics thread over all of the frames.
+    pub commands: usize,
+    /// Draw calls over all of the frames, including those on render targets.
+    pub draws: usize,
+}
+
+// A frame that has been drawn.
+struct Record {
+    // Time since the frame before.
+    elapsed: Duration,
+    // Expected time since the frame before (zero if unknown).
+    expected: Duration,
+    commands: usize,
+    draws: usize,
+}
+
+// Paces frames, and records their timing, on the graphics thread.
+pub(super) struct Timer {
+    // When the last frame started.
+    last: Option<Instant>,
+    records: VecDeque<Record>,
+}
+
+impl Timer {
+    pub(super) fn new() -> Self {
+        Timer {
+            last: None,
+            records: VecDeque::with_capacity(WINDOW),
+        }
+    }
+
+    // Sleep until the next frame is due, and start it, returning the time
+    // since the last frame started.
+    pub(super) fn start(&mut self, pacing: &Pacing) -> Duration {
+        if let (Some(last), Some(fps)) = (self.last, pacing.max_fps) {
+            let due = last + Duration::from_secs_f32(1.0 / fps.max(1.0));
+            let now = Instant::now();
+            if due > now {
+                std::thread::sleep(due - now);
+            }
+        }
+        let now = Instant::now();
+        let elapsed = self.last.map(|last| now - last).unwrap_or_default();
+        self.last = Some(now);
+        elapsed
+    }
+
+    // Record a frame, before its commands are executed.  `refresh` is the
+    // display's refresh interval (zero if unknown).
+    pub(super) fn record(
+        &mut self,
+        pacing: &Pacing,
+        elapsed: Duration,
+        refresh: Duration,
+        cmds: &[GpuCmd],
+    ) {
+        // The first frame has nothing to be timed from.
+        if elapsed == Duration::default() {
+            return;
+        }
+        let cap = pacing
+            .max_fps
+            .map(|fps| Duration::from_secs_f32(1.0 / fps.max(1.0)))
+            .unwrap_or_default();
+        let draws = cmds.iter().filter(|cmd| is_draw(cmd)).count();
+        if self.records.len() == WINDOW {
+            self.records.pop_front();
+        }
+        self.records.push_back(Record {
+            elapsed,
+            expected: refresh.max(cap),
+            commands: cmds.len(),
+            draws,
+        });
+    }
+
+    // Calculate the statistics of the recorded frames.
+    pub(super) fn stats(&self) -> FrameStats {
+        let frames = self.records.len();
+        if frames == 0 {
+            return FrameStats::default();
+        }
+        let mut times: Vec<Duration> =
+            self.records.iter().map(|record| record.elapsed).collect();
+        times.sort_unstable();
+        let percentile = |p: usize| times[((frames - 1) * p + 50) / 100];
+        let median = percentile(50);
+        let dropped = self
+            .records
+            .iter()
+            .filter(|record| {
+                let expected = if record.expected == Duration::default() {
+                    median
+                } else {
+                    record.expected
+                };
+                record.elapsed > expected * 3 / 2
+            })
+            .count();
+        FrameStats {
+            frames,
+            average: times.iter().sum::<Duration>() / frames as u32,
+            median,
+            p95: percentile(95),
+            p99: percentile(99),
+            max: times[frames - 1],
+            dropped,
+            commands: self.records.iter().map(|record| record.commands).sum(),
+            draws: self.records.iter().map(|record| record.draws).sum(),
+        }
+    }
+}
+
+// Check if a command is a draw call.
+pub(super) fn is_draw(cmd: &GpuCmd) -> bool {
+    match cmd {
+        GpuCmd::Draw(..) | GpuCmd::DrawGraphic(..) | GpuCmd::Triangles(..) => {
+            true
+        }
+        GpuCmd::Target(_, cmd) => is_draw(cmd),
+        _ => false,
+    }
+}
@@ -0,0 +1,297 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Particle systems, simulated on the CPU and drawn as instances.
+
+use super::{Canvas, Instance, Instances, Shape, Transform};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+// Number of steps that colors over life are rounded to.  Each instance with a
+// different tint needs its own copy of the shape, so this keeps the number of
+// copies small however many particles there are.
+const COLOR_STEPS: f32 = 32.0;
+
+// Seeds the random number generator of each particle system differently.
+static NEXT_SEED: AtomicU32 = AtomicU32::new(0x9E37_79B9);
+
+/// How an emitter spawns particles, and how they change over their life.
+///
+/// Lists of values over life (`colors`, `sizes` and `frames`) are spread
+/// evenly from when a particle spawns to when it dies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Emitter {
+    /// Where particles spawn (default: the origin).
+    pub position: [f32; 3],
+    /// How far from `position` particles can spawn in each direction, at
+    /// random (default: zero).
+    pub area: [f32; 3],
+    /// Particles spawned per second (default: 10).
+    pub rate: f32,
+    /// Shortest and longest time a particle lives, in seconds, picked at
+    /// random (default: 1 to 1).
+    pub lifetime: (f32, f32),
+    /// Velocity that particles spawn with, in units per second (default:
+    /// zero).
+    pub velocity: [f32; 3],
+    /// How much `velocity` can vary in each direction, at random (default:
+    /// zero).
+    pub spread: [f32; 3],
+    /// Acceleration of particles, in units per second squared (default: zero).
+    /// Y goes down, so gravity is positive Y.
+    pub gravity: [f32; 3],
+    /// RGBA colors over life, blended between in 32 steps (default: white).
+    /// Colors are the tint of each [`Instance`](struct.Instance.html), so
+    /// they need a shape built for a shader with `gradient()`.
+    pub colors: Vec<[f32; 4]>,
+    /// Sizes over life, blended between, that the shape is scaled by
+    /// (default: 1).
+    pub sizes: Vec<f32>,
+    /// Texture coordinates (like from [`Atlas::get()`](struct.Atlas.html))
+    /// over life, switched between without blending, for animated particles
+    /// (default: the shape's own texture coordinates).
+    pub frames: Vec<([f32; 2], [f32; 2])>,
+    /// Most particles alive at once (default: 1000).  No more spawn until
+    /// some die.
+    pub max: usize,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Emitter {
+            position: [0.0; 3],
+            area: [0.0; 3],
+            rate: 10.0,
+            lifetime: (1.0, 1.0),
+            velocity: [0.0; 3],
+            spread: [0.0; 3],
+            gravity: [0.0; 3],
+            colors: Vec::new(),
+            sizes: Vec::new(),
+            frames: Vec::new(),
+            max: 1000,
+        }
+    }
+}
+
+// A live particle.
+struct Particle {
+    position: [f32; 3],
+    velocity: [f32; 3],
+    age: f32,
+    lifetime: f32,
+}
+
+/// A particle system: particles spawned by an [`Emitter`](struct.Emitter.html)
+/// and drawn as copies of a shape.
+///
+/// Call [`update()`](#method.update) once per frame, and then draw
+/// [`instances()`](#method.instances) with
+/// [`Canvas::draw_instances()`](trait.Canvas.html#method.draw_instances) (or
+/// `draw_instances_graphic()` with an atlas texture).
+pub struct Particles {
+    emitter: Emitter,
+    instances: Instances,
+    particles: Vec<Particle>,
+    // Particles waiting to spawn, carried over between frames.
+    pending: f32,
+    // State of the random number generator.
+    seed: u32,
+}
+
+impl Particles {
+    /// Create a particle system, drawing each particle as a copy of `shape`
+    /// (usually a square centered on the origin).
+    ///
+    /// # Panics
+    /// If the emitter has `colors`, but `shape` has no vertex colors to tint.
+    pub fn new(shape: Shape, emitter: Emitter) -> Self {
+        check_colors(&shape, &emitter);
+        Particles {
+            emitter,
+            instances: Instances::new(shape),
+            particles: Vec::new(),
+            pending: 0.0,
+            seed: NEXT_SEED.fetch_add(0x6D2B_79F5, Ordering::Relaxed) | 1,
+        }
+    }
+
+    /// Get the emitter.
+    pub fn emitter(&self) -> &Emitter {
+        &self.emitter
+    }
+
+    /// Get the emitter, to change it (like moving it with the thing that's
+    /// emitting particles).
+    pub fn emitter_mut(&mut self) -> &mut Emitter {
+        &mut self.emitter
+    }
+
+    /// Get the number of live particles.
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    /// Check if there are no live particles.
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Spawn `count` particles at once (like for an explosion), on top of the
+    /// emitter's rate.  They're drawn after the next update.
+    pub fn burst(&mut self, count: usize) {
+        let room = self.emitter.max.saturating_sub(self.particles.len());
+        for _ in 0..count.min(room) {
+            self.spawn();
+        }
+    }
+
+    /// Remove every particle.
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.pending = 0.0;
+        self.instances.set(&[]);
+    }
+
+    /// Simulate the time since the last frame of `canvas`
+    /// ([`Canvas::elapsed()`](trait.Canvas.html#tymethod.elapsed)), and
+    /// update the instances.
+    ///
+    /// # Panics
+    /// The same as [`step()`](#method.step).
+    pub fn update<C: Canvas>(&mut self, canvas: &C) {
+        self.step(canvas.elapsed());
+    }
+
+    /// Simulate `elapsed` time (like a fixed time step, or none while
+    /// paused), and update the instances.
+    ///
+    /// # Panics
+    /// If the emitter has been given `colors`, but the shape has no vertex
+    /// colors to tint.
+    pub fn step(&mut self, elapsed: Duration) {
+        check_colors(self.instances.shape(), &self.emitter);
+        let dt = elapsed.as_secs_f32();
+
+        // Age and move the particles.
+        let gravity = self.emitter.gravity;
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            let motion = particle.velocity.iter_mut().zip(gravity.iter());
+            for (position, (velocity, gravity)) in
+                particle.position.iter_mut().zip(motion)
+            {
+                *velocity += gravity * dt;
+                *position += *velocity * dt;
+            }
+            particle.age < particle.lifetime
+        });
+
+        // Spawn new particles, never waiting on more than there's room for
+        // (which also stops long or NaN time steps from spawning forever).
+        let room = self.emitter.max.saturating_sub(self.particles.len());
+        self.pending += self.emitter.rate.max(0.0) * dt;
+        self.pending = self.pending.min(room as f32);
+        while self.pending >= 1.0 {
+            self.pending -= 1.0;
+            self.spawn();
+        }
+
+        let emitter = &self.emitter;
+        let instances: Vec<Instance> = self
+            .particles
+            .iter()
+            .map(|particle| {
+                let life = particle.age / particle.lifetime;
+                let size = if emitter.sizes.is_empty() {
+                    1.0
+                } else {
+                    let (a, b, t) = over_life(emitter.sizes.len(), life);
+                    let sizes = &emitter.sizes;
+                    sizes[a] + (sizes[b] - sizes[a]) * t
+                };
+                let [x, y, z] = particle.position;
+                let mut instance = Instance::new(
+                    Transform::new().scale(size, size, size).translate(x, y, z),
+                );
+                if !emitter.frames.is_empty() {
+                    let count = emitter.frames.len();
+                    let index = ((life * count as f32) as usize).min(count - 1);
+                    instance.tex_coords = emitter.frames[index];
+                }
+                if !emitter.colors.is_empty() {
+                    let life = (life * COLOR_STEPS).round() / COLOR_STEPS;
+                    let (a, b, t) = over_life(emitter.colors.len(), life);
+                    let (a, b) = (emitter.colors[a], emitter.colors[b]);
+                    for channel in 0..4 {
+                        instance.tint[channel] =
+                            a[channel] + (b[channel] - a[channel]) * t;
+                    }
+                }
+                instance
+            })
+            .collect();
+        self.instances.set(&instances);
+    }
+
+    /// Get the instances to draw.
+    pub fn instances(&self) -> &Instances {
+        &self.instances
+    }
+
+    // Spawn a particle, unless there are too many.
+    fn spawn(&mut self) {
+        if self.particles.len() >= self.emitter.max {
+            return;
+        }
+        let mut position = self.emitter.position;
+        let mut velocity = self.emitter.velocity;
+        for axis in 0..3 {
+            position[axis] += self.emitter.area[axis] * self.random();
+            velocity[axis] += self.emitter.spread[axis] * self.random();
+        }
+        let (shortest, longest) = self.emitter.lifetime;
+        let lifetime =
+            shortest + (longest - shortest) * (self.random() + 1.0) / 2.0;
+        // Particles that die right away still need a lifetime to divide by.
+        let lifetime = lifetime.max(f32::MIN_POSITIVE);
+        self.particles.push(Particle {
+            position,
+            velocity,
+            age: 0.0,
+            lifetime,
+        });
+    }
+
+    // Get a random number from -1 to 1 (xorshift).
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+}
+
+// Check that the emitter's colors can be drawn with the shape.
+fn check_colors(shape: &Shape, emitter: &Emitter) {
+    assert!(
+        emitter.colors.is_empty() || shape.2.components != 0,
+        "Particle colors need a shape built for a shader with gradient()"
+    );
+}
+
+// Find where a point in life (0 to 1) falls in a list of `len` values: the
+// two values to blend between, and how far between them.
+fn over_life(len: usize, life: f32) -> (usize, usize, f32) {
+    let position = life.clamp(0.0, 1.0) * (len - 1) as f32;
+    let index = position as usize;
+    let next = (index + 1).min(len - 1);
+    (index, next, position - index as f32)
+}
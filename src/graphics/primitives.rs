@@ -0,0 +1,320 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Immediate-mode 2D primitives, tessellated into colored triangles.
+
+use super::{GpuCmd, Internal, Rect, Shader, ShaderBuilder};
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+// Floats in a vertex: X, Y, and RGBA color.
+pub(super) const STRIDE: usize = 6;
+
+// Longest that a miter join can be, in stroke widths, before it's cut short.
+const MITER_LIMIT: f32 = 2.0;
+
+// Shader that primitives are drawn with (colored vertices, with blending).
+static SHADER: OnceLock<Shader> = OnceLock::new();
+
+fn shader() -> &'static Shader {
+    SHADER.get_or_init(|| {
+        Shader::new(
+            ShaderBuilder::new(
+                "uniform mat4 cam;\n\
+                attribute vec2 pos;\n\
+                varying vec4 v_gradient;\n\
+                attribute vec4 col;\n\
+                void main() {\n\
+                v_gradient = col;\n\
+                gl_Position = cam * vec4(pos, 0.0, 1.0);\n}",
+                "precision mediump float;\n\
+                varying vec4 v_gradient;\n\
+                void main() {\n    gl_FragColor = v_gradient;\n}",
+            )
+            .gradient()
+            .blend(),
+        )
+    })
+}
+
+// Queue triangles to draw on the frame (or a render target), adding them to
+// the last command if it's also triangles, so they're drawn together.
+pub(super) fn push(target: Option<u32>, vertices: &[f32]) {
+    if vertices.is_empty() {
+        return;
+    }
+    let shader = shader().0;
+    let internal = Internal::new_lazy();
+    let mut cmds = internal.cmds.lock().unwrap();
+    let last = match (target, cmds.last_mut()) {
+        (None, Some(GpuCmd::Triangles(id, last))) if *id == shader => {
+            Some(last)
+        }
+        (Some(target), Some(GpuCmd::Target(id, cmd))) if *id == target => {
+            match **cmd {
+                GpuCmd::Triangles(id, ref mut last) if id == shader => {
+                    Some(last)
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    match last {
+        Some(last) => last.extend_from_slice(vertices),
+        None => {
+            let cmd = GpuCmd::Triangles(shader, vertices.to_vec());
+            cmds.push(match target {
+                Some(target) => GpuCmd::Target(target, Box::new(cmd)),
+                None => cmd,
+            });
+        }
+    }
+}
+
+// Convert a color into RGBA.
+pub(super) fn rgba<P: pix::el::Pixel>(color: P) -> [f32; 4]
+where
+    pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+{
+    use pix::chan::Channel;
+    use pix::el::Pixel;
+
+    let color: pix::rgb::SRgba32 = color.convert();
+    [
+        color.one().to_f32(),
+        color.two().to_f32(),
+        color.three().to_f32(),
+        color.four().to_f32(),
+    ]
+}
+
+// Add a triangle, wound so it faces the camera.
+fn triangle(
+    out: &mut Vec<f32>,
+    a: [f32; 2],
+    b: [f32; 2],
+    c: [f32; 2],
+    color: [f32; 4],
+) {
+    let cross = (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]);
+    let (b, c) = if cross > 0.0 { (c, b) } else { (b, c) };
+    for point in [a, b, c].iter() {
+        out.extend_from_slice(point);
+        out.extend_from_slice(&color);
+    }
+}
+
+// Tessellate a filled polygon (which may be concave, but can't cross itself)
+// by clipping ears.
+pub(super) fn fill(out: &mut Vec<f32>, points: &[[f32; 2]], color: [f32; 4]) {
+    if points.len() < 3 {
+        return;
+    }
+    // Ears point the same way around as the whole polygon.
+    let area: f32 = (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum();
+    let turn = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+        ((b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]))
+            * area.signum()
+    };
+    // Convex polygons (like circles) fan out from their center instead, to
+    // avoid long, thin triangles.
+    let len = points.len();
+    let convex = (0..len).all(|i| {
+        turn(points[i], points[(i + 1) % len], points[(i + 2) % len]) >= 0.0
+    });
+    if convex {
+        let mut center = [0.0, 0.0];
+        for point in points {
+            center[0] += point[0] / len as f32;
+            center[1] += point[1] / len as f32;
+        }
+        for i in 0..len {
+            triangle(out, center, points[i], points[(i + 1) % len], color);
+        }
+        return;
+    }
+    let mut left: Vec<usize> = (0..len).collect();
+    while left.len() > 3 {
+        let len = left.len();
+        let ear = (0..len).find(|&i| {
+            let a = points[left[(i + len - 1) % len]];
+            let b = points[left[i]];
+            let c = points[left[(i + 1) % len]];
+            turn(a, b, c) > 0.0
+                && left.iter().all(|&j| {
+                    let p = points[j];
+                    p == a
+                        || p == b
+                        || p == c
+                        || turn(a, b, p) < 0.0
+                        || turn(b, c, p) < 0.0
+                        || turn(c, a, p) < 0.0
+                })
+        });
+        // Without an ear, the rest is degenerate (or crosses itself).
+        let ear = match ear {
+            Some(ear) => ear,
+            None => break,
+        };
+        let a = points[left[(ear + len - 1) % len]];
+        let c = points[left[(ear + 1) % len]];
+        triangle(out, a, points[left[ear]], c, color);
+        left.remove(ear);
+    }
+    if left.len() == 3 {
+        let [a, b, c] = [points[left[0]], points[left[1]], points[left[2]]];
+        triangle(out, a, b, c, color);
+    }
+}
+
+// Tessellate a closed outline, `width` wide and centered on the edges, with
+// miter joins.
+pub(super) fn stroke(
+    out: &mut Vec<f32>,
+    points: &[[f32; 2]],
+    width: f32,
+    color: [f32; 4],
+) {
+    let len = points.len();
+    if len < 2 {
+        return;
+    }
+    let half = width / 2.0;
+    let ring: Vec<([f32; 2], [f32; 2])> = (0..len)
+        .map(|i| {
+            let prev = points[(i + len - 1) % len];
+            let point = points[i];
+            let next = points[(i + 1) % len];
+            let (a, b) = (normal(prev, point), normal(point, next));
+            // The miter points halfway between the normals of both edges, and
+            // gets longer the sharper the corner is.
+            let sum = [a[0] + b[0], a[1] + b[1]];
+            let length = (sum[0] * sum[0] + sum[1] * sum[1]).sqrt();
+            let (miter, scale) = if length > f32::EPSILON {
+                let miter = [sum[0] / length, sum[1] / length];
+                let dot = miter[0] * b[0] + miter[1] * b[1];
+                (miter, (half / dot).min(half * MITER_LIMIT))
+            } else {
+                (b, half)
+            };
+            let offset = [miter[0] * scale, miter[1] * scale];
+            (
+                [point[0] + offset[0], point[1] + offset[1]],
+                [point[0] - offset[0], point[1] - offset[1]],
+            )
+        })
+        .collect();
+    for i in 0..len {
+        let (outer_a, inner_a) = ring[i];
+        let (outer_b, inner_b) = ring[(i + 1) % len];
+        triangle(out, outer_a, outer_b, inner_b, color);
+        triangle(out, inner_b, inner_a, outer_a, color);
+    }
+}
+
+// Tessellate a line segment, `width` wide.
+pub(super) fn line(
+    out: &mut Vec<f32>,
+    a: [f32; 2],
+    b: [f32; 2],
+    width: f32,
+    color: [f32; 4],
+) {
+    let half = width / 2.0;
+    let n = normal(a, b);
+    let n = [n[0] * half, n[1] * half];
+    let corners = [
+        [a[0] + n[0], a[1] + n[1]],
+        [b[0] + n[0], b[1] + n[1]],
+        [b[0] - n[0], b[1] - n[1]],
+        [a[0] - n[0], a[1] - n[1]],
+    ];
+    triangle(out, corners[0], corners[1], corners[2], color);
+    triangle(out, corners[2], corners[3], corners[0], color);
+}
+
+// Get the corners of a rectangle.
+pub(super) fn rect(rect: Rect) -> Vec<[f32; 2]> {
+    let (right, bottom) = (rect.x + rect.width, rect.y + rect.height);
+    vec![
+        [rect.x, rect.y],
+        [right, rect.y],
+        [right, bottom],
+        [rect.x, bottom],
+    ]
+}
+
+// Get points around a rectangle with rounded corners.
+pub(super) fn rounded_rect(rect: Rect, radius: f32) -> Vec<[f32; 2]> {
+    let radius = radius
+        .min(rect.width.abs() / 2.0)
+        .min(rect.height.abs() / 2.0)
+        .max(0.0);
+    if radius == 0.0 {
+        return self::rect(rect);
+    }
+    let segments = segments(radius) / 4;
+    let (right, bottom) = (rect.x + rect.width, rect.y + rect.height);
+    let centers = [
+        [right - radius, bottom - radius],
+        [rect.x + radius, bottom - radius],
+        [rect.x + radius, rect.y + radius],
+        [right - radius, rect.y + radius],
+    ];
+    let mut points = Vec::with_capacity(4 * (segments + 1));
+    for (corner, center) in centers.iter().enumerate() {
+        for i in 0..=segments {
+            let angle = (corner as f32 + i as f32 / segments as f32) * PI / 2.0;
+            points.push([
+                center[0] + radius * angle.cos(),
+                center[1] + radius * angle.sin(),
+            ]);
+        }
+    }
+    points
+}
+
+// Get points around a circle.
+pub(super) fn circle(center: [f32; 2], radius: f32) -> Vec<[f32; 2]> {
+    let segments = segments(radius);
+    (0..segments)
+        .map(|i| {
+            let angle = i as f32 / segments as f32 * 2.0 * PI;
+            [
+                center[0] + radius * angle.cos(),
+                center[1] + radius * angle.sin(),
+            ]
+        })
+        .collect()
+}
+
+// Get how many segments a circle is split into, so that it looks round at
+// about 1000 pixels across the canvas.
+fn segments(radius: f32) -> usize {
+    let pixels = radius.abs() * 1000.0;
+    ((PI * pixels.sqrt()).ceil() as usize).clamp(3, 64) * 4
+}
+
+// Get the unit normal (to the left, going from `a` to `b`) of an edge.
+fn normal(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    let (x, y) = (b[0] - a[0], b[1] - a[1]);
+    let length = (x * x + y * y).sqrt();
+    if length > f32::EPSILON {
+        [-y / length, x / length]
+    } else {
+        [0.0, 0.0]
+    }
+}
@@ -0,0 +1,1201 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Recording the commands sent to the graphics thread, and replaying them in
+//! software.
+//!
+//! A recording starts with the resources and state of the software renderer
+//! (copied from the GPU's when drawing to a window), followed by each frame's
+//! aspect ratio and commands.  All numbers are little endian.
+
+use super::effect::EffectInternal;
+use super::pacing::is_draw;
+use super::primitives::STRIDE;
+use super::software::Software;
+use super::{
+    Blend, Face, GpuCmd, Instance, Rect, ShaderBuilder, ShapeBuilder,
+    Transform, VertexFormat,
+};
+use pix::{rgb::SRgba8, Raster, Region};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+// Start of every recording.
+const MAGIC: &[u8; 8] = b"CALAREC1";
+
+#[derive(Default)]
+pub(crate) struct RecordingInternal {
+    bytes: Option<Vec<u8>>,
+    waker: Option<Waker>,
+}
+
+/// A recording of the commands sent to the graphics thread.
+///
+/// This is a future that resolves to the recording once every frame has been
+/// recorded.  Save it to a file, and load it with
+/// [`Replay::load()`](struct.Replay.html#method.load) to step through it.
+pub struct Recording(pub(crate) Arc<Mutex<RecordingInternal>>);
+
+impl Future for Recording {
+    type Output = Vec<u8>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut lock = self.0.lock().unwrap();
+        if let Some(bytes) = lock.bytes.take() {
+            Poll::Ready(bytes)
+        } else {
+            lock.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+// A recording in progress, on the graphics thread.
+pub(super) struct Recorder {
+    recording: Arc<Mutex<RecordingInternal>>,
+    // Frames left to record.
+    frames: u32,
+    out: Writer,
+}
+
+impl Recorder {
+    // Start recording from the current state of the software renderer.
+    pub(super) fn new(
+        recording: Arc<Mutex<RecordingInternal>>,
+        frames: u32,
+        software: &Software,
+    ) -> Self {
+        let mut out = Writer(MAGIC.to_vec());
+        software.save(&mut out);
+        Recorder {
+            recording,
+            frames,
+            out,
+        }
+    }
+
+    // Record a frame, returning true once the recording is finished.
+    pub(super) fn frame(&mut self, aspect: f32, cmds: &[GpuCmd]) -> bool {
+        let cmds: Vec<&GpuCmd> =
+            cmds.iter().filter(|cmd| recorded(cmd)).collect();
+        self.out.u8(1);
+        self.out.f32(aspect);
+        self.out.u32(cmds.len() as u32);
+        for cmd in cmds {
+            self.out.cmd(cmd);
+        }
+        self.frames = self.frames.saturating_sub(1);
+        if self.frames != 0 {
+            return false;
+        }
+        self.out.u8(0);
+        let mut recording = self.recording.lock().unwrap();
+        recording.bytes = Some(std::mem::take(&mut self.out.0));
+        if let Some(waker) = recording.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+}
+
+// Start the recordings that a frame's commands request, from the resources and
+// state before the frame.
+pub(super) fn start(
+    recorders: &mut Vec<Recorder>,
+    cmds: &[GpuCmd],
+    software: &Software,
+) {
+    for cmd in cmds {
+        if let GpuCmd::Record(ref recording, frames) = *cmd {
+            recorders.push(Recorder::new(recording.clone(), frames, software));
+        }
+    }
+}
+
+// Record a frame for the recordings in progress, dropping finished ones.
+pub(super) fn record(
+    recorders: &mut Vec<Recorder>,
+    aspect: f32,
+    cmds: &[GpuCmd],
+) {
+    recorders.retain_mut(|recorder| !recorder.frame(aspect, cmds));
+}
+
+// Captures and recordings are requests to the graphics thread rather than
+// rendering, so they aren't recorded.
+fn recorded(cmd: &GpuCmd) -> bool {
+    match cmd {
+        GpuCmd::Capture(_) | GpuCmd::Record(..) => false,
+        GpuCmd::Target(_, cmd) => recorded(cmd),
+        _ => true,
+    }
+}
+
+/// Commands recorded with
+/// [`Frame::record()`](../window/struct.Frame.html#method.record), to be
+/// replayed offline in software.
+///
+/// Replays don't need a window or a GPU, so a recording sent in by a tester
+/// can be stepped through draw by draw on another machine.
+pub struct Replay {
+    // Resources and state when recording started.
+    start: Vec<u8>,
+    // Aspect ratio and commands of each frame.
+    frames: Vec<(f32, Vec<GpuCmd>)>,
+}
+
+impl Replay {
+    /// Load a recording.
+    ///
+    /// Recordings that are cut off or corrupted (including ones with
+    /// commands that use resources which don't exist) are an `InvalidData`
+    /// error, so that they can't panic when they're rendered.
+    pub fn load<R: Read>(mut reader: R) -> io::Result<Replay> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if !bytes.starts_with(MAGIC) {
+            return Err(invalid("not a cala recording"));
+        }
+        let mut input = Reader {
+            bytes: &bytes,
+            index: MAGIC.len(),
+        };
+        let mut live = Software::load(&mut input)?.live();
+        let start = bytes[MAGIC.len()..input.index].to_vec();
+        let mut frames = Vec::new();
+        while input.u8()? == 1 {
+            let aspect = input.f32()?;
+            let count = input.u32()?;
+            let mut cmds = Vec::new();
+            for _ in 0..count {
+                let cmd = input.cmd()?;
+                live.check(&cmd)?;
+                cmds.push(cmd);
+            }
+            frames.push((aspect, cmds));
+        }
+        Ok(Replay { start, frames })
+    }
+
+    /// Get the number of frames.
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Get the aspect ratio (`height / width`) of a frame.
+    pub fn aspect(&self, frame: usize) -> f32 {
+        self.frames[frame].0
+    }
+
+    /// Get the number of draw calls in a frame (including those on render
+    /// targets).
+    pub fn draws(&self, frame: usize) -> usize {
+        self.frames[frame]
+            .1
+            .iter()
+            .filter(|cmd| is_draw(cmd))
+            .count()
+    }
+
+    /// Describe each command in a frame, one per line, for finding which
+    /// draw goes wrong.
+    pub fn commands(&self, frame: usize) -> Vec<String> {
+        self.frames[frame].1.iter().map(describe).collect()
+    }
+
+    /// Render a frame `width` pixels wide, stopping after the first `draws`
+    /// draw calls (or drawing all of them, and then running the effects set
+    /// with [`set_effects()`](fn.set_effects.html), if `None`).
+    ///
+    /// Every frame before it is replayed first, so that render targets and
+    /// textures are the same as when it was recorded.
+    ///
+    /// # Panics
+    /// If `frame` is out of bounds.
+    pub fn render(
+        &self,
+        frame: usize,
+        draws: Option<usize>,
+        width: u16,
+    ) -> Raster<SRgba8> {
+        let mut input = Reader {
+            bytes: &self.start,
+            index: 0,
+        };
+        let mut software =
+            Software::load(&mut input).expect("Checked when loaded");
+        for (i, (aspect, cmds)) in self.frames[..=frame].iter().enumerate() {
+            let height = (f32::from(width) * aspect).round() as u16;
+            software.resize(width, height);
+            software.begin(i == frame);
+            let mut draws = draws.filter(|_| i == frame);
+            for cmd in cmds {
+                if is_draw(cmd) {
+                    match draws {
+                        Some(0) => break,
+                        Some(ref mut left) => *left -= 1,
+                        None => {}
+                    }
+                }
+                software.execute(cmd);
+            }
+            // Effects are run once every draw has been.
+            if draws.is_none() {
+                software.post_process();
+            }
+        }
+        software.frame()
+    }
+}
+
+pub(super) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Describe a command.
+fn describe(cmd: &GpuCmd) -> String {
+    use GpuCmd::*;
+    match *cmd {
+        Background(r, g, b) => format!("Background({}, {}, {})", r, g, b),
+        Draw(shader, group) => {
+            format!("Draw(shader: {}, group: {})", shader, group)
+        }
+        DrawGraphic(shader, group, raster) => format!(
+            "DrawGraphic(shader: {}, group: {}, texture: {})",
+            shader, group, raster
+        ),
+        Triangles(shader, ref vertices) => format!(
+            "Triangles(shader: {}, {} triangles)",
+            shader,
+            vertices.len() / (STRIDE * 3)
+        ),
+        SetCamera(camera) => format!("SetCamera({:?})", camera.mat4()),
+        SetViewport(viewport) => format!("SetViewport({:?})", viewport),
+        SetScissor(scissor) => format!("SetScissor({:?})", scissor),
+        SetBlend(blend) => format!("SetBlend({:?})", blend),
+        SetDepthTest(test) => format!("SetDepthTest({})", test),
+        SetDepthWrite(write) => format!("SetDepthWrite({})", write),
+        SetTint(shader, tint) => format!("SetTint({}, {:?})", shader, tint),
+        Capture(_) => "Capture".to_string(),
+        Record(_, frames) => format!("Record({})", frames),
+        SetEffects(ref effects) => format!(
+            "SetEffects({:?})",
+            effects
+                .iter()
+                .map(|effect| (effect.shader, effect.tint, effect.lut))
+                .collect::<Vec<_>>()
+        ),
+        Clear(r, g, b, a) => format!("Clear({}, {}, {}, {})", r, g, b, a),
+        TargetId(id, w, h) => format!("TargetId({}, {}×{})", id, w, h),
+        Target(id, ref cmd) => format!("Target({}, {})", id, describe(cmd)),
+        RasterId(ref raster, id) => {
+            format!("RasterId({}, {}×{})", id, raster.width(), raster.height())
+        }
+        RasterUpdate(id, region, _) => {
+            format!("RasterUpdate({}, {:?})", id, region)
+        }
+        RasterResize(id, w, h) => format!("RasterResize({}, {}×{})", id, w, h),
+        ShaderId(_, id, _) => format!("ShaderId({})", id),
+        ShapeId(_, id, shader) => {
+            format!("ShapeId({}, shader: {})", id, shader)
+        }
+        GroupId(id) => format!("GroupId({})", id),
+        GroupWrite(group, id, shape, _) => {
+            format!("GroupWrite({}, {}, shape: {})", group, id, shape)
+        }
+        GroupWriteTex(group, id, shape, _, tex_coords) => format!(
+            "GroupWriteTex({}, {}, shape: {}, {:?})",
+            group, id, shape, tex_coords
+        ),
+        GroupRemove(group, id) => format!("GroupRemove({}, {})", group, id),
+        GroupSwap(group, a, b) => format!("GroupSwap({}, {}, {})", group, a, b),
+        GroupClear(group) => format!("GroupClear({})", group),
+        GroupInstances(group, shape, _, ref instances) => format!(
+            "GroupInstances({}, shape: {}, {} instances)",
+            group,
+            shape,
+            instances.len()
+        ),
+        RasterDelete(id) => format!("RasterDelete({})", id),
+        ShaderDelete(id) => format!("ShaderDelete({})", id),
+        ShapeDelete(id) => format!("ShapeDelete({})", id),
+        GroupDelete(id) => format!("GroupDelete({})", id),
+    }
+}
+
+// Writes a recording.
+pub(super) struct Writer(Vec<u8>);
+
+impl Writer {
+    pub(super) fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    pub(super) fn bool(&mut self, value: bool) {
+        self.u8(value.into());
+    }
+
+    pub(super) fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn f32(&mut self, value: f32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn f32s(&mut self, values: &[f32]) {
+        self.u32(values.len() as u32);
+        for value in values {
+            self.f32(*value);
+        }
+    }
+
+    pub(super) fn u32s(&mut self, values: &[u32]) {
+        self.u32(values.len() as u32);
+        for value in values {
+            self.u32(*value);
+        }
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    pub(super) fn mat4(&mut self, mat: &[[f32; 4]; 4]) {
+        for value in mat.iter().flatten() {
+            self.f32(*value);
+        }
+    }
+
+    pub(super) fn rect(&mut self, rect: Option<Rect>) {
+        self.bool(rect.is_some());
+        if let Some(rect) = rect {
+            self.f32(rect.x);
+            self.f32(rect.y);
+            self.f32(rect.width);
+            self.f32(rect.height);
+        }
+    }
+
+    pub(super) fn blend(&mut self, blend: Blend) {
+        self.u8(match blend {
+            Blend::Alpha => 0,
+            Blend::Premultiplied => 1,
+            Blend::Additive => 2,
+            Blend::Multiply => 3,
+            Blend::Opaque => 4,
+        });
+    }
+
+    pub(super) fn raster(&mut self, raster: &Raster<SRgba8>) {
+        self.u32(raster.width());
+        self.u32(raster.height());
+        self.0.extend_from_slice(raster.as_u8_slice());
+    }
+
+    fn transform(&mut self, transform: &Transform) {
+        self.mat4(&transform.mat4());
+    }
+
+    pub(super) fn effects(&mut self, effects: &[EffectInternal]) {
+        self.u32(effects.len() as u32);
+        for effect in effects {
+            self.u32(effect.shader);
+            self.u32(effect.format.dimensions as u32);
+            self.bool(effect.format.graphic);
+            self.u32(effect.format.components as u32);
+            for value in effect.tint.iter() {
+                self.f32(*value);
+            }
+            self.bool(effect.lut.is_some());
+            self.u32(effect.lut.unwrap_or_default());
+        }
+    }
+
+    fn tex_coords(&mut self, (offset, scale): ([f32; 2], [f32; 2])) {
+        for value in offset.iter().chain(scale.iter()) {
+            self.f32(*value);
+        }
+    }
+
+    fn cmd(&mut self, cmd: &GpuCmd) {
+        use GpuCmd::*;
+        match *cmd {
+            Background(r, g, b) => {
+                self.u8(0);
+                self.f32(r);
+                self.f32(g);
+                self.f32(b);
+            }
+            Draw(shader, group) => {
+                self.u8(1);
+                self.u32(shader);
+                self.u32(group);
+            }
+            DrawGraphic(shader, group, raster) => {
+                self.u8(2);
+                self.u32(shader);
+                self.u32(group);
+                self.u32(raster);
+            }
+            SetCamera(ref camera) => {
+                self.u8(3);
+                self.transform(camera);
+            }
+            SetViewport(viewport) => {
+                self.u8(4);
+                self.rect(viewport);
+            }
+            SetScissor(scissor) => {
+                self.u8(5);
+                self.rect(scissor);
+            }
+            SetBlend(blend) => {
+                self.u8(6);
+                self.blend(blend);
+            }
+            SetDepthTest(test) => {
+                self.u8(7);
+                self.bool(test);
+            }
+            SetDepthWrite(write) => {
+                self.u8(8);
+                self.bool(write);
+            }
+            SetTint(shader, tint) => {
+                self.u8(9);
+                self.u32(shader);
+                for value in tint.iter() {
+                    self.f32(*value);
+                }
+            }
+            Clear(r, g, b, a) => {
+                self.u8(10);
+                self.f32(r);
+                self.f32(g);
+                self.f32(b);
+                self.f32(a);
+            }
+            TargetId(id, width, height) => {
+                self.u8(11);
+                self.u32(id);
+                self.u32(width.into());
+                self.u32(height.into());
+            }
+            Target(id, ref cmd) => {
+                self.u8(12);
+                self.u32(id);
+                self.cmd(cmd);
+            }
+            RasterId(ref raster, id) => {
+                self.u8(13);
+                self.u32(id);
+                self.raster(raster);
+            }
+            RasterUpdate(id, region, ref raster) => {
+                self.u8(14);
+                self.u32(id);
+                self.u32(region.left() as u32);
+                self.u32(region.top() as u32);
+                self.u32(region.width());
+                self.u32(region.height());
+                self.raster(raster);
+            }
+            RasterResize(id, width, height) => {
+                self.u8(15);
+                self.u32(id);
+                self.u32(width.into());
+                self.u32(height.into());
+            }
+            ShaderId(ref builder, id, _) => {
+                self.u8(16);
+                self.u32(id);
+                self.bool(builder.tint);
+                self.bool(builder.gradient);
+                self.bool(builder.graphic);
+                self.bool(builder.depth);
+                self.bool(builder.blend);
+                self.str(&builder.vertex);
+                self.str(&builder.fragment);
+            }
+            ShapeId(ref builder, id, shader) => {
+                self.u8(17);
+                self.u32(id);
+                self.u32(shader);
+                self.u32(builder.faces.len() as u32);
+                for face in builder.faces.iter() {
+                    self.bool(face.vertices.is_some());
+                    if let Some(ref vertices) = face.vertices {
+                        self.f32s(vertices);
+                    }
+                    self.bool(face.transform.is_some());
+                    if let Some(ref transform) = face.transform {
+                        self.transform(transform);
+                    }
+                }
+            }
+            GroupId(id) => {
+                self.u8(18);
+                self.u32(id);
+            }
+            GroupWrite(group, id, shape, ref transform) => {
+                self.u8(19);
+                self.u32(group);
+                self.u32(id);
+                self.u32(shape);
+                self.transform(transform);
+            }
+            GroupWriteTex(group, id, shape, ref transform, tex_coords) => {
+                self.u8(20);
+                self.u32(group);
+                self.u32(id);
+                self.u32(shape);
+                self.transform(transform);
+                self.tex_coords(tex_coords);
+            }
+            GroupRemove(group, id) => {
+                self.u8(21);
+                self.u32(group);
+                self.u32(id);
+            }
+            GroupSwap(group, a, b) => {
+                self.u8(22);
+                self.u32(group);
+                self.u32(a);
+                self.u32(b);
+            }
+            GroupClear(group) => {
+                self.u8(23);
+                self.u32(group);
+            }
+            GroupInstances(group, shape, shader, ref instances) => {
+                self.u8(24);
+                self.u32(group);
+                self.u32(shape);
+                self.u32(shader);
+                self.u32(instances.len() as u32);
+                for instance in instances {
+                    self.transform(&instance.transform);
+                    self.tex_coords(instance.tex_coords);
+                    for value in instance.tint.iter() {
+                        self.f32(*value);
+                    }
+                }
+            }
+            RasterDelete(id) => {
+                self.u8(25);
+                self.u32(id);
+            }
+            ShaderDelete(id) => {
+                self.u8(26);
+                self.u32(id);
+            }
+            ShapeDelete(id) => {
+                self.u8(27);
+                self.u32(id);
+            }
+            GroupDelete(id) => {
+                self.u8(28);
+                self.u32(id);
+            }
+            SetEffects(ref effects) => {
+                self.u8(29);
+                self.effects(effects);
+            }
+            Triangles(shader, ref vertices) => {
+                self.u8(30);
+                self.u32(shader);
+                self.f32s(vertices);
+            }
+            Capture(_) | Record(..) => unreachable!(),
+        }
+    }
+}
+
+// Reads a recording.
+pub(super) struct Reader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> io::Result<&[u8]> {
+        let bytes = self
+            .bytes
+            .get(self.index..self.index.saturating_add(len))
+            .ok_or_else(|| invalid("recording ends too soon"))?;
+        self.index += len;
+        Ok(bytes)
+    }
+
+    pub(super) fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(super) fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub(super) fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(super) fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    // Read a length, checking that there are enough bytes left for it.
+    fn len(&mut self, size: usize) -> io::Result<usize> {
+        let len = self.u32()? as usize;
+        if len.saturating_mul(size) > self.bytes.len() - self.index {
+            return Err(invalid("recording ends too soon"));
+        }
+        Ok(len)
+    }
+
+    pub(super) fn f32s(&mut self) -> io::Result<Vec<f32>> {
+        let len = self.len(4)?;
+        (0..len).map(|_| self.f32()).collect()
+    }
+
+    pub(super) fn u32s(&mut self) -> io::Result<Vec<u32>> {
+        let len = self.len(4)?;
+        (0..len).map(|_| self.u32()).collect()
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let len = self.len(1)?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| invalid("invalid UTF-8"))
+    }
+
+    pub(super) fn mat4(&mut self) -> io::Result<[[f32; 4]; 4]> {
+        let mut mat = [[0.0; 4]; 4];
+        for value in mat.iter_mut().flatten() {
+            *value = self.f32()?;
+        }
+        Ok(mat)
+    }
+
+    pub(super) fn rect(&mut self) -> io::Result<Option<Rect>> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+        Ok(Some(Rect::new(
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+        )))
+    }
+
+    pub(super) fn blend(&mut self) -> io::Result<Blend> {
+        Ok(match self.u8()? {
+            0 => Blend::Alpha,
+            1 => Blend::Premultiplied,
+            2 => Blend::Additive,
+            3 => Blend::Multiply,
+            4 => Blend::Opaque,
+            _ => return Err(invalid("unknown blend mode")),
+        })
+    }
+
+    pub(super) fn raster(&mut self) -> io::Result<Raster<SRgba8>> {
+        let width = self.size()?;
+        let height = self.size()?;
+        let len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|len| len.checked_mul(4))
+            .ok_or_else(|| invalid("raster is too big"))?;
+        let pixels = self.take(len)?.to_vec();
+        if pixels.is_empty() {
+            return Ok(Raster::with_clear(width, height));
+        }
+        Ok(Raster::with_u8_buffer(width, height, pixels))
+    }
+
+    fn transform(&mut self) -> io::Result<Transform> {
+        Ok(Transform::from_mat4(self.mat4()?))
+    }
+
+    pub(super) fn effects(&mut self) -> io::Result<Vec<EffectInternal>> {
+        let count = self.len(4 + 4 + 1 + 4 + 16 + 1 + 4)?;
+        let mut effects = Vec::with_capacity(count);
+        for _ in 0..count {
+            let shader = self.u32()?;
+            let format = VertexFormat {
+                dimensions: self.u32()? as usize,
+                graphic: self.bool()?,
+                components: self.u32()? as usize,
+            };
+            let tint = [self.f32()?, self.f32()?, self.f32()?, self.f32()?];
+            let lut = self.bool()?;
+            let id = self.u32()?;
+            effects.push(EffectInternal {
+                shader,
+                format,
+                tint,
+                lut: if lut { Some(id) } else { None },
+            });
+        }
+        Ok(effects)
+    }
+
+    fn tex_coords(&mut self) -> io::Result<([f32; 2], [f32; 2])> {
+        Ok(([self.f32()?, self.f32()?], [self.f32()?, self.f32()?]))
+    }
+
+    // Read the width or height of a raster or region.
+    fn size(&mut self) -> io::Result<u32> {
+        let size = self.u32()?;
+        if size > i32::MAX as u32 {
+            return Err(invalid("size is too big"));
+        }
+        Ok(size)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        use std::convert::TryFrom;
+
+        u16::try_from(self.u32()?).map_err(|_| invalid("size is too big"))
+    }
+
+    fn cmd(&mut self) -> io::Result<GpuCmd> {
+        use GpuCmd::*;
+        Ok(match self.u8()? {
+            0 => Background(self.f32()?, self.f32()?, self.f32()?),
+            1 => Draw(self.u32()?, self.u32()?),
+            2 => DrawGraphic(self.u32()?, self.u32()?, self.u32()?),
+            3 => SetCamera(self.transform()?),
+            4 => SetViewport(self.rect()?),
+            5 => SetScissor(self.rect()?),
+            6 => SetBlend(self.blend()?),
+            7 => SetDepthTest(self.bool()?),
+            8 => SetDepthWrite(self.bool()?),
+            9 => SetTint(
+                self.u32()?,
+                [self.f32()?, self.f32()?, self.f32()?, self.f32()?],
+            ),
+            10 => Clear(self.f32()?, self.f32()?, self.f32()?, self.f32()?),
+            11 => TargetId(self.u32()?, self.u16()?, self.u16()?),
+            12 => {
+                let id = self.u32()?;
+                // Render targets can't be nested, so this can't recurse.
+                if self.bytes.get(self.index) == Some(&12) {
+                    return Err(invalid("render target commands are nested"));
+                }
+                Target(id, Box::new(self.cmd()?))
+            }
+            13 => {
+                let id = self.u32()?;
+                RasterId(self.raster()?, id)
+            }
+            14 => {
+                let id = self.u32()?;
+                let region = Region::new(
+                    self.u32()? as i32,
+                    self.u32()? as i32,
+                    self.size()?,
+                    self.size()?,
+                );
+                RasterUpdate(id, region, self.raster()?)
+            }
+            15 => RasterResize(self.u32()?, self.u16()?, self.u16()?),
+            16 => {
+                let id = self.u32()?;
+                let builder = ShaderBuilder {
+                    tint: self.bool()?,
+                    gradient: self.bool()?,
+                    graphic: self.bool()?,
+                    depth: self.bool()?,
+                    blend: self.bool()?,
+                    vertex: self.str()?,
+                    fragment: self.str()?,
+                };
+                ShaderId(builder, id, None)
+            }
+            17 => {
+                let id = self.u32()?;
+                let shader = self.u32()?;
+                let count = self.len(2)?;
+                let mut faces = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let vertices = if self.bool()? {
+                        Some(self.f32s()?)
+                    } else {
+                        None
+                    };
+                    let transform = if self.bool()? {
+                        Some(self.transform()?)
+                    } else {
+                        None
+                    };
+                    faces.push(Face {
+                        vertices,
+                        transform,
+                    });
+                }
+                ShapeId(ShapeBuilder { faces }, id, shader)
+            }
+            18 => GroupId(self.u32()?),
+            19 => GroupWrite(
+                self.u32()?,
+                self.u32()?,
+                self.u32()?,
+                self.transform()?,
+            ),
+            20 => GroupWriteTex(
+                self.u32()?,
+                self.u32()?,
+                self.u32()?,
+                self.transform()?,
+                self.tex_coords()?,
+            ),
+            21 => GroupRemove(self.u32()?, self.u32()?),
+            22 => GroupSwap(self.u32()?, self.u32()?, self.u32()?),
+            23 => GroupClear(self.u32()?),
+            24 => {
+                let group = self.u32()?;
+                let shape = self.u32()?;
+                let shader = self.u32()?;
+                let count = self.len(64 + 16 + 16)?;
+                let mut instances = Vec::with_capacity(count);
+                for _ in 0..count {
+                    instances.push(Instance {
+                        transform: self.transform()?,
+                        tex_coords: self.tex_coords()?,
+                        tint: [
+                            self.f32()?,
+                            self.f32()?,
+                            self.f32()?,
+                            self.f32()?,
+                        ],
+                    });
+                }
+                GroupInstances(group, shape, shader, instances)
+            }
+            25 => RasterDelete(self.u32()?),
+            26 => ShaderDelete(self.u32()?),
+            27 => ShapeDelete(self.u32()?),
+            28 => GroupDelete(self.u32()?),
+            29 => SetEffects(self.effects()?),
+            30 => Triangles(self.u32()?, self.f32s()?),
+            _ => return Err(invalid("unknown command")),
+        })
+    }
+}
+
+// Which resource IDs are alive while loading a recording, to reject commands
+// that would use ones that aren't.
+pub(super) struct Live {
+    pub(super) rasters: Vec<bool>,
+    pub(super) targets: HashSet<u32>,
+    pub(super) shaders: Vec<bool>,
+    pub(super) shapes: Vec<bool>,
+    // Number of shapes written into each group.
+    pub(super) groups: Vec<Option<u32>>,
+}
+
+impl Live {
+    // Check a command, and then apply it.
+    fn check(&mut self, cmd: &GpuCmd) -> io::Result<()> {
+        use GpuCmd::*;
+        match *cmd {
+            Background(..) | SetCamera(_) | SetViewport(_) | SetScissor(_)
+            | SetBlend(_) | SetDepthTest(_) | SetDepthWrite(_) | Clear(..) => {}
+            Draw(shader, group) => {
+                alive(&self.shaders, shader)?;
+                self.group(group)?;
+            }
+            DrawGraphic(shader, group, raster) => {
+                alive(&self.shaders, shader)?;
+                self.group(group)?;
+                alive(&self.rasters, raster)?;
+            }
+            Triangles(shader, _) | SetTint(shader, _) => {
+                alive(&self.shaders, shader)?;
+            }
+            SetEffects(ref effects) => {
+                for effect in effects {
+                    alive(&self.shaders, effect.shader)?;
+                    if let Some(lut) = effect.lut {
+                        alive(&self.rasters, lut)?;
+                    }
+                }
+            }
+            TargetId(id, _, _) => {
+                create(&mut self.rasters, id)?;
+                self.targets.insert(id);
+            }
+            Target(id, ref cmd) => {
+                alive(&self.rasters, id)?;
+                self.check(cmd)?;
+            }
+            RasterId(_, id) => create(&mut self.rasters, id)?,
+            RasterUpdate(id, _, _) => alive(&self.rasters, id)?,
+            RasterResize(id, _, _) => {
+                alive(&self.rasters, id)?;
+                if self.targets.contains(&id) {
+                    return Err(invalid("render target resized"));
+                }
+            }
+            ShaderId(_, id, _) => create(&mut self.shaders, id)?,
+            ShapeId(_, id, shader) => {
+                alive(&self.shaders, shader)?;
+                create(&mut self.shapes, id)?;
+            }
+            GroupId(id) => {
+                if id as usize > self.groups.len() {
+                    return Err(invalid("resource ID skipped"));
+                }
+                if id as usize == self.groups.len() {
+                    self.groups.push(None);
+                }
+                self.groups[id as usize] = Some(0);
+            }
+            RasterDelete(id) => {
+                alive(&self.rasters, id)?;
+                self.rasters[id as usize] = false;
+                self.targets.remove(&id);
+            }
+            ShaderDelete(id) => {
+                alive(&self.shaders, id)?;
+                self.shaders[id as usize] = false;
+            }
+            ShapeDelete(id) => {
+                alive(&self.shapes, id)?;
+                self.shapes[id as usize] = false;
+            }
+            GroupDelete(id) => {
+                self.group(id)?;
+                self.groups[id as usize] = None;
+            }
+            GroupWrite(group, id, shape, _)
+            | GroupWriteTex(group, id, shape, _, _) => {
+                alive(&self.shapes, shape)?;
+                let len = self.group(group)?;
+                if id > *len {
+                    return Err(invalid("group write out of bounds"));
+                }
+                *len = (*len).max(id + 1);
+            }
+            GroupRemove(group, id) => {
+                let len = self.group(group)?;
+                if id >= *len {
+                    return Err(invalid("group remove out of bounds"));
+                }
+                *len -= 1;
+            }
+            GroupSwap(group, a, b) => {
+                let len = self.group(group)?;
+                if a >= *len || b >= *len {
+                    return Err(invalid("group swap out of bounds"));
+                }
+            }
+            GroupClear(group) => *self.group(group)? = 0,
+            GroupInstances(group, shape, shader, ref instances) => {
+                alive(&self.shapes, shape)?;
+                alive(&self.shaders, shader)?;
+                *self.group(group)? = instances.len() as u32;
+            }
+            Capture(_) | Record(..) => unreachable!(),
+        }
+        Ok(())
+    }
+
+    // Get the number of shapes in a group that's alive.
+    fn group(&mut self, id: u32) -> io::Result<&mut u32> {
+        self.groups
+            .get_mut(id as usize)
+            .and_then(Option::as_mut)
+            .ok_or_else(|| invalid("resource used after delete"))
+    }
+}
+
+// Check that a resource is alive.
+fn alive(slots: &[bool], id: u32) -> io::Result<()> {
+    if slots.get(id as usize) == Some(&true) {
+        Ok(())
+    } else {
+        Err(invalid("resource used after delete"))
+    }
+}
+
+// Create a resource, at a reused ID or the next new one.
+fn create(slots: &mut Vec<bool>, id: u32) -> io::Result<()> {
+    if id as usize > slots.len() {
+        return Err(invalid("resource ID skipped"));
+    }
+    if id as usize == slots.len() {
+        slots.push(true);
+    }
+    slots[id as usize] = true;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A rectangle with texture coordinates, for a shader with `graphic()`.
+    fn quad(x: f32, w: f32) -> Vec<f32> {
+        let corners = [
+            (x, 0.0),
+            (x, 1.0),
+            (x + w, 0.0),
+            (x + w, 0.0),
+            (x, 1.0),
+            (x + w, 1.0),
+        ];
+        let mut vertices = Vec::new();
+        for (x, y) in corners.iter() {
+            vertices.extend_from_slice(&[*x, *y, *x, *y]);
+        }
+        vertices
+    }
+
+    // A rectangle laid out for `Triangles`.
+    fn triangles(x: f32, w: f32, color: [f32; 4]) -> Vec<f32> {
+        let mut vertices = Vec::new();
+        for pos in quad(x, w).chunks_exact(4) {
+            vertices.extend_from_slice(&pos[..2]);
+            vertices.extend_from_slice(&color);
+        }
+        vertices
+    }
+
+    // Record frames, returning the recording and the frames as rendered.
+    fn record(frames: Vec<Vec<GpuCmd>>) -> (Vec<u8>, Vec<Raster<SRgba8>>) {
+        let mut software = Software::new(8, 8);
+        let mut rendered = Vec::new();
+        for cmds in frames.iter() {
+            software.begin(true);
+            software.record(1.0, cmds);
+            for cmd in cmds {
+                software.execute(cmd);
+            }
+            software.post_process();
+            rendered.push(software.frame());
+        }
+        let bytes = recording(&frames).lock().unwrap().bytes.take();
+        (bytes.expect("Recording not finished"), rendered)
+    }
+
+    // Find the recording that the frames request.
+    fn recording(frames: &[Vec<GpuCmd>]) -> Arc<Mutex<RecordingInternal>> {
+        frames
+            .iter()
+            .flatten()
+            .find_map(|cmd| match cmd {
+                GpuCmd::Record(recording, _) => Some(recording.clone()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    // Three frames, recording the last two.
+    fn frames() -> Vec<Vec<GpuCmd>> {
+        let texture = Raster::with_u8_buffer(
+            2,
+            1,
+            vec![255u8, 255, 255, 255, 0, 0, 0, 255],
+        );
+        let graphic = ShaderBuilder::new("", "").graphic();
+        let colors = ShaderBuilder::new("", "").gradient().blend();
+        let shape = ShapeBuilder::new()
+            .vert(&quad(0.0, 0.5))
+            .face(Transform::new());
+        let recording = Arc::new(Mutex::new(RecordingInternal::default()));
+        vec![
+            // Resources made before recording starts are saved with it.
+            vec![
+                GpuCmd::RasterId(texture, 0),
+                GpuCmd::ShaderId(graphic, 0, None),
+                GpuCmd::ShaderId(colors, 1, None),
+                GpuCmd::ShapeId(shape, 0, 0),
+                GpuCmd::GroupId(0),
+                GpuCmd::GroupWrite(0, 0, 0, Transform::new()),
+                GpuCmd::TargetId(1, 4, 4),
+                GpuCmd::Target(1, Box::new(GpuCmd::Clear(1.0, 0.0, 0.0, 1.0))),
+            ],
+            vec![
+                GpuCmd::Record(recording, 2),
+                GpuCmd::DrawGraphic(0, 0, 0),
+                GpuCmd::Triangles(1, triangles(0.5, 0.5, [0.0, 1.0, 0.0, 0.5])),
+            ],
+            vec![
+                GpuCmd::Background(0.0, 0.0, 0.0),
+                GpuCmd::SetBlend(Blend::Additive),
+                GpuCmd::Triangles(
+                    1,
+                    triangles(0.25, 0.5, [0.0, 0.0, 1.0, 1.0]),
+                ),
+                GpuCmd::DrawGraphic(0, 0, 1),
+            ],
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let (bytes, rendered) = record(frames());
+        let replay = Replay::load(bytes.as_slice()).unwrap();
+        assert_eq!(replay.frames(), 2);
+        assert_eq!(replay.aspect(1), 1.0);
+        assert_eq!(replay.draws(0), 2);
+        assert_eq!(
+            replay.commands(1),
+            [
+                "Background(0, 0, 0)",
+                "SetBlend(Additive)",
+                "Triangles(shader: 1, 2 triangles)",
+                "DrawGraphic(shader: 0, group: 0, texture: 1)",
+            ]
+        );
+        for (frame, raster) in rendered[1..].iter().enumerate() {
+            let replayed = replay.render(frame, None, 8);
+            assert_eq!(replayed.as_u8_slice(), raster.as_u8_slice());
+        }
+        // Stopping before the first draw leaves the background.
+        let background = replay.render(0, Some(0), 8);
+        assert!(background
+            .as_u8_slice()
+            .chunks_exact(4)
+            .all(|pixel| pixel == [0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn invalid() {
+        let (bytes, _) = record(frames());
+        let error = |bytes: &[u8]| Replay::load(bytes).err().unwrap().kind();
+        assert_eq!(
+            error(&bytes[..bytes.len() - 1]),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(error(b"CALAREC0"), io::ErrorKind::InvalidData);
+        // Another frame, drawing a group that does or doesn't exist.
+        let with_draw = |group| {
+            let mut out = Writer(bytes[..bytes.len() - 1].to_vec());
+            out.u8(1);
+            out.f32(1.0);
+            out.u32(1);
+            out.cmd(&GpuCmd::Draw(0, group));
+            out.u8(0);
+            out.0
+        };
+        assert_eq!(Replay::load(with_draw(0).as_slice()).unwrap().frames(), 3);
+        assert_eq!(error(&with_draw(7)), io::ErrorKind::InvalidData);
+    }
+}
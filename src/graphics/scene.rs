@@ -0,0 +1,432 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Scene graphs: trees of shapes with transforms relative to their parents,
+//! flattened into `Group`s.
+
+use super::{Canvas, Group, Shader, Shape, Texture, Transform};
+use std::rc::Rc;
+
+/// ID of a node in a [`Scene`](struct.Scene.html).  The IDs of removed nodes
+/// are reused.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// A node's transform, and what's drawn at it.
+#[derive(Clone)]
+pub struct Node {
+    /// Transform relative to the parent node (or the scene, for root nodes).
+    pub transform: Transform,
+    /// Shape drawn at the node, if any.
+    pub shape: Option<Rc<Shape>>,
+    /// Texture the shape is drawn with, if any.
+    pub texture: Option<Rc<Texture>>,
+    /// Texture coordinates (offset and scale), the same as
+    /// [`Group::write_tex()`](../struct.Group.html#method.write_tex).
+    pub tex_coords: ([f32; 2], [f32; 2]),
+    /// RGBA tint of the shader while the shape is drawn.  Only shaders built
+    /// with `tint` have one, so this does nothing for other shaders.
+    pub tint: [f32; 4],
+}
+
+impl Node {
+    /// Create a node with nothing drawn at it (like a joint that other nodes
+    /// are attached to).
+    pub fn new(transform: Transform) -> Self {
+        Node {
+            transform,
+            shape: None,
+            texture: None,
+            tex_coords: ([0.0, 0.0], [1.0, 1.0]),
+            tint: [1.0; 4],
+        }
+    }
+
+    /// Set the shape drawn at the node.
+    pub fn shape(mut self, shape: Rc<Shape>) -> Self {
+        self.shape = Some(shape);
+        self
+    }
+
+    /// Set the texture the shape is drawn with.
+    pub fn texture(mut self, texture: Rc<Texture>) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+
+    /// Set the texture coordinates (offset and scale).
+    pub fn tex_coords(mut self, tex_coords: ([f32; 2], [f32; 2])) -> Self {
+        self.tex_coords = tex_coords;
+        self
+    }
+
+    /// Set the tint of the shader while the shape is drawn.
+    pub fn tint<P: pix::el::Pixel>(mut self, tint: P) -> Self
+    where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        use pix::chan::Channel;
+        use pix::el::Pixel;
+
+        let color: pix::rgb::SRgba32 = tint.convert();
+        self.tint = [
+            color.one().to_f32(),
+            color.two().to_f32(),
+            color.three().to_f32(),
+            color.four().to_f32(),
+        ];
+        self
+    }
+}
+
+// A node, and where it is in the tree.
+struct Entry {
+    node: Node,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    // Transform relative to the scene.
+    world: Transform,
+    // Whether the node has changed since it was last written.
+    dirty: bool,
+    // Batch and index in the batch's group, if the node has been written.
+    slot: Option<(usize, u32)>,
+}
+
+// Shapes drawn with the same texture and tint.
+struct Batch {
+    texture: Option<Rc<Texture>>,
+    tint: [f32; 4],
+    group: Group,
+    // Node at each index of the group.
+    nodes: Vec<usize>,
+}
+
+impl Batch {
+    fn matches(&self, texture: &Option<Rc<Texture>>, tint: [f32; 4]) -> bool {
+        let texture = match (&self.texture, texture) {
+            (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        texture && self.tint == tint
+    }
+}
+
+/// A tree of nodes, each with a transform relative to its parent.
+///
+/// A node's shape is drawn with its world transform: its own transform, and
+/// then its parent's, all the way up to the root.  Nodes are flattened into
+/// one `Group` for each texture and tint, and only the entries of nodes that
+/// have changed (or whose parents have) are rewritten.
+///
+/// ```rust,no_run,ignore
+/// let hand = scene.add(Some(character), Node::new(hand_offset));
+/// let sword = scene.add(Some(hand), Node::new(grip).shape(sword_shape));
+/// // Moving the character moves the sword with it.
+/// scene.set_transform(character, Transform::new().translate(x, y, 0.0));
+/// scene.draw(&mut canvas, &shader);
+/// ```
+#[derive(Default)]
+pub struct Scene {
+    entries: Vec<Option<Entry>>,
+    // Removed entries, to reuse.
+    free: Vec<usize>,
+    roots: Vec<usize>,
+    batches: Vec<Batch>,
+    // Whether any node has changed since the last update.
+    dirty: bool,
+}
+
+impl Scene {
+    /// Create an empty scene.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node as the last child of `parent` (or as a root node, if
+    /// `None`).
+    ///
+    /// # Panics
+    /// If `parent` has been removed.
+    pub fn add(&mut self, parent: Option<NodeId>, node: Node) -> NodeId {
+        let entry = Entry {
+            node,
+            parent: parent.map(|parent| parent.0),
+            children: Vec::new(),
+            world: Transform::new(),
+            dirty: true,
+            slot: None,
+        };
+        let index = if let Some(index) = self.free.pop() {
+            self.entries[index] = Some(entry);
+            index
+        } else {
+            self.entries.push(Some(entry));
+            self.entries.len() - 1
+        };
+        match parent {
+            Some(parent) => self.entry_mut(parent.0).children.push(index),
+            None => self.roots.push(index),
+        }
+        self.dirty = true;
+        NodeId(index)
+    }
+
+    /// Remove a node, and all of its children.
+    pub fn remove(&mut self, node: NodeId) {
+        self.detach(node.0);
+        let mut stack = vec![node.0];
+        while let Some(index) = stack.pop() {
+            self.unslot(index);
+            let entry = self.entries[index].take().unwrap();
+            stack.extend(entry.children);
+            self.free.push(index);
+        }
+    }
+
+    /// Get a node.
+    pub fn node(&self, node: NodeId) -> &Node {
+        &self.entry(node.0).node
+    }
+
+    /// Replace a node (keeping its children).
+    pub fn set(&mut self, node: NodeId, replacement: Node) {
+        // Move it to another batch if its texture or tint changes.
+        if let Some((batch, _)) = self.entry(node.0).slot {
+            let batch = &self.batches[batch];
+            if !batch.matches(&replacement.texture, replacement.tint) {
+                self.unslot(node.0);
+            }
+        }
+        let entry = self.entry_mut(node.0);
+        entry.node = replacement;
+        entry.dirty = true;
+        self.dirty = true;
+    }
+
+    /// Set the transform of a node, relative to its parent.
+    pub fn set_transform(&mut self, node: NodeId, transform: Transform) {
+        let entry = self.entry_mut(node.0);
+        entry.node.transform = transform;
+        entry.dirty = true;
+        self.dirty = true;
+    }
+
+    /// Get the parent of a node, if it's not a root node.
+    pub fn parent(&self, node: NodeId) -> Option<NodeId> {
+        self.entry(node.0).parent.map(NodeId)
+    }
+
+    /// Get the children of a node, in the order they were added.
+    pub fn children(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.entry(node.0).children.iter().cloned().map(NodeId)
+    }
+
+    /// Move a node (and its children) to be the last child of `parent` (or a
+    /// root node, if `None`), like picking up an item.  Its transform is now
+    /// relative to its new parent.
+    ///
+    /// # Panics
+    /// If `parent` is the node, or one of its children.
+    pub fn set_parent(&mut self, node: NodeId, parent: Option<NodeId>) {
+        let mut ancestor = parent.map(|parent| parent.0);
+        while let Some(index) = ancestor {
+            assert_ne!(index, node.0, "Scene node can't be its own parent");
+            ancestor = self.entry(index).parent;
+        }
+        self.detach(node.0);
+        match parent {
+            Some(parent) => self.entry_mut(parent.0).children.push(node.0),
+            None => self.roots.push(node.0),
+        }
+        let entry = self.entry_mut(node.0);
+        entry.parent = parent.map(|parent| parent.0);
+        entry.dirty = true;
+        self.dirty = true;
+    }
+
+    /// Get the transform of a node relative to the scene (like for finding
+    /// where a hand is, to spawn particles there).
+    pub fn world_transform(&self, node: NodeId) -> Transform {
+        let mut entry = self.entry(node.0);
+        let mut transform = entry.node.transform;
+        while let Some(parent) = entry.parent {
+            entry = self.entry(parent);
+            transform = transform * entry.node.transform;
+        }
+        transform
+    }
+
+    /// Rewrite the group entries of nodes that have changed, and of their
+    /// children.  This is done by [`draw()`](#method.draw), so it's only
+    /// needed to draw the groups some other way.
+    pub fn update(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        let mut stack: Vec<(usize, Transform, bool)> = self
+            .roots
+            .iter()
+            .rev()
+            .map(|root| (*root, Transform::new(), false))
+            .collect();
+        while let Some((index, parent, parent_dirty)) = stack.pop() {
+            let entry = self.entries[index].as_mut().unwrap();
+            let dirty = entry.dirty || parent_dirty;
+            if dirty {
+                entry.dirty = false;
+                entry.world = entry.node.transform * parent;
+                self.write(index);
+            }
+            let entry = self.entries[index].as_ref().unwrap();
+            let world = entry.world;
+            stack.extend(
+                entry
+                    .children
+                    .iter()
+                    .rev()
+                    .map(|child| (*child, world, dirty)),
+            );
+        }
+    }
+
+    /// Draw every node with a shape, with `shader` (built with `graphic` for
+    /// nodes with textures).
+    ///
+    /// Nodes are drawn in batches, one for each texture and tint, so nodes
+    /// in different batches aren't drawn in tree order.  Each batch's tint
+    /// is set on `shader` before it's drawn.
+    pub fn draw<C: Canvas>(&mut self, canvas: &mut C, shader: &Shader) {
+        self.update();
+        for batch in self.batches.iter().filter(|batch| !batch.nodes.is_empty())
+        {
+            let [red, green, blue, alpha] = batch.tint;
+            canvas.set_tint(
+                shader,
+                pix::rgb::SRgba32::new(red, green, blue, alpha),
+            );
+            match batch.texture {
+                Some(ref texture) => {
+                    canvas.draw_graphic(shader, &batch.group, texture)
+                }
+                None => canvas.draw(shader, &batch.group),
+            }
+        }
+    }
+
+    fn entry(&self, index: usize) -> &Entry {
+        self.entries[index]
+            .as_ref()
+            .expect("Scene node was removed")
+    }
+
+    fn entry_mut(&mut self, index: usize) -> &mut Entry {
+        self.entries[index]
+            .as_mut()
+            .expect("Scene node was removed")
+    }
+
+    // Remove a node from its parent's children (or the roots).
+    fn detach(&mut self, index: usize) {
+        let siblings = match self.entry(index).parent {
+            Some(parent) => &mut self.entry_mut(parent).children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|sibling| *sibling != index);
+    }
+
+    // Write a node's shape into its batch's group at its world transform.
+    fn write(&mut self, index: usize) {
+        let entry = self.entries[index].as_ref().unwrap();
+        let shape = match entry.node.shape {
+            Some(ref shape) => shape.clone(),
+            None => return self.unslot(index),
+        };
+        let (batch, id) = match entry.slot {
+            Some(slot) => slot,
+            None => {
+                let batch = self.batch(index);
+                let id = self.batches[batch].nodes.len() as u32;
+                self.batches[batch].nodes.push(index);
+                (batch, id)
+            }
+        };
+        let entry = self.entries[index].as_mut().unwrap();
+        entry.slot = Some((batch, id));
+        let group = &mut self.batches[batch].group;
+        if entry.node.texture.is_some() {
+            group.write_tex(id, &shape, &entry.world, entry.node.tex_coords);
+        } else {
+            group.write(id, &shape, &entry.world);
+        }
+    }
+
+    // Find (or make) the batch for a node's texture and tint.
+    fn batch(&mut self, index: usize) -> usize {
+        let node = &self.entries[index].as_ref().unwrap().node;
+        let (texture, tint) = (&node.texture, node.tint);
+        if let Some(batch) = self.batches.iter().position(|batch| {
+            !batch.nodes.is_empty() && batch.matches(texture, tint)
+        }) {
+            return batch;
+        }
+        let batch = match self
+            .batches
+            .iter()
+            .position(|batch| batch.nodes.is_empty())
+        {
+            Some(batch) => batch,
+            None => {
+                self.batches.push(Batch {
+                    texture: None,
+                    tint,
+                    group: Group::new(),
+                    nodes: Vec::new(),
+                });
+                self.batches.len() - 1
+            }
+        };
+        self.batches[batch].texture = texture.clone();
+        self.batches[batch].tint = tint;
+        batch
+    }
+
+    // Remove a node's shape from its batch's group, if it's there.
+    fn unslot(&mut self, index: usize) {
+        let slot = self.entries[index]
+            .as_mut()
+            .and_then(|entry| entry.slot.take());
+        let (batch, id) = match slot {
+            Some(slot) => slot,
+            None => return,
+        };
+        let Batch {
+            texture,
+            group,
+            nodes,
+            ..
+        } = &mut self.batches[batch];
+        // Swap the node to the end, so only one other node moves.
+        let last = nodes.len() as u32 - 1;
+        if id != last {
+            group.swap(id, last);
+            nodes.swap(id as usize, last as usize);
+            let moved = nodes[id as usize];
+            self.entries[moved].as_mut().unwrap().slot = Some((batch, id));
+        }
+        group.remove(last);
+        nodes.pop();
+        if nodes.is_empty() {
+            // Let go of the texture, so the batch can be reused.
+            *texture = None;
+        }
+    }
+}
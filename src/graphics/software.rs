@@ -0,0 +1,1485 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Software rendering backend, which executes `GpuCmd`s into a `Raster`.
+//!
+//! This mirrors what the `window` crate's OpenGL backend does with the same
+//! commands, so that output rendered headlessly matches what appears on the
+//! screen (nearest texture sampling, back-face culling, `LESS` depth test and
+//! `SRC_ALPHA, ONE_MINUS_SRC_ALPHA` blending).
+//!
+//! It's written here instead of on top of `footile` (which text and vector
+//! paths are plotted with), because `footile` only fills 2D paths with
+//! anti-aliased coverage.  Matching the OpenGL backend needs a depth buffer,
+//! clipping against the near and far planes, perspective-correct texture
+//! coordinates and colors, and pixels that are covered (then sampled and
+//! blended) exactly where OpenGL covers them: at their centers, with shared
+//! edges belonging to only one triangle.
+
+use super::effect::EffectInternal;
+use super::primitives::STRIDE;
+use super::replay::{self, invalid, Live, Reader, Recorder, Writer};
+use super::transform::{coords, projection};
+use super::{
+    Blend, CaptureInternal, GpuCmd, Rect, ShapeBuilder, Slots, Stats,
+    Transform, Usage, VertexFormat,
+};
+use pix::{rgb::SRgba8, Raster};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+// Column-major 4x4 matrix.
+type Mat4 = [[f32; 4]; 4];
+
+// Convert a color channel to 8 bits.
+fn to_u8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+// A shader program, described by the features `res` generated it with.
+struct SoftShader {
+    tint: Option<[f32; 4]>,
+    gradient: bool,
+    graphic: bool,
+    depth: bool,
+    blend: bool,
+}
+
+impl SoftShader {
+    // How the vertices of the shader's shapes are laid out.
+    fn format(&self) -> VertexFormat {
+        VertexFormat::new(self.depth, self.graphic, self.gradient, self.blend)
+    }
+}
+
+// A shape, with vertices laid out as position, texture coordinates, color.
+#[derive(Clone)]
+pub(super) struct SoftShape {
+    dimensions: usize,
+    components: usize,
+    stride: usize,
+    vertices: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl SoftShape {
+    // Lay out the vertices of a shape for a shader, applying the transforms of
+    // its faces.
+    pub(super) fn new(builder: &ShapeBuilder, format: VertexFormat) -> Self {
+        let dimensions = format.dimensions;
+        let stride = format.stride();
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut current: &[f32] = &[];
+        for face in builder.faces.iter() {
+            if let Some(ref verts) = face.vertices {
+                current = verts.as_slice();
+            }
+            let transform = if let Some(transform) = face.transform {
+                transform
+            } else {
+                continue;
+            };
+            for vertex in current.chunks_exact(stride) {
+                let z = if dimensions == 3 { vertex[2] } else { 0.0 };
+                let pos = transform * [vertex[0], vertex[1], z];
+                indices.push((vertices.len() / stride) as u32);
+                vertices.extend_from_slice(&pos[..dimensions]);
+                vertices.extend_from_slice(&vertex[dimensions..]);
+            }
+        }
+        SoftShape {
+            dimensions,
+            components: format.components,
+            stride,
+            vertices,
+            indices,
+        }
+    }
+
+    // Get the vertices, as a list of triangles.
+    pub(super) fn vertices(&self) -> &[f32] {
+        &self.vertices
+    }
+
+    // Get the number of bytes of vertex data.
+    pub(super) fn bytes(&self) -> usize {
+        std::mem::size_of_val(self.vertices.as_slice())
+            + std::mem::size_of_val(self.indices.as_slice())
+    }
+
+    // Copy the shape with its colors multiplied by `tint`, or get `None` if it
+    // has no colors.
+    pub(super) fn tinted(&self, tint: [f32; 4]) -> Option<Self> {
+        if self.components == 0 {
+            return None;
+        }
+        let mut shape = self.clone();
+        for vertex in shape.vertices.chunks_exact_mut(self.stride) {
+            let color = &mut vertex[self.stride - self.components..];
+            for (c, t) in color.iter_mut().zip(tint.iter()) {
+                *c *= t;
+            }
+        }
+        Some(shape)
+    }
+}
+
+// One shape written into a group.
+#[derive(Default)]
+struct Entry {
+    vertices: Vec<f32>,
+    indices: Vec<u32>,
+    stride: usize,
+    dimensions: usize,
+    components: usize,
+    graphic: bool,
+}
+
+impl Entry {
+    fn new(
+        shape: &SoftShape,
+        transform: &Transform,
+        tex_coords: ([f32; 2], [f32; 2]),
+        tint: [f32; 4],
+    ) -> Self {
+        let graphic = shape.dimensions + shape.components + 2 == shape.stride;
+        let mut vertices = Vec::with_capacity(shape.vertices.len());
+        for vertex in shape.vertices.chunks_exact(shape.stride) {
+            let z = if shape.dimensions == 3 {
+                vertex[2]
+            } else {
+                0.0
+            };
+            let pos = *transform * [vertex[0], vertex[1], z];
+            vertices.extend_from_slice(&pos[..shape.dimensions]);
+            if graphic {
+                let tex = &vertex[shape.dimensions..];
+                vertices.push(tex[0] * tex_coords.1[0] + tex_coords.0[0]);
+                vertices.push(tex[1] * tex_coords.1[1] + tex_coords.0[1]);
+            }
+            let color = &vertex[shape.stride - shape.components..];
+            vertices.extend(color.iter().zip(tint.iter()).map(|(c, t)| c * t));
+        }
+        Entry {
+            vertices,
+            indices: shape.indices.clone(),
+            stride: shape.stride,
+            dimensions: shape.dimensions,
+            components: shape.components,
+            graphic,
+        }
+    }
+
+    // Create an entry from colored 2D triangles, laid out like the vertices
+    // of `Canvas::draw_triangles()`.
+    fn triangles(vertices: Vec<f32>) -> Self {
+        let count = (vertices.len() / (STRIDE * 3) * 3) as u32;
+        Entry {
+            vertices,
+            indices: (0..count).collect(),
+            stride: STRIDE,
+            dimensions: 2,
+            components: 4,
+            graphic: false,
+        }
+    }
+
+    // Get vertex `index` in homogeneous coordinates, and its attributes
+    // (texture coordinates, then RGBA color).
+    fn vertex(&self, index: u32) -> ([f32; 4], [f32; 6]) {
+        let v = &self.vertices[index as usize * self.stride..];
+        let z = if self.dimensions == 3 { v[2] } else { 0.0 };
+        let mut attr = [0.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+        let mut offset = self.dimensions;
+        if self.graphic {
+            attr[0] = v[offset];
+            attr[1] = v[offset + 1];
+            offset += 2;
+        }
+        attr[2..2 + self.components]
+            .copy_from_slice(&v[offset..offset + self.components]);
+        ([v[0], v[1], z, 1.0], attr)
+    }
+}
+
+// A vertex after transformation into clip space.
+#[derive(Copy, Clone)]
+struct ClipVertex {
+    clip: [f32; 4],
+    attr: [f32; 6],
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mut clip = [0.0; 4];
+        let mut attr = [0.0; 6];
+        for (i, c) in clip.iter_mut().enumerate() {
+            *c = self.clip[i] + (other.clip[i] - self.clip[i]) * t;
+        }
+        for (i, a) in attr.iter_mut().enumerate() {
+            *a = self.attr[i] + (other.attr[i] - self.attr[i]) * t;
+        }
+        ClipVertex { clip, attr }
+    }
+}
+
+// A vertex in window coordinates.
+#[derive(Copy, Clone)]
+struct ScreenVertex {
+    x: f32,
+    y: f32,
+    z: f32,
+    inv_w: f32,
+    attr: [f32; 6],
+}
+
+// Clip a polygon against the near and far planes.
+fn clip_polygon(polygon: Vec<ClipVertex>) -> Vec<ClipVertex> {
+    let mut polygon = polygon;
+    // Signed distance to near plane (z >= -w), then far plane (z <= w).
+    let planes: [fn(&[f32; 4]) -> f32; 2] = [|c| c[2] + c[3], |c| c[3] - c[2]];
+    for plane in planes.iter() {
+        if polygon.is_empty() {
+            break;
+        }
+        let mut output = Vec::with_capacity(polygon.len() + 1);
+        for (i, current) in polygon.iter().enumerate() {
+            let next = &polygon[(i + 1) % polygon.len()];
+            let (d0, d1) = (plane(&current.clip), plane(&next.clip));
+            if d0 >= 0.0 {
+                output.push(*current);
+            }
+            if (d0 >= 0.0) != (d1 >= 0.0) {
+                output.push(current.lerp(next, d0 / (d0 - d1)));
+            }
+        }
+        polygon = output;
+    }
+    polygon
+}
+
+// The state of a render target that isn't currently being drawn on.
+struct Surface {
+    depth: Vec<f32>,
+    camera: Mat4,
+    viewport: Option<Rect>,
+    scissor: Option<Rect>,
+    blend: Blend,
+    depth_test: bool,
+    depth_write: bool,
+}
+
+/// Software renderer.
+pub(super) struct Software {
+    // Output raster.
+    raster: Raster<SRgba8>,
+    // Depth buffer.
+    depth: Vec<f32>,
+    // Background (clear) color.
+    background: [f32; 3],
+    // Camera matrix.
+    camera: Mat4,
+    // Part of the raster that's drawn into.
+    viewport: Option<Rect>,
+    // Part of the raster that drawing is clipped to.
+    scissor: Option<Rect>,
+    // How colors are blended, for shaders with blending.
+    blend: Blend,
+    // Depth state, for shaders with depth.
+    depth_test: bool,
+    depth_write: bool,
+    // Whether or not draw commands are rasterized this frame.
+    drawing: bool,
+    // Captures waiting for the current frame to finish.
+    captures: Vec<Arc<Mutex<CaptureInternal>>>,
+    // Recordings in progress.
+    recorders: Vec<Recorder>,
+    // Render target currently being drawn on (`None` for the frame).
+    target: Option<u32>,
+    // Render targets, keyed by the ID of their raster.
+    targets: HashMap<u32, Surface>,
+    // Effects run over the finished frame.
+    effects: Vec<EffectInternal>,
+    rasters: Slots<Raster<SRgba8>>,
+    shaders: Slots<SoftShader>,
+    shapes: Slots<SoftShape>,
+    groups: Slots<Vec<Entry>>,
+}
+
+impl Software {
+    /// Create a software renderer that renders `width` × `height` frames.
+    pub(super) fn new(width: u16, height: u16) -> Self {
+        let (width, height) = (u32::from(width), u32::from(height));
+        Software {
+            raster: Raster::with_clear(width, height),
+            depth: vec![1.0; (width * height) as usize],
+            // Same default as the OpenGL backend.
+            background: [0.0, 0.0, 1.0],
+            camera: Transform::new().mat4(),
+            viewport: None,
+            scissor: None,
+            blend: Blend::Alpha,
+            depth_test: true,
+            depth_write: true,
+            drawing: true,
+            captures: Vec::new(),
+            recorders: Vec::new(),
+            target: None,
+            targets: HashMap::new(),
+            effects: Vec::new(),
+            rasters: Slots::new(),
+            shaders: Slots::new(),
+            shapes: Slots::new(),
+            groups: Slots::new(),
+        }
+    }
+
+    /// Count the resources that are alive, and how much memory they use.
+    pub(super) fn stats(&self) -> Stats {
+        Stats {
+            textures: Usage::of(self.rasters.iter(), |raster| {
+                raster.as_u8_slice().len()
+            }),
+            shaders: Usage::of(self.shaders.iter(), |_| 0),
+            shapes: Usage::of(self.shapes.iter(), SoftShape::bytes),
+            groups: Usage::of(self.groups.iter(), |entries| {
+                entries
+                    .iter()
+                    .map(|entry| {
+                        std::mem::size_of_val(entry.vertices.as_slice())
+                            + std::mem::size_of_val(entry.indices.as_slice())
+                    })
+                    .sum()
+            }),
+        }
+    }
+
+    /// Get the aspect ratio: `height / width`.
+    pub(super) fn aspect(&self) -> f32 {
+        self.raster.height() as f32 / self.raster.width() as f32
+    }
+
+    /// Change the size of the frames that are rendered.
+    pub(super) fn resize(&mut self, width: u16, height: u16) {
+        let (width, height) = (u32::from(width), u32::from(height));
+        if self.raster.width() != width || self.raster.height() != height {
+            self.raster = Raster::with_clear(width, height);
+            self.depth = vec![1.0; (width * height) as usize];
+        }
+    }
+
+    /// Start a new frame, clearing to the background color.  If `drawing` is
+    /// false, only resources are updated for this frame and nothing is drawn.
+    pub(super) fn begin(&mut self, drawing: bool) {
+        self.drawing = drawing;
+        self.reset();
+        if drawing {
+            let [r, g, b] = self.background;
+            self.clear([r, g, b, 1.0]);
+        }
+    }
+
+    // Go back to the default state for the frame or render target.
+    fn reset(&mut self) {
+        self.viewport = None;
+        self.scissor = None;
+        self.blend = Blend::Alpha;
+        self.depth_test = true;
+        self.depth_write = true;
+    }
+
+    // Clear the raster being drawn on, and its depth buffer.
+    fn clear(&mut self, color: [f32; 4]) {
+        let [r, g, b, a] = color;
+        let clear = SRgba8::new(to_u8(r), to_u8(g), to_u8(b), to_u8(a));
+        for pixel in self.raster.pixels_mut() {
+            *pixel = clear;
+        }
+        for depth in self.depth.iter_mut() {
+            *depth = 1.0;
+        }
+    }
+
+    /// Get a copy of the frame, as it's displayed.
+    pub(super) fn frame(&self) -> Raster<SRgba8> {
+        let mut raster = self.raster.clone();
+        // The frame is displayed opaque.
+        for pixel in raster.as_u8_slice_mut().chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+        raster
+    }
+
+    /// Run the effects over the frame.  Shader source can't be run, so each
+    /// effect multiplies the frame by its tint (sampling the copy of the frame
+    /// at the same size leaves it as it is).
+    pub(super) fn post_process(&mut self) {
+        if !self.drawing {
+            return;
+        }
+        for effect in self.effects.iter() {
+            let shader = &mut self.shaders[effect.shader];
+            if shader.tint.is_none() {
+                continue;
+            }
+            shader.tint = Some(effect.tint);
+            for pixel in self.raster.as_u8_slice_mut().chunks_exact_mut(4) {
+                for (channel, tint) in pixel.iter_mut().zip(effect.tint.iter())
+                {
+                    *channel = to_u8(f32::from(*channel) / 255.0 * tint);
+                }
+            }
+        }
+    }
+
+    /// Record a frame's commands for the recordings in progress, starting
+    /// any that they request.  Call this after `begin()`, before the commands
+    /// are executed.
+    pub(super) fn record(&mut self, aspect: f32, cmds: &[GpuCmd]) {
+        let mut recorders = std::mem::take(&mut self.recorders);
+        replay::start(&mut recorders, cmds, self);
+        replay::record(&mut recorders, aspect, cmds);
+        self.recorders = recorders;
+    }
+
+    /// Leave empty slots for resource IDs that have been freed, up to the
+    /// number of raster, shader, shape and group IDs in use.
+    pub(super) fn reserve(&mut self, ids: [usize; 4]) {
+        let [rasters, shaders, shapes, groups] = ids;
+        self.rasters.reserve(rasters);
+        self.shaders.reserve(shaders);
+        self.shapes.reserve(shapes);
+        self.groups.reserve(groups);
+    }
+
+    /// Store a shape that's already laid out.
+    pub(super) fn store_shape(&mut self, id: u32, shape: SoftShape) {
+        self.shapes.store(id, shape);
+    }
+
+    /// Store a group of shapes that are already laid out, with the transforms
+    /// and texture coordinates they're written with.
+    pub(super) fn store_group<'a>(
+        &mut self,
+        id: u32,
+        writes: impl Iterator<
+            Item = (&'a SoftShape, &'a Transform, ([f32; 2], [f32; 2])),
+        >,
+    ) {
+        let entries = writes
+            .map(|(shape, transform, tex_coords)| {
+                Entry::new(shape, transform, tex_coords, [1.0; 4])
+            })
+            .collect();
+        self.groups.store(id, entries);
+    }
+
+    /// Save the resources, and the state that's kept between frames.
+    pub(super) fn save(&self, out: &mut Writer) {
+        fn slots<T>(
+            out: &mut Writer,
+            slots: &Slots<T>,
+            f: fn(&mut Writer, &T),
+        ) {
+            out.u32(slots.0.len() as u32);
+            for slot in slots.0.iter() {
+                out.bool(slot.is_some());
+                if let Some(item) = slot {
+                    f(out, item);
+                }
+            }
+        }
+        for value in self.background.iter() {
+            out.f32(*value);
+        }
+        out.mat4(&self.camera);
+        slots(out, &self.rasters, |out, raster| out.raster(raster));
+        slots(out, &self.shaders, |out, shader| {
+            out.bool(shader.tint.is_some());
+            for value in shader.tint.unwrap_or_default().iter() {
+                out.f32(*value);
+            }
+            out.bool(shader.gradient);
+            out.bool(shader.graphic);
+            out.bool(shader.depth);
+            out.bool(shader.blend);
+        });
+        slots(out, &self.shapes, |out, shape| {
+            out.u32(shape.dimensions as u32);
+            out.u32(shape.components as u32);
+            out.u32(shape.stride as u32);
+            out.f32s(&shape.vertices);
+            out.u32s(&shape.indices);
+        });
+        slots(out, &self.groups, |out, entries| {
+            out.u32(entries.len() as u32);
+            for entry in entries {
+                out.f32s(&entry.vertices);
+                out.u32s(&entry.indices);
+                out.u32(entry.stride as u32);
+                out.u32(entry.dimensions as u32);
+                out.u32(entry.components as u32);
+                out.bool(entry.graphic);
+            }
+        });
+        out.u32(self.targets.len() as u32);
+        for (id, surface) in self.targets.iter() {
+            out.u32(*id);
+            out.f32s(&surface.depth);
+            out.mat4(&surface.camera);
+            out.rect(surface.viewport);
+            out.rect(surface.scissor);
+            out.blend(surface.blend);
+            out.bool(surface.depth_test);
+            out.bool(surface.depth_write);
+        }
+        out.effects(&self.effects);
+    }
+
+    /// Load resources and state saved with `save()`, into a renderer with no
+    /// frame size.
+    pub(super) fn load(input: &mut Reader<'_>) -> io::Result<Self> {
+        fn slots<'a, T>(
+            input: &mut Reader<'a>,
+            f: fn(&mut Reader<'a>) -> io::Result<T>,
+        ) -> io::Result<Slots<T>> {
+            let len = input.u32()?;
+            let mut slots = Vec::new();
+            for _ in 0..len {
+                slots.push(if input.bool()? { Some(f(input)?) } else { None });
+            }
+            Ok(Slots(slots))
+        }
+        let mut software = Software::new(0, 0);
+        software.background = [input.f32()?, input.f32()?, input.f32()?];
+        software.camera = input.mat4()?;
+        software.rasters = slots(input, |input| input.raster())?;
+        software.shaders = slots(input, |input| {
+            let tint = input.bool()?;
+            let color =
+                [input.f32()?, input.f32()?, input.f32()?, input.f32()?];
+            Ok(SoftShader {
+                tint: if tint { Some(color) } else { None },
+                gradient: input.bool()?,
+                graphic: input.bool()?,
+                depth: input.bool()?,
+                blend: input.bool()?,
+            })
+        })?;
+        software.shapes = slots(input, |input| {
+            let shape = SoftShape {
+                dimensions: input.u32()? as usize,
+                components: input.u32()? as usize,
+                stride: input.u32()? as usize,
+                vertices: input.f32s()?,
+                indices: input.u32s()?,
+            };
+            let graphic = shape.dimensions + shape.components + 2;
+            check_layout(
+                shape.dimensions,
+                shape.components,
+                shape.stride,
+                shape.stride == graphic,
+                &shape.vertices,
+                &shape.indices,
+            )?;
+            Ok(shape)
+        })?;
+        software.groups = slots(input, |input| {
+            let len = input.u32()?;
+            let mut entries = Vec::new();
+            for _ in 0..len {
+                let entry = Entry {
+                    vertices: input.f32s()?,
+                    indices: input.u32s()?,
+                    stride: input.u32()? as usize,
+                    dimensions: input.u32()? as usize,
+                    components: input.u32()? as usize,
+                    graphic: input.bool()?,
+                };
+                check_layout(
+                    entry.dimensions,
+                    entry.components,
+                    entry.stride,
+                    entry.graphic,
+                    &entry.vertices,
+                    &entry.indices,
+                )?;
+                entries.push(entry);
+            }
+            Ok(entries)
+        })?;
+        for _ in 0..input.u32()? {
+            let id = input.u32()?;
+            let raster = software
+                .rasters
+                .0
+                .get(id as usize)
+                .and_then(Option::as_ref)
+                .ok_or_else(|| invalid("render target has no raster"))?;
+            let pixels = raster.width() as usize * raster.height() as usize;
+            let surface = Surface {
+                depth: input.f32s()?,
+                camera: input.mat4()?,
+                viewport: input.rect()?,
+                scissor: input.rect()?,
+                blend: input.blend()?,
+                depth_test: input.bool()?,
+                depth_write: input.bool()?,
+            };
+            if surface.depth.len() != pixels {
+                return Err(invalid("render target is the wrong size"));
+            }
+            software.targets.insert(id, surface);
+        }
+        software.effects = input.effects()?;
+        let live = software.live();
+        for effect in software.effects.iter() {
+            let shader = live.shaders.get(effect.shader as usize);
+            let lut = effect.lut.map(|id| live.rasters.get(id as usize));
+            if shader != Some(&true)
+                || lut.unwrap_or(Some(&true)) != Some(&true)
+            {
+                return Err(invalid("effect uses a deleted resource"));
+            }
+        }
+        Ok(software)
+    }
+
+    /// Get which resources are alive, for checking the commands of a
+    /// recording before they're replayed.
+    pub(super) fn live(&self) -> Live {
+        fn live<T>(slots: &Slots<T>) -> Vec<bool> {
+            slots.0.iter().map(Option::is_some).collect()
+        }
+        Live {
+            rasters: live(&self.rasters),
+            targets: self.targets.keys().cloned().collect::<HashSet<u32>>(),
+            shaders: live(&self.shaders),
+            shapes: live(&self.shapes),
+            groups: self
+                .groups
+                .0
+                .iter()
+                .map(|entries| entries.as_ref().map(|e| e.len() as u32))
+                .collect(),
+        }
+    }
+
+    /// Finish the frame, handing it to any captures that were requested.
+    pub(super) fn finish(&mut self) {
+        for capture in std::mem::take(&mut self.captures) {
+            capture.lock().unwrap().finish(self.frame());
+        }
+    }
+
+    /// Execute a command.
+    pub(super) fn execute(&mut self, cmd: &GpuCmd) {
+        use GpuCmd::*;
+        match *cmd {
+            Background(r, g, b) => self.background = [r, g, b],
+            Draw(shader, group) => {
+                if self.drawing {
+                    self.draw(shader, group, None)
+                }
+            }
+            DrawGraphic(shader, group, raster) => {
+                if self.drawing {
+                    self.draw(shader, group, Some(raster))
+                }
+            }
+            Triangles(shader, ref vertices) => {
+                if self.drawing {
+                    let entry = Entry::triangles(vertices.clone());
+                    self.draw_entries(shader, &[entry], None)
+                }
+            }
+            SetCamera(camera) => self.camera = camera.mat4(),
+            SetViewport(viewport) => self.viewport = viewport,
+            SetScissor(scissor) => self.scissor = scissor,
+            SetBlend(blend) => self.blend = blend,
+            SetDepthTest(test) => self.depth_test = test,
+            SetDepthWrite(write) => self.depth_write = write,
+            SetTint(shader, tint) => {
+                let shader = &mut self.shaders[shader];
+                if shader.tint.is_some() {
+                    shader.tint = Some(tint);
+                }
+            }
+            Capture(ref capture) => self.captures.push(capture.clone()),
+            Record(..) => {}
+            SetEffects(ref effects) => self.effects = effects.clone(),
+            Clear(r, g, b, a) => {
+                self.reset();
+                self.clear([r, g, b, a]);
+            }
+            TargetId(id, width, height) => {
+                let (width, height) = (u32::from(width), u32::from(height));
+                self.rasters.store(id, Raster::with_clear(width, height));
+                let surface = Surface {
+                    depth: vec![1.0; (width * height) as usize],
+                    camera: Transform::new().mat4(),
+                    viewport: None,
+                    scissor: None,
+                    blend: Blend::Alpha,
+                    depth_test: true,
+                    depth_write: true,
+                };
+                self.targets.insert(id, surface);
+            }
+            Target(id, ref cmd) => self.execute_target(id, cmd),
+            RasterId(ref raster, id) => self.rasters.store(id, raster.clone()),
+            RasterUpdate(id, region, ref raster) => {
+                self.rasters[id].copy_raster(region, raster, ())
+            }
+            RasterResize(id, width, height) => {
+                let (width, height) = (u32::from(width), u32::from(height));
+                let old = &self.rasters[id];
+                let mut raster = Raster::with_clear(width, height);
+                raster.copy_raster((0, 0, width, height), old, ());
+                self.rasters[id] = raster;
+            }
+            ShaderId(ref builder, id, _) => {
+                let shader = SoftShader {
+                    // Uniforms start out zeroed.
+                    tint: if builder.tint { Some([0.0; 4]) } else { None },
+                    gradient: builder.gradient,
+                    graphic: builder.graphic,
+                    depth: builder.depth,
+                    blend: builder.blend,
+                };
+                self.shaders.store(id, shader);
+            }
+            ShapeId(ref builder, id, shader) => {
+                let shape =
+                    SoftShape::new(builder, self.shaders[shader].format());
+                self.shapes.store(id, shape);
+            }
+            GroupId(id) => self.groups.store(id, Vec::new()),
+            RasterDelete(id) => {
+                self.rasters.delete(id);
+                self.targets.remove(&id);
+                self.effects.retain(|effect| effect.lut != Some(id));
+            }
+            ShaderDelete(id) => {
+                self.shaders.delete(id);
+                self.effects.retain(|effect| effect.shader != id);
+            }
+            ShapeDelete(id) => {
+                self.shapes.delete(id);
+            }
+            GroupDelete(id) => {
+                self.groups.delete(id);
+            }
+            GroupWrite(group, id, shape, transform) => {
+                self.group_write(
+                    group,
+                    id,
+                    shape,
+                    transform,
+                    ([0.0, 0.0], [1.0, 1.0]),
+                );
+            }
+            GroupWriteTex(group, id, shape, transform, tex_coords) => {
+                self.group_write(group, id, shape, transform, tex_coords);
+            }
+            GroupRemove(group, id) => {
+                self.groups[group].remove(id as usize);
+            }
+            GroupSwap(group, a, b) => {
+                self.groups[group].swap(a as usize, b as usize);
+            }
+            GroupClear(group) => {
+                self.groups[group].clear();
+            }
+            GroupInstances(group, shape, _, ref instances) => {
+                let shape = &self.shapes[shape];
+                self.groups[group] = instances
+                    .iter()
+                    .map(|instance| {
+                        Entry::new(
+                            shape,
+                            &instance.transform,
+                            instance.tex_coords,
+                            instance.tint,
+                        )
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    // Execute a command on a render target, by swapping it in for the frame.
+    fn execute_target(&mut self, id: u32, cmd: &GpuCmd) {
+        let mut surface = if let Some(surface) = self.targets.remove(&id) {
+            surface
+        } else {
+            return;
+        };
+        self.swap_target(id, &mut surface);
+        let drawing = self.drawing;
+        self.drawing = true;
+        self.target = Some(id);
+        self.execute(cmd);
+        self.target = None;
+        self.drawing = drawing;
+        self.swap_target(id, &mut surface);
+        self.targets.insert(id, surface);
+    }
+
+    fn swap_target(&mut self, id: u32, surface: &mut Surface) {
+        std::mem::swap(&mut self.raster, &mut self.rasters[id]);
+        std::mem::swap(&mut self.depth, &mut surface.depth);
+        std::mem::swap(&mut self.camera, &mut surface.camera);
+        std::mem::swap(&mut self.viewport, &mut surface.viewport);
+        std::mem::swap(&mut self.scissor, &mut surface.scissor);
+        std::mem::swap(&mut self.blend, &mut surface.blend);
+        std::mem::swap(&mut self.depth_test, &mut surface.depth_test);
+        std::mem::swap(&mut self.depth_write, &mut surface.depth_write);
+    }
+
+    fn group_write(
+        &mut self,
+        group: u32,
+        id: u32,
+        shape: u32,
+        transform: Transform,
+        tex_coords: ([f32; 2], [f32; 2]),
+    ) {
+        let entry =
+            Entry::new(&self.shapes[shape], &transform, tex_coords, [1.0; 4]);
+        let entries = &mut self.groups[group];
+        if id as usize >= entries.len() {
+            entries.resize_with(id as usize + 1, Default::default);
+        }
+        entries[id as usize] = entry;
+    }
+
+    // Draw every triangle in a group.
+    fn draw(&mut self, shader: u32, group: u32, raster: Option<u32>) {
+        // Take the entries out, so they can be read while drawing.
+        let entries = std::mem::take(&mut self.groups[group]);
+        self.draw_entries(shader, &entries, raster);
+        self.groups[group] = entries;
+    }
+
+    // Draw every triangle in a list of entries.
+    fn draw_entries(
+        &mut self,
+        shader: u32,
+        entries: &[Entry],
+        raster: Option<u32>,
+    ) {
+        // A render target can't be drawn onto itself.
+        if raster.is_some() && raster == self.target {
+            return;
+        }
+        let height = self.aspect();
+        let shader = &self.shaders[shader];
+        let rasters = &self.rasters;
+        let texture = raster.map(|id| &rasters[id]);
+        let camera = Transform::from_mat4(self.camera);
+        let camera = if let Some(viewport) = self.viewport {
+            camera * viewport.camera(height)
+        } else {
+            camera
+        };
+        let matrix = coords(height) * camera * projection(height, shader.depth);
+        let (width, rows) = (self.raster.width(), self.raster.height());
+        let view = pixels(self.viewport, width, rows);
+        // Everything outside of the viewport is clipped along with the
+        // triangles, so only the scissor rectangle needs to be clipped to.
+        let (x, y, w, h) = pixels(self.scissor, width, rows);
+        let left = x.max(0.0).round() as u32;
+        let top = y.max(0.0).round() as u32;
+        let right = (x + w).min(width as f32).round() as u32;
+        let bottom = (y + h).min(rows as f32).round() as u32;
+        let pass = Pass {
+            view,
+            bounds: (left, top, right.max(left), bottom.max(top)),
+            blend: self.blend,
+            depth_test: self.depth_test,
+            depth_write: self.depth_write,
+        };
+
+        for entry in entries {
+            for triangle in entry.indices.chunks_exact(3) {
+                let polygon = triangle
+                    .iter()
+                    .map(|index| {
+                        let (pos, attr) = entry.vertex(*index);
+                        ClipVertex {
+                            clip: matrix.apply(pos),
+                            attr,
+                        }
+                    })
+                    .collect();
+                let polygon = clip_polygon(polygon);
+                for i in 2..polygon.len() {
+                    rasterize(
+                        &mut self.raster,
+                        &mut self.depth,
+                        shader,
+                        texture,
+                        &pass,
+                        [polygon[0], polygon[i - 1], polygon[i]],
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Check that loaded vertices are laid out the way a shader could have laid
+// them out, and that their indices are in bounds.
+fn check_layout(
+    dimensions: usize,
+    components: usize,
+    stride: usize,
+    graphic: bool,
+    vertices: &[f32],
+    indices: &[u32],
+) -> io::Result<()> {
+    let tex = if graphic { 2 } else { 0 };
+    if !matches!(dimensions, 2 | 3)
+        || !matches!(components, 0 | 3 | 4)
+        || stride != dimensions + components + tex
+        || !vertices.len().is_multiple_of(stride)
+    {
+        return Err(invalid("vertices are laid out wrong"));
+    }
+    let count = vertices.len() / stride;
+    if indices.iter().any(|index| *index as usize >= count) {
+        return Err(invalid("vertex index out of bounds"));
+    }
+    Ok(())
+}
+
+// Convert a rectangle to pixels on a `width` × `height` raster, as
+// (x, y, width, height).
+fn pixels(rect: Option<Rect>, width: u32, height: u32) -> (f32, f32, f32, f32) {
+    if let Some(rect) = rect {
+        let scale = width as f32;
+        (
+            (rect.x * scale).round(),
+            (rect.y * scale).round(),
+            (rect.width * scale).round().max(0.0),
+            (rect.height * scale).round().max(0.0),
+        )
+    } else {
+        (0.0, 0.0, width as f32, height as f32)
+    }
+}
+
+// State that triangles are rasterized with.
+struct Pass {
+    // Rectangle that clip space is mapped onto (x, y, width, height).
+    view: (f32, f32, f32, f32),
+    // Rectangle that pixels are drawn within (left, top, right, bottom).
+    bounds: (u32, u32, u32, u32),
+    blend: Blend,
+    depth_test: bool,
+    depth_write: bool,
+}
+
+// Rasterize one triangle (already clipped) onto the raster.
+fn rasterize(
+    raster: &mut Raster<SRgba8>,
+    depth: &mut [f32],
+    shader: &SoftShader,
+    texture: Option<&Raster<SRgba8>>,
+    pass: &Pass,
+    triangle: [ClipVertex; 3],
+) {
+    let (view_x, view_y, width, height) = pass.view;
+    let mut verts = [ScreenVertex {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        inv_w: 0.0,
+        attr: [0.0; 6],
+    }; 3];
+    for (screen, vertex) in verts.iter_mut().zip(triangle.iter()) {
+        let [x, y, z, w] = vertex.clip;
+        if w <= 0.0 {
+            return;
+        }
+        let inv_w = 1.0 / w;
+        *screen = ScreenVertex {
+            x: view_x + (x * inv_w + 1.0) * 0.5 * width,
+            y: view_y + (1.0 - y * inv_w) * 0.5 * height,
+            z: z * inv_w * 0.5 + 0.5,
+            inv_w,
+            attr: vertex.attr,
+        };
+    }
+
+    // Cull back faces (clockwise on the screen, since Y is flipped).
+    let edge = |a: &ScreenVertex, b: &ScreenVertex, x: f32, y: f32| {
+        (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+    };
+    let area = edge(&verts[0], &verts[1], verts[2].x, verts[2].y);
+    if area >= 0.0 {
+        return;
+    }
+
+    // Find the pixels the triangle could cover.
+    let (left, top, right, bottom) = pass.bounds;
+    let (left_f, top_f) = (left as f32, top as f32);
+    let (right_f, bottom_f) = (right as f32, bottom as f32);
+    let min_x = verts
+        .iter()
+        .map(|v| v.x)
+        .fold(right_f, f32::min)
+        .max(left_f);
+    let min_y = verts
+        .iter()
+        .map(|v| v.y)
+        .fold(bottom_f, f32::min)
+        .max(top_f);
+    let max_x = verts
+        .iter()
+        .map(|v| v.x)
+        .fold(left_f, f32::max)
+        .min(right_f);
+    let max_y = verts
+        .iter()
+        .map(|v| v.y)
+        .fold(top_f, f32::max)
+        .min(bottom_f);
+    let (left, top) = (min_x.floor() as u32, min_y.floor() as u32);
+    let (right, bottom) = (max_x.ceil() as u32, max_y.ceil() as u32);
+    if right <= left || bottom <= top {
+        return;
+    }
+
+    // A pixel whose center is exactly on an edge belongs to only one of the
+    // triangles sharing it (since they go along it in opposite directions),
+    // so there are no gaps or overlaps between them.
+    let owns = |a: &ScreenVertex, b: &ScreenVertex| {
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        dy > 0.0 || (dy == 0.0 && dx < 0.0)
+    };
+    // Edges are always measured from the same end, so that rounding can't
+    // leave a pixel outside of both triangles sharing one.
+    let side = |a: &ScreenVertex, b: &ScreenVertex, x: f32, y: f32| {
+        if (a.x, a.y) <= (b.x, b.y) {
+            edge(a, b, x, y)
+        } else {
+            -edge(b, a, x, y)
+        }
+    };
+    let edges = [(1, 2), (2, 0), (0, 1)];
+
+    let stride = raster.width() as usize;
+    let pixels = raster.as_u8_slice_mut();
+    for y in top as usize..bottom as usize {
+        for x in left as usize..right as usize {
+            let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+            // Barycentric coordinates, skipping pixels outside the triangle.
+            let mut bary = [0.0; 3];
+            let mut inside = true;
+            for (b, (i, j)) in bary.iter_mut().zip(edges.iter()) {
+                let (a, c) = (&verts[*i], &verts[*j]);
+                *b = side(a, c, px, py) / area;
+                inside &= *b > 0.0 || (*b == 0.0 && owns(a, c));
+            }
+            if !inside {
+                continue;
+            }
+
+            // Depth test.
+            let index = y * stride + x;
+            if shader.depth {
+                let z: f32 = (0..3).map(|i| bary[i] * verts[i].z).sum();
+                if pass.depth_test && z >= depth[index] {
+                    continue;
+                }
+                if pass.depth_write {
+                    depth[index] = z;
+                }
+            }
+
+            // Perspective-correct attribute interpolation.
+            let weights = [
+                bary[0] * verts[0].inv_w,
+                bary[1] * verts[1].inv_w,
+                bary[2] * verts[2].inv_w,
+            ];
+            let total: f32 = weights.iter().sum();
+            let mut attr = [0.0; 6];
+            for (i, a) in attr.iter_mut().enumerate() {
+                *a = (0..3).map(|v| weights[v] * verts[v].attr[i]).sum::<f32>()
+                    / total;
+            }
+
+            // Shade the fragment.
+            let mut color = [1.0f32; 4];
+            if shader.gradient {
+                color.copy_from_slice(&attr[2..]);
+            }
+            if let (true, Some(texture)) = (shader.graphic, texture) {
+                let texel = sample(texture, attr[0], attr[1]);
+                for (c, t) in color.iter_mut().zip(texel.iter()) {
+                    *c *= t;
+                }
+            }
+            if let Some(tint) = shader.tint {
+                for (c, t) in color.iter_mut().zip(tint.iter()) {
+                    *c *= t;
+                }
+            }
+
+            // Write the fragment.
+            let dst = &mut pixels[index * 4..index * 4 + 4];
+            if shader.blend {
+                let mut old = [0.0; 4];
+                for (o, d) in old.iter_mut().zip(dst.iter()) {
+                    *o = f32::from(*d) / 255.0;
+                }
+                let color = blend(pass.blend, color, old);
+                for (d, c) in dst.iter_mut().zip(color.iter()) {
+                    *d = to_u8(*c);
+                }
+            } else {
+                for (d, c) in dst.iter_mut().zip(color.iter()) {
+                    *d = to_u8(*c);
+                }
+            }
+        }
+    }
+}
+
+// Blend a color onto a pixel, with the same factors as the OpenGL backend.
+fn blend(mode: Blend, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    let alpha = src[3].clamp(0.0, 1.0);
+    let mut out = [0.0; 4];
+    for (i, o) in out.iter_mut().enumerate().take(3) {
+        *o = match mode {
+            Blend::Alpha => src[i] * alpha + dst[i] * (1.0 - alpha),
+            Blend::Premultiplied => src[i] + dst[i] * (1.0 - alpha),
+            Blend::Additive => src[i] * alpha + dst[i],
+            Blend::Multiply => src[i] * dst[i],
+            Blend::Opaque => src[i],
+        };
+    }
+    out[3] = match mode {
+        Blend::Alpha => alpha * alpha + dst[3] * dst[3],
+        Blend::Premultiplied => alpha + dst[3] * (1.0 - alpha),
+        Blend::Additive | Blend::Multiply => dst[3],
+        Blend::Opaque => alpha,
+    };
+    out
+}
+
+// Sample a texture with nearest filtering and repeat wrapping.
+fn sample(texture: &Raster<SRgba8>, u: f32, v: f32) -> [f32; 4] {
+    let (width, height) = (texture.width(), texture.height());
+    if width == 0 || height == 0 {
+        return [0.0; 4];
+    }
+    let x = (u * width as f32).floor().rem_euclid(width as f32) as usize;
+    let y = (v * height as f32).floor().rem_euclid(height as f32) as usize;
+    let index = (y * width as usize + x) * 4;
+    let texel = &texture.as_u8_slice()[index..index + 4];
+    [
+        f32::from(texel[0]) / 255.0,
+        f32::from(texel[1]) / 255.0,
+        f32::from(texel[2]) / 255.0,
+        f32::from(texel[3]) / 255.0,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::ShaderBuilder;
+
+    // Colors in the test images, by the character each pixel is drawn as.
+    const PALETTE: &[(char, [u8; 4])] = &[
+        ('.', [0, 0, 255, 255]),
+        ('R', [255, 0, 0, 255]),
+        ('G', [0, 255, 0, 255]),
+        ('W', [255, 255, 255, 255]),
+        ('K', [0, 0, 0, 255]),
+        ('+', [128, 128, 255, 255]),
+    ];
+
+    // Shader for `Triangles`, which have colors with alpha.
+    const COLORS: u32 = 0;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    // Render one frame of commands onto a `width` × `height` raster.
+    fn render(width: u16, height: u16, cmds: Vec<GpuCmd>) -> Software {
+        let mut software = Software::new(width, height);
+        software.begin(true);
+        let builder = ShaderBuilder::new("", "").gradient().blend();
+        software.execute(&GpuCmd::ShaderId(builder, COLORS, None));
+        for cmd in cmds.iter() {
+            software.execute(cmd);
+        }
+        software
+    }
+
+    // Draw the frame as text, one line per row of pixels.
+    fn art(software: &Software) -> Vec<String> {
+        let width = software.raster.width() as usize;
+        let pixels = software.raster.as_u8_slice();
+        pixels
+            .chunks_exact(width * 4)
+            .map(|row| {
+                row.chunks_exact(4)
+                    .map(|pixel| {
+                        PALETTE
+                            .iter()
+                            .find(|(_, color)| color == pixel)
+                            .map_or('?', |(c, _)| *c)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Get the color of one pixel.
+    fn pixel(software: &Software, x: u32, y: u32) -> [u8; 4] {
+        let index = (y * software.raster.width() + x) as usize * 4;
+        let mut color = [0; 4];
+        color.copy_from_slice(&software.raster.as_u8_slice()[index..][..4]);
+        color
+    }
+
+    // Corners of two triangles covering a rectangle, facing the camera.
+    fn corners(x: f32, y: f32, w: f32, h: f32) -> [(f32, f32); 6] {
+        [
+            (x, y),
+            (x, y + h),
+            (x + w, y),
+            (x + w, y),
+            (x, y + h),
+            (x + w, y + h),
+        ]
+    }
+
+    // A rectangle laid out for `Triangles`.
+    fn quad(x: f32, y: f32, w: f32, h: f32, color: [f32; 4]) -> Vec<f32> {
+        let mut vertices = Vec::new();
+        for (x, y) in corners(x, y, w, h).iter() {
+            vertices.extend_from_slice(&[*x, *y]);
+            vertices.extend_from_slice(&color);
+        }
+        vertices
+    }
+
+    // A rectangle at a depth, laid out for a shader with depth and colors.
+    fn quad_3d(
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        z: f32,
+        color: [f32; 3],
+    ) -> Vec<f32> {
+        let mut vertices = Vec::new();
+        for (x, y) in corners(x, y, w, h).iter() {
+            vertices.extend_from_slice(&[*x, *y, z]);
+            vertices.extend_from_slice(&color);
+        }
+        vertices
+    }
+
+    // Commands to draw one shape with a shader (as shader 1, shape 0 and
+    // group 0), with its texture coordinates scaled by `repeat`.
+    fn draw(
+        builder: ShaderBuilder,
+        vertices: &[f32],
+        texture: Option<u32>,
+        repeat: f32,
+    ) -> Vec<GpuCmd> {
+        let shape = ShapeBuilder::new().vert(vertices).face(Transform::new());
+        let tex_coords = ([0.0, 0.0], [repeat, repeat]);
+        vec![
+            GpuCmd::ShaderId(builder, 1, None),
+            GpuCmd::ShapeId(shape, 0, 1),
+            GpuCmd::GroupId(0),
+            GpuCmd::GroupWriteTex(0, 0, 0, Transform::new(), tex_coords),
+            match texture {
+                Some(raster) => GpuCmd::DrawGraphic(1, 0, raster),
+                None => GpuCmd::Draw(1, 0),
+            },
+        ]
+    }
+
+    #[test]
+    fn shared_edges() {
+        // Half transparent, so pixels drawn by both triangles would be lighter.
+        let vertices = quad(0.25, 0.25, 0.5, 0.5, [1.0, 1.0, 1.0, 0.5]);
+        let frame = render(8, 8, vec![GpuCmd::Triangles(COLORS, vertices)]);
+        assert_eq!(
+            art(&frame),
+            [
+                "........", "........", "..++++..", "..++++..", "..++++..",
+                "..++++..", "........", "........",
+            ]
+        );
+    }
+
+    #[test]
+    fn back_faces() {
+        // The red triangle faces the camera, and the green one faces away.
+        let mut vertices = Vec::new();
+        for (x, y) in [(0.0, 0.0), (0.0, 1.0), (1.0, 0.0)].iter() {
+            vertices.extend_from_slice(&[*x, *y, 1.0, 0.0, 0.0, 1.0]);
+        }
+        for (x, y) in [(1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].iter() {
+            vertices.extend_from_slice(&[*x, *y, 0.0, 1.0, 0.0, 1.0]);
+        }
+        let frame = render(8, 8, vec![GpuCmd::Triangles(COLORS, vertices)]);
+        assert_eq!(
+            art(&frame),
+            [
+                "RRRRRRR.", "RRRRRR..", "RRRRR...", "RRRR....", "RRR.....",
+                "RR......", "R.......", "........",
+            ]
+        );
+    }
+
+    #[test]
+    fn depth() {
+        let shader = || ShaderBuilder::new("", "").depth().gradient();
+        // A near red rectangle, then a far green one behind all of it.
+        let mut vertices = quad_3d(0.0, 0.0, 0.75, 1.0, 0.5, [1.0, 0.0, 0.0]);
+        vertices.extend(quad_3d(-1.0, -1.0, 3.0, 3.0, 1.0, [0.0, 1.0, 0.0]));
+
+        let frame = render(8, 8, draw(shader(), &vertices, None, 1.0));
+        assert_eq!(art(&frame), ["RRRRRRGG"; 8]);
+
+        let mut cmds = vec![GpuCmd::SetDepthTest(false)];
+        cmds.extend(draw(shader(), &vertices, None, 1.0));
+        let frame = render(8, 8, cmds);
+        assert_eq!(art(&frame), ["GGGGGGGG"; 8]);
+    }
+
+    #[test]
+    fn blending() {
+        // Half transparent red onto the blue background.
+        let vertices = quad(0.0, 0.0, 1.0, 1.0, [1.0, 0.0, 0.0, 0.5]);
+        let expected = [
+            (Blend::Alpha, [128, 0, 128, 255]),
+            (Blend::Premultiplied, [255, 0, 128, 255]),
+            (Blend::Additive, [128, 0, 255, 255]),
+            (Blend::Multiply, [0, 0, 0, 255]),
+            (Blend::Opaque, [255, 0, 0, 128]),
+        ];
+        for (blend, color) in expected.iter() {
+            let cmds = vec![
+                GpuCmd::SetBlend(*blend),
+                GpuCmd::Triangles(COLORS, vertices.clone()),
+            ];
+            let frame = render(2, 2, cmds);
+            assert_eq!(pixel(&frame, 1, 1), *color, "{:?}", blend);
+        }
+    }
+
+    #[test]
+    fn scissor_and_viewport() {
+        let vertices = quad(0.0, 0.0, 1.0, 1.0, RED);
+
+        let scissor = Rect::new(0.25, 0.25, 0.5, 0.25);
+        let cmds = vec![
+            GpuCmd::SetScissor(Some(scissor)),
+            GpuCmd::Triangles(COLORS, vertices.clone()),
+        ];
+        assert_eq!(
+            art(&render(8, 8, cmds)),
+            [
+                "........", "........", "..RRRR..", "..RRRR..", "........",
+                "........", "........", "........",
+            ]
+        );
+
+        let viewport = Rect::new(0.5, 0.5, 0.5, 0.5);
+        let cmds = vec![
+            GpuCmd::SetViewport(Some(viewport)),
+            GpuCmd::Triangles(COLORS, vertices),
+        ];
+        assert_eq!(
+            art(&render(8, 8, cmds)),
+            [
+                "........", "........", "........", "........", "....RRRR",
+                "....RRRR", "....RRRR", "....RRRR",
+            ]
+        );
+    }
+
+    #[test]
+    fn near_and_far_planes() {
+        let mut vertices = Vec::new();
+        // A red floor from in front of the camera to behind it, which is
+        // clipped instead of dropped.
+        let floor = [(1.0, 0.75, 0.5), (0.0, 0.75, 0.5), (0.5, 0.75, -0.5)];
+        for (x, y, z) in floor.iter() {
+            vertices.extend_from_slice(&[*x, *y, *z, 1.0, 0.0, 0.0]);
+        }
+        // A green rectangle past the horizon.
+        vertices.extend(quad_3d(-1.0, -1.0, 3.0, 3.0, 1.0e4, [0.0, 1.0, 0.0]));
+        let shader = ShaderBuilder::new("", "").depth().gradient();
+        let frame = render(8, 8, draw(shader, &vertices, None, 1.0));
+        assert_eq!(
+            art(&frame),
+            [
+                "........", "........", "........", "........", "........",
+                "........", "RRRRRRRR", "RRRRRRRR",
+            ]
+        );
+    }
+
+    #[test]
+    fn textures() {
+        // Red, green, white and black texels.
+        let texture = Raster::<SRgba8>::with_u8_buffer(
+            2,
+            2,
+            vec![
+                255u8, 0, 0, 255, 0, 255, 0, 255, 255, 255, 255, 255, 0, 0, 0,
+                255,
+            ],
+        );
+        let mut vertices = Vec::new();
+        for (x, y) in corners(0.0, 0.0, 1.0, 1.0).iter() {
+            vertices.extend_from_slice(&[*x, *y, *x, *y]);
+        }
+        let shader = || ShaderBuilder::new("", "").graphic();
+
+        let mut cmds = vec![GpuCmd::RasterId(texture.clone(), 0)];
+        cmds.extend(draw(shader(), &vertices, Some(0), 1.0));
+        assert_eq!(
+            art(&render(8, 8, cmds)),
+            [
+                "RRRRGGGG", "RRRRGGGG", "RRRRGGGG", "RRRRGGGG", "WWWWKKKK",
+                "WWWWKKKK", "WWWWKKKK", "WWWWKKKK",
+            ]
+        );
+
+        // Texture coordinates past the edge repeat the texture.
+        let mut cmds = vec![GpuCmd::RasterId(texture, 0)];
+        cmds.extend(draw(shader(), &vertices, Some(0), 2.0));
+        assert_eq!(
+            art(&render(8, 8, cmds)),
+            [
+                "RRGGRRGG", "RRGGRRGG", "WWKKWWKK", "WWKKWWKK", "RRGGRRGG",
+                "RRGGRRGG", "WWKKWWKK", "WWKKWWKK",
+            ]
+        );
+    }
+}
@@ -0,0 +1,478 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Text layout and rasterization with `fonterator`.
+
+use super::{Atlas, Group, Shader, Shape, ShapeBuilder, Texture, Transform};
+use fonterator::{Font, TextAlign};
+use footile::{FillRule, PathOp, Plotter, Pt};
+use pix::{matte::Matte8, rgb::SRgba8, Raster};
+use std::collections::HashMap;
+
+// Size of a new atlas, in pixels.
+const ATLAS_SIZE: u32 = 256;
+// Glyph drawn after others to measure how far along the line they reach.
+const REFERENCE: char = 'M';
+
+/// Text, rasterized into an atlas `Texture` and laid out in a `Group`.
+///
+/// Each glyph is rasterized once per size, and reused from the atlas wherever
+/// it's laid out again.  When the atlas is full, it's cleared and only the
+/// glyphs of the current text are rasterized again; glyphs that still don't
+/// fit are left out.  To draw it, use
+/// [`Canvas::draw_graphic()`](trait.Canvas.html#tymethod.draw_graphic) with the
+/// same shader that the `Text` was created with:
+///
+/// ```rust,no_run,ignore
+/// canvas.draw_graphic(&shader, text.group(), text.texture());
+/// ```
+///
+/// Glyphs are white, so the color can be set with a tinted shader.  Lines
+/// wrap at spaces (or between any two characters, for words that are wider
+/// than a line), and are laid out from left to right.
+pub struct Text {
+    glyphs: Glyphs,
+    shape: Shape,
+    group: Group,
+    atlas: Atlas,
+    // Height of a line in the atlas, in pixels.
+    pixels: u16,
+    // Layout settings.
+    size: f32,
+    width: f32,
+    align: TextAlign,
+    transform: Transform,
+    text: String,
+}
+
+impl Text {
+    /// Create empty text with a font.
+    ///
+    /// `shader` must be built with `graphic` (and usually `blend` and `tint`),
+    /// but without `gradient` or `depth`.  Each glyph is rasterized `pixels`
+    /// tall (the height of a line), which should be about as tall as it
+    /// appears on screen.
+    pub fn new(shader: &Shader, font: Font<'static>, pixels: u16) -> Self {
+        #[rustfmt::skip]
+        let vertices = [
+            0.0, 0.0, 0.0, 0.0,  1.0, 1.0, 1.0, 1.0,  1.0, 0.0, 1.0, 0.0,
+            1.0, 1.0, 1.0, 1.0,  0.0, 0.0, 0.0, 0.0,  0.0, 1.0, 0.0, 1.0,
+        ];
+        let shape = ShapeBuilder::new()
+            .vert(&vertices)
+            .face(Transform::new())
+            .finish(shader);
+
+        Text {
+            glyphs: Glyphs::new(font),
+            shape,
+            group: Group::new(),
+            atlas: Atlas::with_size(ATLAS_SIZE),
+            pixels,
+            size: 0.05,
+            width: 1.0,
+            align: TextAlign::Left,
+            transform: Transform::new(),
+            text: String::new(),
+        }
+    }
+
+    /// Set the text, and lay it out.
+    pub fn set_text(&mut self, text: &str) {
+        if text != self.text {
+            self.text.clear();
+            self.text.push_str(text);
+            self.layout();
+        }
+    }
+
+    /// Set the height of a line of text (default: 0.05).
+    pub fn set_size(&mut self, size: f32) {
+        self.size = size;
+        self.layout();
+    }
+
+    /// Set the width that text wraps at (default: 1.0).
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width;
+        self.layout();
+    }
+
+    /// Set how lines are aligned within the wrapping width (default: left).
+    pub fn set_align(&mut self, align: TextAlign) {
+        self.align = align;
+        self.layout();
+    }
+
+    /// Set the transform applied to the text, which starts at the origin.
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+        self.layout();
+    }
+
+    /// Get the `Group` to draw.
+    pub fn group(&self) -> &Group {
+        &self.group
+    }
+
+    /// Get the atlas `Texture` to draw the `Group` with.
+    pub fn texture(&self) -> &Texture {
+        self.atlas.texture()
+    }
+
+    // Rasterize any new glyphs into the atlas, and write them into the group.
+    fn layout(&mut self) {
+        let row = self.width / self.size;
+        let glyphs = self.glyphs.place(&self.text, row, self.align);
+        let mut fits = true;
+        for (_, glyph, _) in glyphs.iter() {
+            fits = fits && self.rasterize(*glyph);
+        }
+        if !fits {
+            // The atlas is full, so start over with only the current text.
+            self.atlas.clear();
+            for (_, glyph, _) in glyphs.iter() {
+                self.rasterize(*glyph);
+            }
+        }
+
+        let pixels = f32::from(self.pixels);
+        self.group.clear();
+        let mut id = 0;
+        for (row, glyph, x) in glyphs {
+            let name = self.name(glyph);
+            let (region, tex_coords) =
+                match (self.atlas.region(&name), self.atlas.get(&name)) {
+                    (Some(region), Some(tex_coords)) => (region, tex_coords),
+                    _ => continue,
+                };
+            let (width, height) =
+                (region.width() as f32, region.height() as f32);
+            let offset = self.offset(glyph);
+            let transform = Transform::new()
+                .scale(
+                    width / pixels * self.size,
+                    height / pixels * self.size,
+                    1.0,
+                )
+                .translate(
+                    (x + offset as f32 / pixels) * self.size,
+                    row as f32 * self.size,
+                    0.0,
+                )
+                * self.transform;
+            self.group
+                .write_tex(id, &self.shape, &transform, tex_coords);
+            id += 1;
+        }
+    }
+
+    // Get the name of a glyph in the atlas, for its size.
+    fn name(&self, glyph: char) -> String {
+        format!("{:x}/{}", u32::from(glyph), self.pixels)
+    }
+
+    // Offset of the left edge of a rasterized glyph from its origin, in
+    // pixels.
+    fn offset(&self, glyph: char) -> i32 {
+        let left = points(&self.glyphs.outlines[&glyph])
+            .fold(f32::MAX, |left, point| left.min(point.0));
+        (left * f32::from(self.pixels)).floor() as i32
+    }
+
+    // Rasterize a glyph into the atlas, if it's not already there.  Returns
+    // `false` if the atlas is full.
+    fn rasterize(&mut self, glyph: char) -> bool {
+        let name = self.name(glyph);
+        if self.atlas.region(&name).is_some() {
+            return true;
+        }
+        let pixels = f32::from(self.pixels);
+        let path = &self.glyphs.outlines[&glyph];
+        let right = points(path).fold(f32::MIN, |right, p| right.max(p.0));
+        let offset = self.offset(glyph);
+        // Footile writes 8 pixels at a time, so round the width up to avoid it
+        // writing past the end of the raster.
+        let width = ((right * pixels).ceil() as i32 - offset + 7) as u32 & !7;
+        let height = u32::from(self.pixels);
+
+        // Render the glyph's coverage, and use it as the alpha channel.
+        let x = offset as f32;
+        let path = path.iter().map(|op| match *op {
+            PathOp::Move(a) => PathOp::Move(scale(a, pixels, x)),
+            PathOp::Line(a) => PathOp::Line(scale(a, pixels, x)),
+            PathOp::Quad(a, b) => {
+                PathOp::Quad(scale(a, pixels, x), scale(b, pixels, x))
+            }
+            PathOp::Cubic(a, b, c) => PathOp::Cubic(
+                scale(a, pixels, x),
+                scale(b, pixels, x),
+                scale(c, pixels, x),
+            ),
+            op => op,
+        });
+        let mut plotter =
+            Plotter::new(Raster::<Matte8>::with_clear(width, height));
+        plotter.fill(FillRule::NonZero, path, Matte8::new(255));
+        let coverage = plotter.raster();
+        let mut raster = Raster::<SRgba8>::with_clear(width, height);
+        for (pixel, alpha) in
+            raster.pixels_mut().iter_mut().zip(coverage.as_u8_slice())
+        {
+            *pixel = SRgba8::new(255, 255, 255, *alpha);
+        }
+        self.atlas.add(&name, &raster).is_ok()
+    }
+}
+
+// A font, and the outlines and spacing of the glyphs that have been laid out.
+struct Glyphs {
+    font: Font<'static>,
+    // Outlines of glyphs at the origin (empty for glyphs like spaces).
+    outlines: HashMap<char, Vec<PathOp>>,
+    // How far a glyph moves the next one along (its advance, plus kerning),
+    // by the glyph before it on the line.
+    steps: HashMap<(Option<char>, char), f32>,
+}
+
+impl Glyphs {
+    fn new(font: Font<'static>) -> Self {
+        Glyphs {
+            font,
+            outlines: HashMap::new(),
+            steps: HashMap::new(),
+        }
+    }
+
+    // Split the text into lines that are `row` wide, and find where each
+    // glyph with an outline is, as (line, glyph, x), where x is in lines.
+    fn place(
+        &mut self,
+        text: &str,
+        row: f32,
+        align: TextAlign,
+    ) -> Vec<(usize, char, f32)> {
+        let mut glyphs = Vec::new();
+        let mut line = 0;
+        for paragraph in text.split('\n') {
+            let chars: Vec<char> = paragraph.chars().collect();
+            if let TextAlign::Vertical = align {
+                for glyph in chars {
+                    if !self.outline(glyph).is_empty() {
+                        glyphs.push((line, glyph, 0.0));
+                    }
+                    line += 1;
+                }
+                continue;
+            }
+
+            let mut start = 0;
+            loop {
+                // Move along the line until a glyph doesn't fit (there's
+                // always at least one glyph on a line).
+                let mut xs = vec![0.0];
+                let mut end = start;
+                let mut space = None;
+                while end < chars.len() {
+                    let last = chars[start..end].last().cloned();
+                    let x = xs[end - start] + self.step(last, chars[end]);
+                    if x > row && end > start {
+                        break;
+                    }
+                    if chars[end] == ' ' {
+                        space = Some(end);
+                    }
+                    xs.push(x);
+                    end += 1;
+                }
+
+                // Wrap at the last space, dropping it, if there is one.
+                let (end, next) = match chars.get(end) {
+                    None => (end, end),
+                    Some(' ') => (end, end + 1),
+                    Some(_) => match space {
+                        Some(space) if space > start => (space, space + 1),
+                        _ => (end, end),
+                    },
+                };
+                let offset = match align {
+                    TextAlign::Right => row - xs[end - start],
+                    TextAlign::Center => (row - xs[end - start]) * 0.5,
+                    _ => 0.0,
+                };
+                for (glyph, x) in chars[start..end].iter().zip(xs) {
+                    if !self.outline(*glyph).is_empty() {
+                        glyphs.push((line, *glyph, offset + x));
+                    }
+                }
+                line += 1;
+                start = next;
+                if start >= chars.len() {
+                    break;
+                }
+            }
+        }
+        glyphs
+    }
+
+    // Get the outline of a glyph at the origin.
+    fn outline(&mut self, glyph: char) -> &[PathOp] {
+        let font = &self.font;
+        self.outlines.entry(glyph).or_insert_with(|| {
+            let mut string = [0; 4];
+            let string = glyph.encode_utf8(&mut string);
+            font.render(string, f32::MAX, TextAlign::Left).0.collect()
+        })
+    }
+
+    // Get how far `glyph` moves the next glyph along, after `last`.
+    fn step(&mut self, last: Option<char>, glyph: char) -> f32 {
+        if let Some(step) = self.steps.get(&(last, glyph)) {
+            return *step;
+        }
+        let step = match last {
+            None => self.reach(&[glyph]),
+            Some(last) => self.reach(&[last, glyph]) - self.step(None, last),
+        };
+        self.steps.insert((last, glyph), step);
+        step
+    }
+
+    // Get how far along the line a glyph drawn after `glyphs` starts.
+    fn reach(&mut self, glyphs: &[char]) -> f32 {
+        let mut skip = 0;
+        for glyph in glyphs.iter() {
+            skip += self.outline(*glyph).len();
+        }
+        let reference = match points(self.outline(REFERENCE)).next() {
+            Some(point) => point.0,
+            None => return 0.0,
+        };
+        let mut text: String = glyphs.iter().collect();
+        text.push(REFERENCE);
+        let path: Vec<PathOp> = self
+            .font
+            .render(&text, f32::MAX, TextAlign::Left)
+            .0
+            .collect();
+        path.get(skip..)
+            .and_then(|path| points(path).next())
+            .map_or(0.0, |point| point.0 - reference)
+    }
+}
+
+// Get the points of a path.
+fn points(path: &[PathOp]) -> impl Iterator<Item = Pt> + '_ {
+    path.iter().flat_map(|op| match *op {
+        PathOp::Move(a) | PathOp::Line(a) => vec![a],
+        PathOp::Quad(a, b) => vec![a, b],
+        PathOp::Cubic(a, b, c) => vec![a, b, c],
+        PathOp::Close() | PathOp::PenWidth(_) => vec![],
+    })
+}
+
+// Scale a point from font units (1.0 is the height of a line) to pixels, and
+// shift it left by `x` pixels.
+fn scale(point: Pt, pixels: f32, x: f32) -> Pt {
+    Pt(point.0 * pixels - x, point.1 * pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyphs() -> Glyphs {
+        Glyphs::new(fonterator::normal_font())
+    }
+
+    // Get the lines and glyphs of placed text.
+    fn lines(placed: &[(usize, char, f32)]) -> Vec<(usize, char)> {
+        placed
+            .iter()
+            .map(|(line, glyph, _)| (*line, *glyph))
+            .collect()
+    }
+
+    #[test]
+    fn layout() {
+        let mut glyphs = glyphs();
+        let placed = glyphs.place("AVA b", f32::MAX, TextAlign::Left);
+        assert_eq!(lines(&placed), [(0, 'A'), (0, 'V'), (0, 'A'), (0, 'b')]);
+
+        // Glyphs are where fonterator draws them in the same text.
+        let path: Vec<PathOp> = glyphs
+            .font
+            .render("AVA b", f32::MAX, TextAlign::Left)
+            .0
+            .collect();
+        let mut skip = 0;
+        for (_, glyph, x) in placed {
+            let outline = &glyphs.outlines[&glyph];
+            let start = points(&path[skip..]).next().unwrap().0;
+            let origin = points(outline).next().unwrap().0;
+            assert!((start - origin - x).abs() < 1e-4, "{} at {}", glyph, x);
+            skip += outline.len();
+        }
+
+        // Spaces take up room, but aren't placed.
+        let space = glyphs.step(Some('A'), ' ');
+        assert!(space > 0.0);
+        assert_eq!(glyphs.outline(' '), []);
+    }
+
+    #[test]
+    fn wrapping() {
+        let mut glyphs = glyphs();
+        let word = glyphs.step(None, 'a') + glyphs.step(Some('a'), 'b');
+        let placed = glyphs.place("ab ab ab", word * 1.5, TextAlign::Left);
+        assert_eq!(
+            lines(&placed),
+            [(0, 'a'), (0, 'b'), (1, 'a'), (1, 'b'), (2, 'a'), (2, 'b')]
+        );
+        // Each line starts at the left.
+        assert_eq!(placed[2].2, 0.0);
+        assert_eq!(placed[4].2, 0.0);
+
+        // Words wider than a line are split between glyphs.
+        let placed = glyphs.place("abab", word * 1.5, TextAlign::Left);
+        assert_eq!(lines(&placed), [(0, 'a'), (0, 'b'), (0, 'a'), (1, 'b')]);
+        // Every line has at least one glyph.
+        let placed = glyphs.place("ab", 0.0, TextAlign::Left);
+        assert_eq!(lines(&placed), [(0, 'a'), (1, 'b')]);
+        // Line breaks, including empty lines.
+        let placed = glyphs.place("a\n\nb", f32::MAX, TextAlign::Left);
+        assert_eq!(lines(&placed), [(0, 'a'), (2, 'b')]);
+
+        // Lines are aligned within the width.
+        let right = glyphs.place("ab", 1.0, TextAlign::Right);
+        assert!((right[1].2 + glyphs.step(Some('a'), 'b') - 1.0).abs() < 1e-6);
+        let center = glyphs.place("ab", 1.0, TextAlign::Center);
+        assert!((center[0].2 * 2.0 - right[0].2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn multibyte() {
+        let mut glyphs = glyphs();
+        // Wrapping in the middle of multibyte characters doesn't panic.
+        let placed = glyphs.place("éßΩ€ é", 0.0, TextAlign::Left);
+        assert_eq!(
+            lines(&placed),
+            [(0, 'é'), (1, 'ß'), (2, 'Ω'), (3, '€'), (4, 'é')]
+        );
+        let width = glyphs.step(None, 'é') + glyphs.step(Some('é'), 'ß');
+        let placed = glyphs.place("éß éß", width, TextAlign::Left);
+        assert_eq!(lines(&placed), [(0, 'é'), (0, 'ß'), (1, 'é'), (1, 'ß')]);
+        assert!(placed[1].2 > 0.0);
+        // Glyphs repeated on a line are all placed.
+        let placed = glyphs.place("ééé", f32::MAX, TextAlign::Left);
+        assert_eq!(placed.len(), 3);
+        assert!(placed[0].2 < placed[1].2 && placed[1].2 < placed[2].2);
+    }
+}
@@ -0,0 +1,833 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Tilemaps, drawn in chunks, and loading them from the Tiled editor.
+
+use super::json::Json;
+use super::mesh::base64;
+use super::xml::Element;
+use super::{
+    Canvas, Group, Rect, Shader, Shape, ShapeBuilder, Texture, Transform,
+};
+use std::convert::TryFrom;
+use std::fmt;
+
+// Tiles across and down a chunk.
+const CHUNK: u32 = 16;
+
+// Flags in the high bits of a tile, same as Tiled's.
+const FLIP_X: u32 = 0x8000_0000;
+const FLIP_Y: u32 = 0x4000_0000;
+// Diagonal and hexagonal rotation flags, which are ignored.
+const ROTATE: u32 = 0x3000_0000;
+
+// Most tiles in a map (64 MiB for each layer).
+const MAX_TILES: u32 = 1 << 24;
+
+/// Error from loading a tilemap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TilemapError {
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl TilemapError {
+    fn new(message: impl Into<String>) -> Self {
+        TilemapError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for TilemapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for TilemapError {}
+
+// A chunk of a layer, and whether its group needs to be rebuilt.
+struct Chunk {
+    group: Group,
+    dirty: bool,
+}
+
+// A layer of tiles.
+struct Layer {
+    name: String,
+    visible: bool,
+    tiles: Vec<u32>,
+    chunks: Vec<Chunk>,
+}
+
+/// A grid of tiles from a tileset `Texture`, in layers.
+///
+/// Tiles are numbered from 1, across and then down the tileset, and 0 is an
+/// empty tile.  A tile can be mirrored by adding
+/// [`FLIP_X`](#associatedconstant.FLIP_X) or
+/// [`FLIP_Y`](#associatedconstant.FLIP_Y) to it (the same as Tiled).
+///
+/// Each layer is split into chunks of 16×16 tiles, each in its own `Group`,
+/// which is only rebuilt when a tile in it changes.  The map starts at the
+/// origin, with tile (0, 0) in the top left.
+pub struct Tilemap {
+    tileset: Texture,
+    shape: Shape,
+    // Tiles across and down the tileset.
+    tiles: (u32, u32),
+    // Tiles across and down the map.
+    size: (u32, u32),
+    // Width and height of a tile.
+    tile_size: (f32, f32),
+    layers: Vec<Layer>,
+}
+
+impl Tilemap {
+    /// Mirror a tile horizontally.
+    pub const FLIP_X: u32 = FLIP_X;
+    /// Mirror a tile vertically.
+    pub const FLIP_Y: u32 = FLIP_Y;
+
+    /// Create a tilemap `size` tiles across and down, with no layers.
+    ///
+    /// `tileset` is a grid of `tiles` tiles across and down.  `shader` must
+    /// be built with `graphic` (and usually `blend`), but without `gradient`
+    /// or `depth`.
+    ///
+    /// # Panics
+    /// If the map has more than 2²⁴ (16,777,216) tiles.
+    pub fn new(
+        shader: &Shader,
+        tileset: Texture,
+        tiles: (u32, u32),
+        size: (u32, u32),
+    ) -> Self {
+        if let Err(error) = check_size(size) {
+            panic!("{}", error);
+        }
+        #[rustfmt::skip]
+        let vertices = [
+            0.0, 0.0, 0.0, 0.0,  1.0, 1.0, 1.0, 1.0,  1.0, 0.0, 1.0, 0.0,
+            1.0, 1.0, 1.0, 1.0,  0.0, 0.0, 0.0, 0.0,  0.0, 1.0, 0.0, 1.0,
+        ];
+        let shape = ShapeBuilder::new()
+            .vert(&vertices)
+            .face(Transform::new())
+            .finish(shader);
+
+        Tilemap {
+            tileset,
+            shape,
+            tiles,
+            size,
+            tile_size: (1.0 / CHUNK as f32, 1.0 / CHUNK as f32),
+            layers: Vec::new(),
+        }
+    }
+
+    /// Load a map saved by Tiled as TMX (XML).
+    ///
+    /// Only orthogonal, finite maps are supported, with layer data saved as
+    /// CSV or uncompressed Base64.  Every tile must be from the map's first
+    /// tileset, which is `tileset` (see [`new()`](#method.new)).  Tiles are
+    /// 1/16 wide, and as tall as the map's tiles are compared to that.
+    pub fn load_tmx(
+        text: &str,
+        shader: &Shader,
+        tileset: Texture,
+        tiles: (u32, u32),
+    ) -> Result<Self, TilemapError> {
+        let map = Element::parse(text).map_err(TilemapError::new)?;
+        if map.name != "map" {
+            return Err(TilemapError::new("TMX root isn't a map"));
+        }
+        let number = |key| -> Result<u32, TilemapError> {
+            map.get(key)
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| TilemapError::new(format!("map has no {}", key)))
+        };
+        let size = (number("width")?, number("height")?);
+        let tile_size = (number("tilewidth")?, number("tileheight")?);
+        check_size(size)?;
+        check_map(
+            map.get("orientation").unwrap_or("orthogonal"),
+            map.get("infinite") == Some("1"),
+        )?;
+        let gids = gid_range(
+            map.children
+                .iter()
+                .filter(|child| child.name == "tileset")
+                .map(|tileset| {
+                    tileset.get("firstgid").and_then(|gid| gid.parse().ok())
+                }),
+        )?;
+
+        let mut tilemap = Self::new(shader, tileset, tiles, size);
+        tilemap.set_pixel_size(tile_size);
+        tilemap.load_tmx_layers(&map, true, gids)?;
+        Ok(tilemap)
+    }
+
+    /// Load a map saved by Tiled as JSON, the same as
+    /// [`load_tmx()`](#method.load_tmx).
+    pub fn load_json(
+        text: &str,
+        shader: &Shader,
+        tileset: Texture,
+        tiles: (u32, u32),
+    ) -> Result<Self, TilemapError> {
+        let map = Json::parse(text).map_err(TilemapError::new)?;
+        let number = |key| -> Result<u32, TilemapError> {
+            map.get(key)
+                .and_then(Json::as_usize)
+                .and_then(|number| u32::try_from(number).ok())
+                .ok_or_else(|| TilemapError::new(format!("map has no {}", key)))
+        };
+        let size = (number("width")?, number("height")?);
+        let tile_size = (number("tilewidth")?, number("tileheight")?);
+        check_size(size)?;
+        check_map(
+            map.get("orientation")
+                .and_then(Json::as_str)
+                .unwrap_or("orthogonal"),
+            map.get("infinite").and_then(Json::as_bool) == Some(true),
+        )?;
+        let gids =
+            gid_range(map.get("tilesets").into_iter().flat_map(|tilesets| {
+                tilesets.iter().map(|tileset| {
+                    tileset
+                        .get("firstgid")
+                        .and_then(Json::as_usize)
+                        .map(|gid| gid as u32)
+                })
+            }))?;
+
+        let mut tilemap = Self::new(shader, tileset, tiles, size);
+        tilemap.set_pixel_size(tile_size);
+        tilemap.load_json_layers(&map, true, gids)?;
+        Ok(tilemap)
+    }
+
+    /// Get the number of tiles across and down the map.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Get the width and height of a tile.
+    pub fn tile_size(&self) -> (f32, f32) {
+        self.tile_size
+    }
+
+    /// Set the width and height of a tile (default: 1/16 by 1/16).
+    pub fn set_tile_size(&mut self, width: f32, height: f32) {
+        self.tile_size = (width, height);
+        for layer in self.layers.iter_mut() {
+            for chunk in layer.chunks.iter_mut() {
+                chunk.dirty = true;
+            }
+        }
+    }
+
+    /// Get the tileset `Texture`.
+    pub fn tileset(&self) -> &Texture {
+        &self.tileset
+    }
+
+    /// Add an empty layer on top of the others, returning its index.
+    pub fn add_layer(&mut self, name: &str) -> usize {
+        let (columns, rows) = self.chunks();
+        let chunks = (0..columns * rows)
+            .map(|_| Chunk {
+                group: Group::new(),
+                dirty: false,
+            })
+            .collect();
+        self.layers.push(Layer {
+            name: name.to_string(),
+            visible: true,
+            tiles: vec![0; self.area()],
+            chunks,
+        });
+        self.layers.len() - 1
+    }
+
+    /// Get the number of layers.
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Find the index of the first layer with a name.
+    pub fn layer(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    /// Show or hide a layer.
+    pub fn set_visible(&mut self, layer: usize, visible: bool) {
+        self.layers[layer].visible = visible;
+    }
+
+    /// Get the tile at (`x`, `y`) on a layer.
+    ///
+    /// # Panics
+    /// If the layer or position is out of bounds.
+    pub fn get(&self, layer: usize, x: u32, y: u32) -> u32 {
+        self.layers[layer].tiles[self.index(x, y)]
+    }
+
+    /// Set the tile at (`x`, `y`) on a layer.  Its chunk is rebuilt the next
+    /// time it's drawn.
+    ///
+    /// # Panics
+    /// If the layer or position is out of bounds.
+    pub fn set(&mut self, layer: usize, x: u32, y: u32, tile: u32) {
+        let index = self.index(x, y);
+        let chunk = self.chunk(x, y);
+        let layer = &mut self.layers[layer];
+        if layer.tiles[index] != tile {
+            layer.tiles[index] = tile;
+            layer.chunks[chunk].dirty = true;
+        }
+    }
+
+    /// Find which tile a point (like from
+    /// [`Ray::at_z()`](struct.Ray.html#method.at_z)) is on, if it's on the
+    /// map.
+    pub fn tile_at(&self, point: [f32; 2]) -> Option<(u32, u32)> {
+        let x = (point[0] / self.tile_size.0).floor();
+        let y = (point[1] / self.tile_size.1).floor();
+        if x >= 0.0
+            && y >= 0.0
+            && x < self.size.0 as f32
+            && y < self.size.1 as f32
+        {
+            Some((x as u32, y as u32))
+        } else {
+            None
+        }
+    }
+
+    /// Draw every visible layer, bottom to top, with the same shader that the
+    /// `Tilemap` was created with.  Only chunks that overlap `view` (the
+    /// rectangle the camera shows, in the map's coordinates) are drawn.
+    pub fn draw<C: Canvas>(
+        &mut self,
+        canvas: &mut C,
+        shader: &Shader,
+        view: Rect,
+    ) {
+        for layer in 0..self.layers.len() {
+            self.draw_layer(canvas, shader, layer, view);
+        }
+    }
+
+    /// Draw one layer, like [`draw()`](#method.draw) (for drawing other
+    /// things between layers).
+    pub fn draw_layer<C: Canvas>(
+        &mut self,
+        canvas: &mut C,
+        shader: &Shader,
+        layer: usize,
+        view: Rect,
+    ) {
+        if !self.layers[layer].visible {
+            return;
+        }
+        let (columns, rows) = self.chunks();
+        let chunk_width = self.tile_size.0 * CHUNK as f32;
+        let chunk_height = self.tile_size.1 * CHUNK as f32;
+        let range = |start: f32, end: f32, size: f32, count: u32| {
+            let first = (start / size).floor().max(0.0) as u32;
+            let last = (end / size).ceil().max(0.0) as u32;
+            first..last.min(count)
+        };
+        let across = range(view.x, view.x + view.width, chunk_width, columns);
+        let down = range(view.y, view.y + view.height, chunk_height, rows);
+        for y in down {
+            for x in across.clone() {
+                let chunk = (y * columns + x) as usize;
+                if self.layers[layer].chunks[chunk].dirty {
+                    self.rebuild(layer, x, y);
+                }
+                let group = &self.layers[layer].chunks[chunk].group;
+                if !group.is_empty() {
+                    canvas.draw_graphic(shader, group, &self.tileset);
+                }
+            }
+        }
+    }
+
+    // Write the tiles of a chunk into its group.
+    fn rebuild(&mut self, layer: usize, x: u32, y: u32) {
+        let (columns, _) = self.chunks();
+        let (width, height) = self.tile_size;
+        let tiles = &self.layers[layer].tiles;
+        let mut writes = Vec::new();
+        for ty in y * CHUNK..((y + 1) * CHUNK).min(self.size.1) {
+            for tx in x * CHUNK..((x + 1) * CHUNK).min(self.size.0) {
+                let tile = tiles[self.index(tx, ty)];
+                if let Some(tex_coords) = self.tex_coords(tile) {
+                    let transform = Transform::new()
+                        .scale(width, height, 1.0)
+                        .translate(tx as f32 * width, ty as f32 * height, 0.0);
+                    writes.push((transform, tex_coords));
+                }
+            }
+        }
+        let chunk = &mut self.layers[layer].chunks[(y * columns + x) as usize];
+        chunk.group.clear();
+        for (i, (transform, tex_coords)) in writes.iter().enumerate() {
+            chunk.group.write_tex(
+                i as u32,
+                &self.shape,
+                transform,
+                *tex_coords,
+            );
+        }
+        chunk.dirty = false;
+    }
+
+    // Get the texture coordinates of a tile, or `None` if it's empty (or not
+    // in the tileset).
+    fn tex_coords(&self, tile: u32) -> Option<([f32; 2], [f32; 2])> {
+        let index = (tile & !(FLIP_X | FLIP_Y | ROTATE)).checked_sub(1)?;
+        let (columns, rows) = self.tiles;
+        if index >= columns * rows {
+            return None;
+        }
+        let mut scale = [1.0 / columns as f32, 1.0 / rows as f32];
+        let mut offset = [
+            (index % columns) as f32 * scale[0],
+            (index / columns) as f32 * scale[1],
+        ];
+        if tile & FLIP_X != 0 {
+            offset[0] += scale[0];
+            scale[0] = -scale[0];
+        }
+        if tile & FLIP_Y != 0 {
+            offset[1] += scale[1];
+            scale[1] = -scale[1];
+        }
+        Some((offset, scale))
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(
+            x < self.size.0 && y < self.size.1,
+            "Tile ({}, {}) out of bounds",
+            x,
+            y
+        );
+        (y * self.size.0 + x) as usize
+    }
+
+    // Get the index of the chunk a tile is in.
+    fn chunk(&self, x: u32, y: u32) -> usize {
+        let (columns, _) = self.chunks();
+        ((y / CHUNK) * columns + x / CHUNK) as usize
+    }
+
+    // Get the number of tiles in a layer, which `new()` checked isn't too
+    // many.
+    fn area(&self) -> usize {
+        (self.size.0 * self.size.1) as usize
+    }
+
+    // Get the number of chunks across and down.
+    fn chunks(&self) -> (u32, u32) {
+        (self.size.0.div_ceil(CHUNK), self.size.1.div_ceil(CHUNK))
+    }
+
+    // Keep tiles 1/16 wide, with the aspect ratio of Tiled's tiles (in
+    // pixels).
+    fn set_pixel_size(&mut self, (width, height): (u32, u32)) {
+        let tile = 1.0 / CHUNK as f32;
+        let aspect = height as f32 / width.max(1) as f32;
+        self.set_tile_size(tile, tile * aspect);
+    }
+
+    // Add a layer from Tiled's global tile IDs.
+    fn add_gids(
+        &mut self,
+        name: &str,
+        visible: bool,
+        gids: &[u32],
+        (first, end): (u32, u32),
+    ) -> Result<(), TilemapError> {
+        let width = self.size.0;
+        if gids.len() != self.area() {
+            return Err(TilemapError::new(format!(
+                "layer {} has {} tiles, not {}",
+                name,
+                gids.len(),
+                self.area()
+            )));
+        }
+        let layer = self.add_layer(name);
+        self.layers[layer].visible = visible;
+        for (i, gid) in gids.iter().enumerate() {
+            let flags = gid & (FLIP_X | FLIP_Y | ROTATE);
+            let id = gid & !flags;
+            if id == 0 {
+                continue;
+            }
+            if id < first || id >= end {
+                return Err(TilemapError::new(format!(
+                    "layer {} has a tile that isn't from the first tileset",
+                    name
+                )));
+            }
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            self.set(layer, x, y, (id - first + 1) | flags);
+        }
+        Ok(())
+    }
+
+    // Add the tile layers in a map or group, in order.
+    fn load_tmx_layers(
+        &mut self,
+        parent: &Element,
+        visible: bool,
+        gids: (u32, u32),
+    ) -> Result<(), TilemapError> {
+        for child in parent.children.iter() {
+            let name = child.get("name").unwrap_or("");
+            let visible = visible && child.get("visible") != Some("0");
+            match child.name.as_str() {
+                "layer" => {
+                    let data = child.child("data").ok_or_else(|| {
+                        TilemapError::new(format!("layer {} has no data", name))
+                    })?;
+                    let tiles = tmx_data(data).map_err(|message| {
+                        TilemapError::new(format!(
+                            "layer {}: {}",
+                            name, message
+                        ))
+                    })?;
+                    self.add_gids(name, visible, &tiles, gids)?;
+                }
+                "group" => self.load_tmx_layers(child, visible, gids)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Add the tile layers in a map or group, in order.
+    fn load_json_layers(
+        &mut self,
+        parent: &Json,
+        visible: bool,
+        gids: (u32, u32),
+    ) -> Result<(), TilemapError> {
+        for child in parent.get("layers").into_iter().flat_map(Json::iter) {
+            let name = child.get("name").and_then(Json::as_str).unwrap_or("");
+            let visible = visible
+                && child.get("visible").and_then(Json::as_bool) != Some(false);
+            match child.get("type").and_then(Json::as_str) {
+                Some("tilelayer") => {
+                    let tiles = json_data(child).map_err(|message| {
+                        TilemapError::new(format!(
+                            "layer {}: {}",
+                            name, message
+                        ))
+                    })?;
+                    self.add_gids(name, visible, &tiles, gids)?;
+                }
+                Some("group") => self.load_json_layers(child, visible, gids)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+// Check that a map isn't too big to have a layer.
+fn check_size((width, height): (u32, u32)) -> Result<(), TilemapError> {
+    match width.checked_mul(height) {
+        Some(tiles) if tiles <= MAX_TILES => Ok(()),
+        _ => Err(TilemapError::new(format!(
+            "map is too big ({}×{} tiles)",
+            width, height
+        ))),
+    }
+}
+
+// Check that a map is a kind that's supported.
+fn check_map(orientation: &str, infinite: bool) -> Result<(), TilemapError> {
+    if orientation != "orthogonal" {
+        return Err(TilemapError::new(format!(
+            "{} maps aren't supported",
+            orientation
+        )));
+    }
+    if infinite {
+        return Err(TilemapError::new("infinite maps aren't supported"));
+    }
+    Ok(())
+}
+
+// Get the range of global tile IDs in the first tileset, from the first
+// global tile ID of each tileset.
+fn gid_range(
+    firsts: impl Iterator<Item = Option<u32>>,
+) -> Result<(u32, u32), TilemapError> {
+    let firsts = firsts
+        .collect::<Option<Vec<u32>>>()
+        .ok_or_else(|| TilemapError::new("tileset has no firstgid"))?;
+    let first = *firsts
+        .first()
+        .ok_or_else(|| TilemapError::new("map has no tilesets"))?;
+    let end = firsts
+        .iter()
+        .filter(|gid| **gid > first)
+        .min()
+        .cloned()
+        .unwrap_or(u32::MAX);
+    Ok((first, end))
+}
+
+// Decode global tile IDs stored as little-endian base64.
+fn base64_gids(text: &str) -> Result<Vec<u32>, String> {
+    let text: String = text.split_whitespace().collect();
+    let bytes = base64(&text).ok_or("bad base64")?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+        .collect())
+}
+
+// Read the global tile IDs of a TMX layer's `<data>`.
+fn tmx_data(data: &Element) -> Result<Vec<u32>, String> {
+    if let Some(compression) = data.get("compression") {
+        return Err(format!("{} compression isn't supported", compression));
+    }
+    match data.get("encoding") {
+        Some("csv") => data
+            .text
+            .split(',')
+            .map(|gid| gid.trim().parse().map_err(|_| "bad CSV".to_string()))
+            .collect(),
+        Some("base64") => base64_gids(&data.text),
+        Some(encoding) => Err(format!("{} encoding isn't supported", encoding)),
+        None => Ok(data
+            .children
+            .iter()
+            .filter(|tile| tile.name == "tile")
+            .map(|tile| {
+                tile.get("gid")
+                    .and_then(|gid| gid.parse().ok())
+                    .unwrap_or(0)
+            })
+            .collect()),
+    }
+}
+
+// Read the global tile IDs of a JSON tile layer.
+fn json_data(layer: &Json) -> Result<Vec<u32>, String> {
+    if let Some(compression) = layer.get("compression").and_then(Json::as_str) {
+        if !compression.is_empty() {
+            return Err(format!("{} compression isn't supported", compression));
+        }
+    }
+    match layer.get("data") {
+        Some(Json::String(data)) => base64_gids(data),
+        Some(Json::Array(data)) => data
+            .iter()
+            .map(|gid| {
+                gid.as_f64()
+                    .filter(|gid| {
+                        gid.fract() == 0.0
+                            && (0.0..=u32::MAX as f64).contains(gid)
+                    })
+                    .map(|gid| gid as u32)
+                    .ok_or_else(|| "bad tile".to_string())
+            })
+            .collect(),
+        _ => Err("no data".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::ShaderBuilder;
+    use pix::{rgb::SRgba8, Raster};
+
+    // A 3 × 2 map of 16 × 8 pixel tiles, with a ground layer (in CSV), and a
+    // hidden roof layer (in Base64, with a mirrored tile) in a group.
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.5" orientation="orthogonal" width="3" height="2"
+     tilewidth="16" tileheight="8" infinite="0">
+ <tileset firstgid="1" source="tiles.tsx"/>
+ <tileset firstgid="5" source="other.tsx"/>
+ <layer id="1" name="ground" width="3" height="2">
+  <data encoding="csv">
+1,2,0,
+4,0,3
+</data>
+ </layer>
+ <objectgroup id="2" name="spawns"/>
+ <group id="3" name="upstairs" visible="0">
+  <layer id="4" name="roof" width="3" height="2">
+   <data encoding="base64">AAAAAAAAAAAAAAAAAgAAgAMAAAAAAAAA</data>
+  </layer>
+ </group>
+</map>
+"#;
+
+    // The same map, saved as JSON.
+    const JSON: &str = r#"{
+        "width": 3, "height": 2, "tilewidth": 16, "tileheight": 8,
+        "orientation": "orthogonal", "infinite": false,
+        "tilesets": [
+            { "firstgid": 1, "source": "tiles.tsx" },
+            { "firstgid": 5, "source": "other.tsx" }
+        ],
+        "layers": [
+            { "type": "tilelayer", "name": "ground", "data": [1, 2, 0, 4, 0, 3] },
+            { "type": "objectgroup", "name": "spawns" },
+            { "type": "group", "name": "upstairs", "visible": false, "layers": [
+                { "type": "tilelayer", "name": "roof", "encoding": "base64",
+                  "compression": "", "data": "AAAAAAAAAAAAAAAAAgAAgAMAAAAAAAAA" }
+            ] }
+        ]
+    }"#;
+
+    fn shader() -> Shader {
+        Shader::new(ShaderBuilder::new("", "").graphic().blend())
+    }
+
+    fn tileset() -> Texture {
+        Texture::new(&Raster::<SRgba8>::with_clear(2, 2))
+    }
+
+    fn check(tilemap: &Tilemap) {
+        assert_eq!(tilemap.size(), (3, 2));
+        assert_eq!(tilemap.tile_size(), (1.0 / 16.0, 1.0 / 32.0));
+        assert_eq!(tilemap.layer_count(), 2);
+        let ground = tilemap.layer("ground").unwrap();
+        let tiles: Vec<u32> =
+            (0..6).map(|i| tilemap.get(ground, i % 3, i / 3)).collect();
+        assert_eq!(tiles, [1, 2, 0, 4, 0, 3]);
+        assert!(tilemap.layers[ground].visible);
+        let roof = tilemap.layer("roof").unwrap();
+        assert_eq!(tilemap.get(roof, 0, 1), 2 | Tilemap::FLIP_X);
+        assert_eq!(tilemap.get(roof, 1, 1), 3);
+        assert!(!tilemap.layers[roof].visible);
+        assert_eq!(tilemap.layer("spawns"), None);
+    }
+
+    #[test]
+    fn tmx() {
+        let tilemap =
+            Tilemap::load_tmx(TMX, &shader(), tileset(), (2, 2)).unwrap();
+        check(&tilemap);
+    }
+
+    #[test]
+    fn json() {
+        let tilemap =
+            Tilemap::load_json(JSON, &shader(), tileset(), (2, 2)).unwrap();
+        check(&tilemap);
+    }
+
+    #[test]
+    fn tmx_errors() {
+        let error = |text: &str| {
+            Tilemap::load_tmx(text, &shader(), tileset(), (2, 2))
+                .err()
+                .unwrap()
+                .message
+        };
+        let replace = |from, to| TMX.replacen(from, to, 1);
+        assert_eq!(error("<tileset/>"), "TMX root isn't a map");
+        assert_eq!(error("<map/>"), "map has no width");
+        assert_eq!(
+            error(&replace(r#"height="2""#, r#"height="4294967295""#)),
+            "map is too big (3×4294967295 tiles)"
+        );
+        assert_eq!(
+            error(&replace(r#"width="3""#, r#"width="4294967296""#)),
+            "map has no width"
+        );
+        assert_eq!(
+            error(&replace("orthogonal", "isometric")),
+            "isometric maps aren't supported"
+        );
+        assert_eq!(
+            error(&replace(r#"infinite="0""#, r#"infinite="1""#)),
+            "infinite maps aren't supported"
+        );
+        assert_eq!(
+            error(&replace(r#"firstgid="5" "#, "")),
+            "tileset has no firstgid"
+        );
+        assert_eq!(
+            error(&replace("1,2,0,", "1,2,")),
+            "layer ground has 5 tiles, not 6"
+        );
+        assert_eq!(
+            error(&replace("1,2,0,", "1,5,0,")),
+            "layer ground has a tile that isn't from the first tileset"
+        );
+        assert_eq!(
+            error(&replace("1,2,0,", "1,x,0,")),
+            "layer ground: bad CSV"
+        );
+        assert_eq!(
+            error(&replace(
+                r#"encoding="base64""#,
+                r#"encoding="base64" compression="zlib""#
+            )),
+            "layer roof: zlib compression isn't supported"
+        );
+        assert_eq!(error("<!-- <map/>"), "expected `-->` (line 1)");
+    }
+
+    #[test]
+    fn json_errors() {
+        let error = |text: &str| {
+            Tilemap::load_json(text, &shader(), tileset(), (2, 2))
+                .err()
+                .unwrap()
+                .message
+        };
+        let replace = |from, to| JSON.replacen(from, to, 1);
+        assert_eq!(
+            error(&replace(r#""width": 3"#, r#""width": 8388609"#)),
+            "map is too big (8388609×2 tiles)"
+        );
+        assert_eq!(
+            error(&replace(r#""width": 3"#, r#""width": 4294967296"#)),
+            "map has no width"
+        );
+        assert_eq!(
+            error(&replace(r#""infinite": false"#, r#""infinite": true"#)),
+            "infinite maps aren't supported"
+        );
+        assert_eq!(
+            error(&replace(r#""tilesets": ["#, r#""tilesets": [], "x": ["#)),
+            "map has no tilesets"
+        );
+        assert_eq!(
+            error(&replace("[1, 2, 0, 4, 0, 3]", "[1, 2, 0, 4, 0, -3]")),
+            "layer ground: bad tile"
+        );
+        assert_eq!(
+            error(&replace(r#""compression": """#, r#""compression": "gzip""#)),
+            "layer roof: gzip compression isn't supported"
+        );
+        assert_eq!(
+            error(&replace(r#""data": "AAAA"#, r#""data": "!AAA"#)),
+            "layer roof: bad base64"
+        );
+    }
+}
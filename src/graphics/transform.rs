@@ -0,0 +1,441 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Transformation matrices, cameras and picking.
+
+use std::ops::Mul;
+
+// Distance of the near clipping plane for shaders with depth (1cm), same as
+// the OpenGL backend.
+const NEAR: f32 = 0.01;
+// Distance of the far clipping plane for shaders with depth (5km), same as
+// the OpenGL backend.
+const HORIZON: f32 = 5000.0;
+
+/// A transformation (4×4 matrix), for moving shapes and cameras.
+///
+/// Transformations are combined with `*`, where `a * b` applies `a` and then
+/// `b`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    // Column-major.
+    mat: [[f32; 4]; 4],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transform {
+    /// Create a new identity matrix (transform that does nothing).
+    pub fn new() -> Self {
+        Self::from_mat4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Create a new Transform from a column-major 4x4 matrix.
+    pub fn from_mat4(mat: [[f32; 4]; 4]) -> Self {
+        Self { mat }
+    }
+
+    /// Get the column-major 4x4 matrix.
+    pub fn mat4(&self) -> [[f32; 4]; 4] {
+        self.mat
+    }
+
+    /// Scale transformation (make biggger or smaller).
+    pub fn scale(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.mat[0][0] *= x;
+        self.mat[1][1] *= y;
+        self.mat[2][2] *= z;
+        self
+    }
+
+    /// Translate (move) transformation.
+    pub fn translate(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.mat[3][0] += x;
+        self.mat[3][1] += y;
+        self.mat[3][2] += z;
+        self
+    }
+
+    /// Rotate transformation.  Parameters are quaternion in axis-angle form.
+    /// - `x`: axis-vector x.
+    /// - `y`: axis-vector y.
+    /// - `z`: axis-vector z.
+    /// - `c`: angle in cycles.
+    pub fn rotate(self, x: f32, y: f32, z: f32, cycles: f32) -> Self {
+        // Normalize the axis, and get the quaternion.
+        let length = ((x * x) + (y * y) + (z * z)).sqrt();
+        let angle = cycles * std::f32::consts::PI;
+        let scalar = angle.sin() / length;
+        let (x, y, z) = (x * scalar, y * scalar, z * scalar);
+        let w = angle.cos();
+
+        // Convert the quaternion into a matrix.
+        let (x2, y2, z2) = (x + x, y + y, z + z);
+        let (xx2, xy2, xz2) = (x2 * x, x2 * y, x2 * z);
+        let (yy2, yz2, zz2) = (y2 * y, y2 * z, z2 * z);
+        let (wx2, wy2, wz2) = (x2 * w, y2 * w, z2 * w);
+        self * Self::from_mat4([
+            [1.0 - yy2 - zz2, xy2 + wz2, xz2 - wy2, 0.0],
+            [xy2 - wz2, 1.0 - xx2 - zz2, yz2 + wx2, 0.0],
+            [xz2 + wy2, yz2 - wx2, 1.0 - xx2 - yy2, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Create a camera for shaders without depth that shows the rectangle
+    /// from (`left`, `top`) to (`right`, `bottom`) on the whole canvas.
+    /// `height` is the canvas' aspect ratio
+    /// ([`Canvas::height()`](trait.Canvas.html#tymethod.height)).
+    pub fn orthographic(
+        left: f32,
+        top: f32,
+        right: f32,
+        bottom: f32,
+        height: f32,
+    ) -> Self {
+        let (width, rows) = (right - left, bottom - top);
+        let view = Self::from_mat4([
+            [1.0 / width, 0.0, 0.0, 0.0],
+            [0.0, height / rows, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [-left / width, -top * height / rows, 0.0, 1.0],
+        ]);
+        // The camera applies after the coordinate system is converted to
+        // OpenGL's, so convert back first.
+        let coords = coords(height);
+        coords.inverse().unwrap_or_default() * view * coords
+    }
+
+    /// Create a camera for shaders with depth, with a vertical field of view
+    /// of `fov` (in cycles, so 0.25 is 90°), looking forward (+Z) from the
+    /// center of the canvas.  `height` is the canvas' aspect ratio
+    /// ([`Canvas::height()`](trait.Canvas.html#tymethod.height)).
+    pub fn perspective(fov: f32, height: f32) -> Self {
+        // Shaders with depth see `height` up and down at a distance of 1.
+        let zoom = height / (fov * std::f32::consts::PI).tan();
+        Self::new().scale(zoom, zoom, 1.0)
+    }
+
+    /// Get the inverse transformation, which undoes this one, or `None` if it
+    /// can't be undone (like scaling by zero).
+    pub fn inverse(&self) -> Option<Self> {
+        if self.mat.iter().flatten().any(|value| !value.is_finite()) {
+            return None;
+        }
+        // Gauss-Jordan elimination, on rows of the transposed matrix.
+        let mut a = self.mat;
+        let mut inv = Self::new().mat;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < f32::EPSILON * 1e-3 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let scale = 1.0 / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= factor * a[col][k];
+                        inv[row][k] -= factor * inv[col][k];
+                    }
+                }
+            }
+        }
+        Some(Self::from_mat4(inv))
+    }
+
+    /// Transform a point, dividing by W for projections.
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        let [x, y, z, w] = self.apply([point[0], point[1], point[2], 1.0]);
+        [x / w, y / w, z / w]
+    }
+
+    /// Transform a direction (without translating it).
+    pub fn transform_vector(&self, vector: [f32; 3]) -> [f32; 3] {
+        let [x, y, z, _] = self.apply([vector[0], vector[1], vector[2], 0.0]);
+        [x, y, z]
+    }
+
+    /// Find what's under a pointer, using this transformation as the camera.
+    ///
+    /// `pointer` is in the canvas' coordinates (0 to 1 across, 0 to `height`
+    /// down), and `depth` is whether the shader the world is drawn with has
+    /// depth.  Within a viewport, use the viewport's coordinates and aspect
+    /// ratio instead.  For shaders without depth, every point along the ray
+    /// is drawn at the pointer, so `origin`'s X and Y are what's under it.
+    pub fn unproject(
+        &self,
+        height: f32,
+        depth: bool,
+        pointer: [f32; 2],
+    ) -> Ray {
+        let inverse = (coords(height) * *self * projection(height, depth))
+            .inverse()
+            .unwrap_or_default();
+        let x = pointer[0] * 2.0 - 1.0;
+        let y = 1.0 - pointer[1] * 2.0 / height;
+        let near = inverse.transform_point([x, y, -1.0]);
+        let far = inverse.transform_point([x, y, 1.0]);
+        let direction = [far[0] - near[0], far[1] - near[1], far[2] - near[2]];
+        let length = direction.iter().map(|d| d * d).sum::<f32>().sqrt();
+        Ray {
+            origin: near,
+            direction: [
+                direction[0] / length,
+                direction[1] / length,
+                direction[2] / length,
+            ],
+        }
+    }
+
+    // Transform a homogeneous point.
+    pub(super) fn apply(&self, p: [f32; 4]) -> [f32; 4] {
+        let mut out = [0.0; 4];
+        for (r, value) in out.iter_mut().enumerate() {
+            *value = (0..4).map(|c| self.mat[c][r] * p[c]).sum();
+        }
+        out
+    }
+
+    // Determinant of the linear part (negative if it mirrors).
+    pub(super) fn determinant(&self) -> f32 {
+        let m = &self.mat;
+        m[0][0] * (m[1][1] * m[2][2] - m[2][1] * m[1][2])
+            - m[1][0] * (m[0][1] * m[2][2] - m[2][1] * m[0][2])
+            + m[2][0] * (m[0][1] * m[1][2] - m[1][1] * m[0][2])
+    }
+}
+
+// Convert from cala's coordinate system to OpenGL's (before the camera).
+pub(super) fn coords(height: f32) -> Transform {
+    Transform::new()
+        .scale(2.0, -2.0, -2.0)
+        .translate(-1.0, height, 0.0)
+}
+
+// Projection after the camera, same as the OpenGL backend's.
+pub(super) fn projection(height: f32, depth: bool) -> Transform {
+    if depth {
+        Transform::from_mat4([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0 / height, 0.0, 0.0],
+            [0.0, 0.0, (HORIZON + NEAR) / (NEAR - HORIZON), -1.0],
+            [0.0, 0.0, (2.0 * HORIZON * NEAR) / (NEAR - HORIZON), 0.0],
+        ])
+    } else {
+        Transform::new().scale(1.0, 1.0 / height, 1.0)
+    }
+}
+
+/// Transform a point, the same way as shapes in a group are: ignoring W, so
+/// projections don't apply.
+impl Mul<[f32; 3]> for Transform {
+    type Output = [f32; 3];
+
+    fn mul(self, vertex: [f32; 3]) -> Self::Output {
+        let [x, y, z, _] = self.apply([vertex[0], vertex[1], vertex[2], 1.0]);
+        [x, y, z]
+    }
+}
+
+impl Mul<Transform> for Transform {
+    type Output = Transform;
+
+    fn mul(self, rhs: Transform) -> Self::Output {
+        let mut mat = [[0.0; 4]; 4];
+        for (i, column) in mat.iter_mut().enumerate() {
+            for (j, value) in column.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.mat[i][k] * rhs.mat[k][j]).sum();
+            }
+        }
+        Transform { mat }
+    }
+}
+
+impl From<Transform> for window::Transform {
+    fn from(transform: Transform) -> Self {
+        window::Transform::from_mat4(transform.mat)
+    }
+}
+
+/// A ray from the camera into the world, from
+/// [`Transform::unproject()`](struct.Transform.html#method.unproject).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    /// Where the ray starts, on the near clipping plane.
+    pub origin: [f32; 3],
+    /// Which way the ray goes (1 unit long).
+    pub direction: [f32; 3],
+}
+
+impl Ray {
+    /// Get the point `distance` along the ray.
+    pub fn at(&self, distance: f32) -> [f32; 3] {
+        [
+            self.origin[0] + self.direction[0] * distance,
+            self.origin[1] + self.direction[1] * distance,
+            self.origin[2] + self.direction[2] * distance,
+        ]
+    }
+
+    /// Get where the ray crosses the plane at `z` (like the plane that the
+    /// ground or a 2D scene is drawn on), if it does.
+    pub fn at_z(&self, z: f32) -> Option<[f32; 3]> {
+        let distance = (z - self.origin[2]) / self.direction[2];
+        if distance.is_finite() && distance >= 0.0 {
+            Some(self.at(distance))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: &[f32], b: &[f32]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+
+    // Where a point is drawn on the canvas (0 to 1 across, 0 to `height`
+    // down), through `camera` and the projection.
+    fn canvas(
+        camera: Transform,
+        height: f32,
+        depth: bool,
+        point: [f32; 3],
+    ) -> [f32; 2] {
+        let clip = coords(height) * camera * projection(height, depth);
+        let [x, y, _] = clip.transform_point(point);
+        [(x + 1.0) / 2.0, (1.0 - y) * height / 2.0]
+    }
+
+    #[test]
+    fn inverse() {
+        let transform = Transform::new()
+            .scale(2.0, 3.0, 0.5)
+            .rotate(1.0, 2.0, 3.0, 0.1)
+            .translate(4.0, -5.0, 6.0);
+        let inverse = transform.inverse().unwrap();
+        let identity = Transform::new().mat4();
+        for product in [transform * inverse, inverse * transform].iter() {
+            let mat = product.mat4();
+            assert!(close(mat.as_flattened(), identity.as_flattened()));
+        }
+        let point = transform * [1.0, 2.0, 3.0];
+        assert!(close(&(inverse * point), &[1.0, 2.0, 3.0]));
+
+        assert_eq!(Transform::new().scale(1.0, 0.0, 1.0).inverse(), None);
+        let mut flat = Transform::new().mat4();
+        flat[2] = flat[0];
+        assert_eq!(Transform::from_mat4(flat).inverse(), None);
+    }
+
+    #[test]
+    fn inverse_not_finite() {
+        for &value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY].iter() {
+            let transform = Transform::new().translate(value, 0.0, 0.0);
+            assert_eq!(transform.inverse(), None);
+            let transform = Transform::new().scale(1.0, value, 1.0);
+            assert_eq!(transform.inverse(), None);
+        }
+    }
+
+    #[test]
+    fn orthographic() {
+        let height = 0.75;
+        let camera = Transform::orthographic(10.0, 20.0, 30.0, 35.0, height);
+        let corners = [
+            ([10.0, 20.0], [0.0, 0.0]),
+            ([30.0, 20.0], [1.0, 0.0]),
+            ([10.0, 35.0], [0.0, height]),
+            ([30.0, 35.0], [1.0, height]),
+            ([20.0, 27.5], [0.5, height / 2.0]),
+        ];
+        for (world, expected) in corners.iter() {
+            let point = [world[0], world[1], 0.0];
+            assert!(close(&canvas(camera, height, false, point), expected));
+            // The pointer at the same place is over the same point.
+            let ray = camera.unproject(height, false, *expected);
+            assert!(close(&ray.origin[..2], world), "{:?}", ray);
+        }
+    }
+
+    #[test]
+    fn perspective() {
+        let height = 0.5;
+        // 90°, so the edges are as far from the center as they are ahead.
+        let camera = Transform::perspective(0.25, height);
+        let center = [0.5, height / 2.0];
+        let top = [0.5, height / 2.0 - 2.0, 2.0];
+        let right = [0.5 + 2.0 / height, height / 2.0, 2.0];
+        assert!(close(&canvas(camera, height, true, top), &[0.5, 0.0]));
+        assert!(close(
+            &canvas(camera, height, true, right),
+            &[1.0, center[1]]
+        ));
+        let ahead = [0.5, height / 2.0, 7.0];
+        assert!(close(&canvas(camera, height, true, ahead), &center));
+
+        // The center of the canvas looks straight ahead.
+        let ray = camera.unproject(height, true, center);
+        assert!(close(&ray.direction, &[0.0, 0.0, 1.0]), "{:?}", ray);
+        assert!(close(&ray.at_z(7.0).unwrap(), &ahead));
+
+        // The top left corner looks up and left as far as it looks ahead.
+        let ray = camera.unproject(height, true, [0.0, 0.0]);
+        let corner = [0.5 - 3.0 / height, height / 2.0 - 3.0, 3.0];
+        assert!(close(&ray.at_z(3.0).unwrap(), &corner), "{:?}", ray);
+        let length = (1.0 / (height * height) + 2.0).sqrt();
+        let direction = [-1.0 / height / length, -1.0 / length, 1.0 / length];
+        assert!(close(&ray.direction, &direction), "{:?}", ray);
+    }
+
+    #[test]
+    fn at_z() {
+        let ray = Ray {
+            origin: [1.0, 2.0, 1.0],
+            direction: [0.0, 0.6, 0.8],
+        };
+        assert!(close(&ray.at_z(5.0).unwrap(), &[1.0, 5.0, 5.0]));
+        assert_eq!(ray.at_z(1.0), Some(ray.origin));
+        // Behind the ray.
+        assert_eq!(ray.at_z(0.0), None);
+        // Parallel to the plane, or not a number.
+        let flat = Ray {
+            direction: [1.0, 0.0, 0.0],
+            ..ray
+        };
+        assert_eq!(flat.at_z(5.0), None);
+        assert_eq!(ray.at_z(f32::NAN), None);
+        let broken = Ray {
+            direction: [f32::NAN; 3],
+            ..ray
+        };
+        assert_eq!(broken.at_z(5.0), None);
+    }
+}
@@ -0,0 +1,618 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Vector graphics, loaded from `.rvg` files or built from paths.
+
+use super::{Shader, Shape, ShapeBuilder, Texture, Transform};
+use footile::{FillRule, PathOp, Plotter, Pt};
+use pix::{matte::Matte8, rgb::SRgba8, Raster};
+use std::fmt;
+use std::io::Read;
+
+// Width in pixels that tessellated curves are smooth up to.
+const TESSELLATION_WIDTH: f32 = 1024.0;
+
+/// Error from loading a vector graphic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VectorError {
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl VectorError {
+    fn new(message: impl Into<String>) -> Self {
+        VectorError {
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for VectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for VectorError {}
+
+// A path, and how it's painted.
+struct Paint {
+    ops: Vec<PathOp>,
+    fill: Option<[f32; 4]>,
+    stroke: Option<([f32; 4], f32)>,
+}
+
+/// Vector graphic, made of filled and stroked paths.
+///
+/// Vector graphics can be rasterized into a `Texture` at any resolution, or
+/// tessellated into a `Shape`, so they stay sharp at any window size.
+pub struct VectorGraphic {
+    width: f32,
+    height: f32,
+    paths: Vec<Paint>,
+}
+
+impl VectorGraphic {
+    /// Create an empty vector graphic, with paths that go from (0, 0) to
+    /// (`width`, `height`).
+    pub fn new(width: f32, height: f32) -> Self {
+        VectorGraphic {
+            width,
+            height,
+            paths: Vec::new(),
+        }
+    }
+
+    /// Load a vector graphic from an `.rvg` file.
+    ///
+    /// Only the first model is loaded, and only fill colors, stroke colors and
+    /// stroke widths are used.
+    ///
+    /// # Panics
+    /// The file is read by the `rvg` crate, which panics on some invalid data
+    /// (bad compression, and unknown codes for attributes or path operations)
+    /// instead of returning an error.
+    pub fn load<R: Read>(reader: R) -> Result<Self, VectorError> {
+        let graphic = rvg::Graphic::load(reader)
+            .ok_or_else(|| VectorError::new("not a valid RVG file"))?;
+        let model = graphic
+            .models
+            .first()
+            .ok_or_else(|| VectorError::new("no models"))?;
+        let point = |index: u32| {
+            let index = index as usize * 2;
+            match graphic.vertex_list.get(index..index + 2) {
+                Some(xy) => Ok(Pt(xy[0], xy[1])),
+                None => Err(VectorError::new("point index out of bounds")),
+            }
+        };
+
+        let mut vector = VectorGraphic::new(model.width, model.height);
+        for (path, properties) in model.groups.iter() {
+            let ops = graphic
+                .group
+                .get(*path as usize)
+                .ok_or_else(|| VectorError::new("path index out of bounds"))?
+                .iter()
+                .map(|op| {
+                    Ok(match *op {
+                        rvg::PathOp::Close() => PathOp::Close(),
+                        rvg::PathOp::Move(a) => PathOp::Move(point(a)?),
+                        rvg::PathOp::Line(a) => PathOp::Line(point(a)?),
+                        rvg::PathOp::Quad(a, b) => {
+                            PathOp::Quad(point(a)?, point(b)?)
+                        }
+                        rvg::PathOp::Cubic(a, b, c) => {
+                            PathOp::Cubic(point(a)?, point(b)?, point(c)?)
+                        }
+                    })
+                })
+                .collect::<Result<_, VectorError>>()?;
+            let mut paint = Paint {
+                ops,
+                fill: None,
+                stroke: None,
+            };
+            let mut stroke = (None, 1.0);
+            for property in properties.iter() {
+                match *property {
+                    rvg::GroupProperty::FillColorRgba(color) => {
+                        paint.fill = Some(rgba(color))
+                    }
+                    rvg::GroupProperty::StrokeColorRgba(color) => {
+                        stroke.0 = Some(rgba(color))
+                    }
+                    rvg::GroupProperty::StrokeWidth(width) => stroke.1 = width,
+                    // Join styles, fill rules, glyphs and patterns.
+                    _ => {}
+                }
+            }
+            paint.stroke = stroke.0.map(|color| (color, stroke.1));
+            vector.paths.push(paint);
+        }
+        Ok(vector)
+    }
+
+    /// Add a filled path (built with [`Path2D`](struct.Path2D.html)).
+    pub fn fill<P: pix::el::Pixel>(
+        mut self,
+        path: Vec<PathOp>,
+        color: P,
+    ) -> Self
+    where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        self.paths.push(Paint {
+            ops: path,
+            fill: Some(convert(color)),
+            stroke: None,
+        });
+        self
+    }
+
+    /// Add a stroked path (built with [`Path2D`](struct.Path2D.html)).
+    pub fn stroke<P: pix::el::Pixel>(
+        mut self,
+        path: Vec<PathOp>,
+        width: f32,
+        color: P,
+    ) -> Self
+    where
+        pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+    {
+        self.paths.push(Paint {
+            ops: path,
+            fill: None,
+            stroke: Some((convert(color), width)),
+        });
+        self
+    }
+
+    /// Get the width of the vector graphic.
+    pub fn width(&self) -> f32 {
+        self.width
+    }
+
+    /// Get the height of the vector graphic.
+    pub fn height(&self) -> f32 {
+        self.height
+    }
+
+    /// Rasterize into a `width` × `height` raster.
+    pub fn raster(&self, width: u32, height: u32) -> Raster<SRgba8> {
+        let scale = (width as f32 / self.width, height as f32 / self.height);
+        let mut raster = Raster::<SRgba8>::with_clear(width, height);
+        for paint in self.paths.iter() {
+            let ops: Vec<PathOp> = paint
+                .ops
+                .iter()
+                .map(|op| match *op {
+                    PathOp::Move(a) => PathOp::Move(scale_pt(a, scale)),
+                    PathOp::Line(a) => PathOp::Line(scale_pt(a, scale)),
+                    PathOp::Quad(a, b) => {
+                        PathOp::Quad(scale_pt(a, scale), scale_pt(b, scale))
+                    }
+                    PathOp::Cubic(a, b, c) => PathOp::Cubic(
+                        scale_pt(a, scale),
+                        scale_pt(b, scale),
+                        scale_pt(c, scale),
+                    ),
+                    op => op,
+                })
+                .collect();
+            if let Some(color) = paint.fill {
+                // Footile can miss contours when filling several at once, so
+                // fill them one at a time, adding up how many times each pixel
+                // is wound around (in either direction).
+                let mut winding =
+                    vec![0i16; ((width + 7) & !7) as usize * height as usize];
+                for (contour, _) in flatten(&ops, 1.0) {
+                    let mut path = vec![PathOp::Move(contour[0])];
+                    path.extend(contour[1..].iter().map(|p| PathOp::Line(*p)));
+                    path.push(PathOp::Close());
+                    let sign =
+                        if polygon_area(&contour) < 0.0 { -1 } else { 1 };
+                    let mut plotter = plotter(width, height);
+                    plotter.fill(FillRule::NonZero, &path, Matte8::new(255));
+                    for (sum, coverage) in
+                        winding.iter_mut().zip(plotter.raster().as_u8_slice())
+                    {
+                        *sum += sign * i16::from(*coverage);
+                    }
+                }
+                let coverage: Vec<u8> = winding
+                    .iter()
+                    .map(|sum| sum.abs().min(255) as u8)
+                    .collect();
+                let coverage =
+                    Raster::with_u8_buffer((width + 7) & !7, height, coverage);
+                composite(&mut raster, &coverage, color);
+            }
+            if let Some((color, stroke)) = paint.stroke {
+                // Strokes are as thick as the graphic is scaled horizontally.
+                let pen = PathOp::PenWidth(stroke * scale.0);
+                let mut plotter = plotter(width, height);
+                plotter.stroke(
+                    std::iter::once(&pen).chain(&ops),
+                    Matte8::new(255),
+                );
+                composite(&mut raster, &plotter.raster(), color);
+            }
+        }
+        raster
+    }
+
+    /// Rasterize into a `width` × `height` `Texture`.
+    pub fn texture(&self, width: u32, height: u32) -> Texture {
+        Texture::new(&self.raster(width, height))
+    }
+
+    /// Tessellate into a `Shape` that's 1 wide (and keeps the aspect ratio).
+    ///
+    /// The vertices are built for `shader`: colors are only used if it has
+    /// `gradient` (and are opaque unless it also has `blend`), texture
+    /// coordinates stretch a texture over the whole graphic if it has
+    /// `graphic`, and Z is 0 if it has `depth`.  Curves are split into line
+    /// segments, holes aren't cut out of fills, and strokes don't have joins,
+    /// so rasterize graphics that need those.
+    pub fn shape(&self, shader: &Shader) -> Shape {
+        let format = shader.1;
+        let scale = self.width.recip();
+        let (width, height) = (self.width, self.height);
+        let mut vertices = Vec::new();
+        let mut push = |triangle: [Pt; 3], color: [f32; 4]| {
+            // Faces must wind the same way to not be culled.
+            let triangle = if area(&triangle) > 0.0 {
+                [triangle[0], triangle[2], triangle[1]]
+            } else {
+                triangle
+            };
+            for point in triangle.iter() {
+                vertices.extend_from_slice(&[point.0 * scale, point.1 * scale]);
+                if format.dimensions == 3 {
+                    vertices.push(0.0);
+                }
+                if format.graphic {
+                    vertices.extend_from_slice(&[
+                        point.0 / width,
+                        point.1 / height,
+                    ]);
+                }
+                vertices.extend_from_slice(&color[..format.components]);
+            }
+        };
+        for paint in self.paths.iter() {
+            let contours = flatten(&paint.ops, TESSELLATION_WIDTH / self.width);
+            if let Some(color) = paint.fill {
+                for (contour, _) in contours.iter() {
+                    for triangle in triangulate(contour) {
+                        push(triangle, color);
+                    }
+                }
+            }
+            if let Some((color, width)) = paint.stroke {
+                for (contour, closed) in contours.iter() {
+                    let mut lines: Vec<_> = contour.windows(2).collect();
+                    let last = [contour[contour.len() - 1], contour[0]];
+                    if *closed {
+                        lines.push(&last);
+                    }
+                    for line in lines {
+                        let (a, b) = (line[0], line[1]);
+                        let (x, y) = (b.0 - a.0, b.1 - a.1);
+                        let length = (x * x + y * y).sqrt();
+                        if length == 0.0 {
+                            continue;
+                        }
+                        let half = width * 0.5 / length;
+                        let normal = Pt(-y * half, x * half);
+                        let quad = [
+                            Pt(a.0 + normal.0, a.1 + normal.1),
+                            Pt(b.0 + normal.0, b.1 + normal.1),
+                            Pt(b.0 - normal.0, b.1 - normal.1),
+                            Pt(a.0 - normal.0, a.1 - normal.1),
+                        ];
+                        push([quad[0], quad[1], quad[2]], color);
+                        push([quad[2], quad[3], quad[0]], color);
+                    }
+                }
+            }
+        }
+        ShapeBuilder::new()
+            .vert(&vertices)
+            .face(Transform::new())
+            .finish(shader)
+    }
+}
+
+fn convert<P: pix::el::Pixel>(color: P) -> [f32; 4]
+where
+    pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
+{
+    use pix::chan::Channel;
+    use pix::el::Pixel;
+
+    let color: pix::rgb::SRgba32 = color.convert();
+    [
+        color.one().to_f32(),
+        color.two().to_f32(),
+        color.three().to_f32(),
+        color.four().to_f32(),
+    ]
+}
+
+fn rgba(color: [u8; 4]) -> [f32; 4] {
+    [
+        f32::from(color[0]) / 255.0,
+        f32::from(color[1]) / 255.0,
+        f32::from(color[2]) / 255.0,
+        f32::from(color[3]) / 255.0,
+    ]
+}
+
+fn scale_pt(point: Pt, scale: (f32, f32)) -> Pt {
+    Pt(point.0 * scale.0, point.1 * scale.1)
+}
+
+// Create a plotter for coverage of a `width` × `height` raster.
+fn plotter(width: u32, height: u32) -> Plotter<Matte8> {
+    // Footile writes 8 pixels at a time, so round the width up to avoid it
+    // writing past the end of the raster.
+    Plotter::new(Raster::with_clear((width + 7) & !7, height))
+}
+
+// Blend a color onto a raster, using coverage as alpha.
+fn composite(
+    raster: &mut Raster<SRgba8>,
+    coverage: &Raster<Matte8>,
+    color: [f32; 4],
+) {
+    let width = raster.width() as usize;
+    let stride = coverage.width() as usize;
+    let coverage = coverage.as_u8_slice();
+    for (i, pixel) in raster.as_u8_slice_mut().chunks_exact_mut(4).enumerate() {
+        let alpha = color[3]
+            * f32::from(coverage[i / width * stride + i % width])
+            / 255.0;
+        if alpha == 0.0 {
+            continue;
+        }
+        let under = f32::from(pixel[3]) / 255.0 * (1.0 - alpha);
+        let out = alpha + under;
+        for channel in 0..3 {
+            let dst = f32::from(pixel[channel]) / 255.0;
+            let value = (color[channel] * alpha + dst * under) / out;
+            pixel[channel] = (value * 255.0).round() as u8;
+        }
+        pixel[3] = (out * 255.0).round() as u8;
+    }
+}
+
+// Split a path into contours of points (and whether they're closed), turning
+// curves into line segments that are smooth when scaled up to `scale` pixels
+// per unit.
+fn flatten(ops: &[PathOp], scale: f32) -> Vec<(Vec<Pt>, bool)> {
+    let mut contours = Vec::new();
+    let mut contour = Vec::new();
+    let mut pen = Pt(0.0, 0.0);
+    let mut finish = |contour: &mut Vec<Pt>, closed| {
+        if contour.len() > 1 {
+            contours.push((std::mem::take(contour), closed));
+        } else {
+            contour.clear();
+        }
+    };
+    for op in ops {
+        match *op {
+            PathOp::Move(a) => {
+                finish(&mut contour, false);
+                contour.push(a);
+                pen = a;
+            }
+            PathOp::Line(a) => {
+                if contour.is_empty() {
+                    contour.push(pen);
+                }
+                contour.push(a);
+                pen = a;
+            }
+            PathOp::Quad(b, c) => {
+                if contour.is_empty() {
+                    contour.push(pen);
+                }
+                let a = pen;
+                let n = segments(&[a, b, c], scale);
+                for i in 1..=n {
+                    let t = i as f32 / n as f32;
+                    let s = 1.0 - t;
+                    contour.push(Pt(
+                        s * s * a.0 + 2.0 * s * t * b.0 + t * t * c.0,
+                        s * s * a.1 + 2.0 * s * t * b.1 + t * t * c.1,
+                    ));
+                }
+                pen = c;
+            }
+            PathOp::Cubic(b, c, d) => {
+                if contour.is_empty() {
+                    contour.push(pen);
+                }
+                let a = pen;
+                let n = segments(&[a, b, c, d], scale);
+                for i in 1..=n {
+                    let t = i as f32 / n as f32;
+                    let s = 1.0 - t;
+                    let (w0, w1, w2, w3) = (
+                        s * s * s,
+                        3.0 * s * s * t,
+                        3.0 * s * t * t,
+                        t * t * t,
+                    );
+                    contour.push(Pt(
+                        w0 * a.0 + w1 * b.0 + w2 * c.0 + w3 * d.0,
+                        w0 * a.1 + w1 * b.1 + w2 * c.1 + w3 * d.1,
+                    ));
+                }
+                pen = d;
+            }
+            PathOp::Close() => {
+                finish(&mut contour, true);
+                pen = Pt(0.0, 0.0);
+            }
+            PathOp::PenWidth(_) => {}
+        }
+    }
+    finish(&mut contour, false);
+    contours
+}
+
+// How many line segments a curve is split into, based on the length of its
+// control polygon in pixels (keeps the error around a fifth of a pixel).
+fn segments(points: &[Pt], scale: f32) -> usize {
+    let length: f32 = points.windows(2).map(|p| p[0].dist(p[1])).sum();
+    ((length * scale).sqrt().ceil() as usize).max(1)
+}
+
+// Twice the signed area of a polygon.
+fn polygon_area(points: &[Pt]) -> f32 {
+    (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+// Twice the signed area of a triangle.
+fn area(triangle: &[Pt; 3]) -> f32 {
+    let [a, b, c] = *triangle;
+    (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1)
+}
+
+// Split a polygon into triangles, by clipping off ears.
+fn triangulate(contour: &[Pt]) -> Vec<[Pt; 3]> {
+    let mut points: Vec<Pt> = Vec::with_capacity(contour.len());
+    for point in contour {
+        if points.last() != Some(point) {
+            points.push(*point);
+        }
+    }
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+
+    // Find which way the polygon winds, so convex corners can be found.
+    let winding = polygon_area(&points);
+    let convex = |triangle: &[Pt; 3]| area(triangle) * winding > 0.0;
+
+    let mut triangles = Vec::new();
+    let mut i = 0;
+    let mut tries = 0;
+    while points.len() > 2 && tries < points.len() {
+        let len = points.len();
+        let triangle = [
+            points[(i + len - 1) % len],
+            points[i],
+            points[(i + 1) % len],
+        ];
+        let ear = convex(&triangle)
+            && !points.iter().any(|p| {
+                !triangle.contains(p)
+                    && [
+                        [triangle[0], triangle[1], *p],
+                        [triangle[1], triangle[2], *p],
+                        [triangle[2], triangle[0], *p],
+                    ]
+                    .iter()
+                    .all(|t| area(t) * winding >= 0.0)
+            });
+        if ear {
+            triangles.push(triangle);
+            points.remove(i);
+            i %= points.len();
+            tries = 0;
+        } else {
+            i = (i + 1) % len;
+            tries += 1;
+        }
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Wrap data in a Zstandard frame, stored as one uncompressed block.
+    fn zstd(data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x28, 0xB5, 0x2F, 0xFD, 0x20, data.len() as u8];
+        let block = (data.len() as u32) << 3 | 1;
+        frame.extend_from_slice(&block.to_le_bytes()[..3]);
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    // An RVG file with one model, filling the path `ops` (which use `points`)
+    // with red.
+    fn rvg(points: &[f32], ops: &[u8]) -> Vec<u8> {
+        let mut data = b"rVg\0\0".to_vec();
+        for value in points.iter().chain(&[f32::NAN]) {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(ops);
+        data.extend_from_slice(&[0, 0]);
+        for value in [4.0f32, 2.0].iter() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&[1, 255, 0, 0, 255, 3]);
+        data.extend_from_slice(&2.0f32.to_le_bytes());
+        data.push(0);
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        // One frame, with no transforms.
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(&f32::NAN.to_le_bytes());
+        zstd(&data)
+    }
+
+    #[test]
+    fn load() {
+        // The left half of the graphic.
+        let points = [0.0, 0.0, 2.0, 0.0, 2.0, 2.0, 0.0, 2.0];
+        let ops = [
+            2, 0, 0, 0, 0, 3, 1, 0, 0, 0, 3, 2, 0, 0, 0, 3, 3, 0, 0, 0, 1,
+        ];
+        let vector = VectorGraphic::load(&rvg(&points, &ops)[..]).unwrap();
+        assert_eq!((vector.width(), vector.height()), (4.0, 2.0));
+        assert_eq!(vector.paths.len(), 1);
+        assert_eq!(vector.paths[0].fill, Some([1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(vector.paths[0].stroke, None);
+
+        let raster = vector.raster(4, 2);
+        let red: Vec<bool> = raster
+            .as_u8_slice()
+            .chunks(4)
+            .map(|pixel| pixel == [255, 0, 0, 255])
+            .collect();
+        assert_eq!(red, [true, true, false, false, true, true, false, false]);
+    }
+
+    #[test]
+    fn load_errors() {
+        let error = |data: &[u8]| VectorGraphic::load(data).err().unwrap();
+        assert_eq!(error(&zstd(b"rVg")).message, "not a valid RVG file");
+        assert_eq!(error(&zstd(b"PNG\0\0")).message, "not a valid RVG file");
+        // Points past the end of the vertex list.
+        let ops = [2, 0, 0, 0, 0, 3, 9, 0, 0, 0, 1];
+        assert_eq!(
+            error(&rvg(&[0.0, 0.0], &ops)).message,
+            "point index out of bounds"
+        );
+    }
+}
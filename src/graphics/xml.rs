@@ -0,0 +1,305 @@
+// Cala
+// Copyright © 2017-2021 Jeron Aldaron Lau.
+//
+// Licensed under any of:
+// - Apache License, Version 2.0 (https://www.apache.org/licenses/LICENSE-2.0)
+// - MIT License (https://mit-license.org/)
+// - Boost Software License, Version 1.0 (https://www.boost.org/LICENSE_1_0.txt)
+// At your choosing (See accompanying files LICENSE_APACHE_2_0.txt,
+// LICENSE_MIT.txt and LICENSE_BOOST_1_0.txt).
+
+//! Minimal XML reader, for the asset formats that use it.
+
+// An XML element (namespaces, doctypes and processing instructions are
+// ignored).
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct Element {
+    pub(super) name: String,
+    pub(super) attributes: Vec<(String, String)>,
+    pub(super) children: Vec<Element>,
+    // Text directly inside the element (including CDATA).
+    pub(super) text: String,
+}
+
+impl Element {
+    // Parse an XML document, returning its root element, or an error message
+    // if it's invalid.
+    pub(super) fn parse(text: &str) -> Result<Element, String> {
+        let mut parser = Parser {
+            text: text.as_bytes(),
+            index: 0,
+        };
+        parser.misc()?;
+        if parser.peek() != Some(b'<') {
+            return Err(parser.error("expected an element"));
+        }
+        let root = parser.element()?;
+        parser.misc()?;
+        if parser.index != parser.text.len() {
+            return Err(parser.error("unexpected data after root element"));
+        }
+        Ok(root)
+    }
+
+    // Get the value of an attribute.
+    pub(super) fn get(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Get the first child element with a name.
+    pub(super) fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    index: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.index.min(self.text.len())]
+            .iter()
+            .filter(|c| **c == b'\n')
+            .count()
+            + 1;
+        format!("{} (line {})", message, line)
+    }
+
+    fn whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.index += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.index).cloned()
+    }
+
+    fn starts_with(&self, word: &str) -> bool {
+        self.text[self.index..].starts_with(word.as_bytes())
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        if self.starts_with(word) {
+            self.index += word.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", word)))
+        }
+    }
+
+    // Skip past `end`.
+    fn skip_past(&mut self, end: &str) -> Result<(), String> {
+        while !self.starts_with(end) {
+            if self.peek().is_none() {
+                return Err(self.error(&format!("expected `{}`", end)));
+            }
+            self.index += 1;
+        }
+        self.index += end.len();
+        Ok(())
+    }
+
+    // Skip whitespace, comments, processing instructions and doctypes.
+    fn misc(&mut self) -> Result<(), String> {
+        loop {
+            self.whitespace();
+            if self.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let start = self.index;
+        while let Some(c) = self.peek() {
+            if matches!(c, b' ' | b'\t' | b'\n' | b'\r' | b'/' | b'>' | b'=') {
+                break;
+            }
+            self.index += 1;
+        }
+        if start == self.index {
+            return Err(self.error("expected a name"));
+        }
+        String::from_utf8(self.text[start..self.index].to_vec())
+            .map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn element(&mut self) -> Result<Element, String> {
+        // Skip the `<`.
+        self.index += 1;
+        let mut element = Element {
+            name: self.name()?,
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        };
+        loop {
+            self.whitespace();
+            match self.peek() {
+                Some(b'/') => {
+                    self.expect("/>")?;
+                    return Ok(element);
+                }
+                Some(b'>') => {
+                    self.index += 1;
+                    break;
+                }
+                Some(_) => {
+                    let key = self.name()?;
+                    self.whitespace();
+                    self.expect("=")?;
+                    self.whitespace();
+                    let quote = match self.peek() {
+                        Some(quote @ (b'"' | b'\'')) => quote,
+                        _ => return Err(self.error("expected a quote")),
+                    };
+                    self.index += 1;
+                    let value = self.text_until(quote)?;
+                    self.index += 1;
+                    element.attributes.push((key, value));
+                }
+                None => return Err(self.error("unclosed element")),
+            }
+        }
+        // Content.
+        loop {
+            if self.starts_with("</") {
+                self.index += 2;
+                let name = self.name()?;
+                if name != element.name {
+                    return Err(
+                        self.error(&format!("expected `</{}>`", element.name))
+                    );
+                }
+                self.whitespace();
+                self.expect(">")?;
+                return Ok(element);
+            } else if self.starts_with("<![CDATA[") {
+                self.index += 9;
+                let start = self.index;
+                self.skip_past("]]>")?;
+                element.text.push_str(&String::from_utf8_lossy(
+                    &self.text[start..self.index - 3],
+                ));
+            } else if self.starts_with("<!--") || self.starts_with("<?") {
+                self.misc()?;
+            } else if self.peek() == Some(b'<') {
+                element.children.push(self.element()?);
+            } else if self.peek().is_some() {
+                let text = self.text_until(b'<')?;
+                element.text.push_str(&text);
+            } else {
+                return Err(self.error("unclosed element"));
+            }
+        }
+    }
+
+    // Read text up to (not including) `end`, replacing entities.
+    fn text_until(&mut self, end: u8) -> Result<String, String> {
+        let mut bytes = Vec::new();
+        loop {
+            match self.peek() {
+                Some(c) if c == end => break,
+                Some(b'&') => {
+                    self.index += 1;
+                    let start = self.index;
+                    self.skip_past(";")?;
+                    let entity = &self.text[start..self.index - 1];
+                    let c = match entity {
+                        b"lt" => '<',
+                        b"gt" => '>',
+                        b"amp" => '&',
+                        b"quot" => '"',
+                        b"apos" => '\'',
+                        _ => self.character(entity)?,
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(
+                        c.encode_utf8(&mut buffer).as_bytes(),
+                    );
+                }
+                Some(c) => {
+                    bytes.push(c);
+                    self.index += 1;
+                }
+                None if end == b'<' => break,
+                None => return Err(self.error("unclosed attribute")),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    // Parse a character reference (`#123` or `#x7B`).
+    fn character(&self, entity: &[u8]) -> Result<char, String> {
+        let entity = std::str::from_utf8(entity).unwrap_or("");
+        let code = if let Some(hex) = entity.strip_prefix("#x") {
+            u32::from_str_radix(hex, 16).ok()
+        } else if let Some(decimal) = entity.strip_prefix('#') {
+            decimal.parse().ok()
+        } else {
+            None
+        };
+        code.and_then(std::char::from_u32)
+            .ok_or_else(|| self.error("unknown entity"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements() {
+        let root = Element::parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!DOCTYPE map>
+            <!-- A comment. -->
+            <map version="1.5" name='a &lt;&amp;&gt; b'>
+                <layer id="1"/>
+                <data encoding="csv"><![CDATA[1,2]]>&#51;&#x34;</data>
+            </map>
+            "#,
+        )
+        .unwrap();
+        assert_eq!(root.name, "map");
+        assert_eq!(root.get("version"), Some("1.5"));
+        assert_eq!(root.get("name"), Some("a <&> b"));
+        assert_eq!(root.get("missing"), None);
+        assert_eq!(root.children.len(), 2);
+        let layer = root.child("layer").unwrap();
+        assert_eq!(layer.get("id"), Some("1"));
+        assert!(layer.children.is_empty());
+        let data = root.child("data").unwrap();
+        assert_eq!(data.get("encoding"), Some("csv"));
+        assert_eq!(data.text, "1,234");
+        assert_eq!(root.child("missing"), None);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text| Element::parse(text).unwrap_err();
+        assert_eq!(error("text"), "expected an element (line 1)");
+        assert_eq!(error("<a>\n</b>"), "expected `</a>` (line 2)");
+        assert_eq!(error("<a>"), "unclosed element (line 1)");
+        assert_eq!(error("<a b=c/>"), "expected a quote (line 1)");
+        assert_eq!(error("<a b=\"c/>"), "unclosed attribute (line 1)");
+        assert_eq!(error("<a>&nbsp;</a>"), "unknown entity (line 1)");
+        assert_eq!(
+            error("<a/><b/>"),
+            "unexpected data after root element (line 1)"
+        );
+        assert_eq!(error("<a><!-- </a>"), "expected `-->` (line 1)");
+    }
+}
@@ -31,7 +31,7 @@ impl Future for FrameFuture {
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let internal = Internal::new_lazy();
         let mut lock = internal.frame.lock().unwrap();
-        if let Some(secs) = lock.frame.take() {
+        if let Some(secs) = lock.frame.take().or_else(quit_frame) {
             Poll::Ready(secs)
         } else {
             lock.waker = Some(cx.waker().clone());
@@ -94,6 +94,35 @@ impl Frame {
             resized: secs.2,
         }
     }
+
+    /// Capture this frame as a `Raster`, once it's finished rendering.
+    ///
+    /// The returned future resolves after the `Frame` is dropped and the
+    /// graphics thread has executed everything drawn on it (including
+    /// effects).  The raster is the size of the window (or of the frames
+    /// rendered in software), and opaque.
+    pub fn capture(&mut self) -> Capture {
+        let capture = Arc::new(Mutex::new(Default::default()));
+        let internal = Internal::new_lazy();
+        let mut cmds = internal.cmds.lock().unwrap();
+        cmds.push(GpuCmd::Capture(capture.clone()));
+        Capture(capture)
+    }
+
+    /// Record the commands sent to the graphics thread for this frame and the
+    /// ones after it, `frames` frames in all, to replay them later with a
+    /// [`Replay`](../graphics/struct.Replay.html).
+    ///
+    /// The returned future resolves to the recording once the last frame is
+    /// finished.  Recordings include every texture, shader and shape that's
+    /// alive, so they can be large.
+    pub fn record(&mut self, frames: u32) -> Recording {
+        let recording = Arc::new(Mutex::new(Default::default()));
+        let internal = Internal::new_lazy();
+        let mut cmds = internal.cmds.lock().unwrap();
+        cmds.push(GpuCmd::Record(recording.clone(), frames.max(1)));
+        Recording(recording)
+    }
 }
 
 impl Canvas for Frame {
@@ -109,6 +138,36 @@ impl Canvas for Frame {
         cmds.push(GpuCmd::SetCamera(camera));
     }
 
+    fn set_viewport(&mut self, viewport: Option<Rect>) {
+        let internal = Internal::new_lazy();
+        let mut cmds = internal.cmds.lock().unwrap();
+        cmds.push(GpuCmd::SetViewport(viewport));
+    }
+
+    fn set_scissor(&mut self, scissor: Option<Rect>) {
+        let internal = Internal::new_lazy();
+        let mut cmds = internal.cmds.lock().unwrap();
+        cmds.push(GpuCmd::SetScissor(scissor));
+    }
+
+    fn set_blend(&mut self, blend: Blend) {
+        let internal = Internal::new_lazy();
+        let mut cmds = internal.cmds.lock().unwrap();
+        cmds.push(GpuCmd::SetBlend(blend));
+    }
+
+    fn set_depth_test(&mut self, test: bool) {
+        let internal = Internal::new_lazy();
+        let mut cmds = internal.cmds.lock().unwrap();
+        cmds.push(GpuCmd::SetDepthTest(test));
+    }
+
+    fn set_depth_write(&mut self, write: bool) {
+        let internal = Internal::new_lazy();
+        let mut cmds = internal.cmds.lock().unwrap();
+        cmds.push(GpuCmd::SetDepthWrite(write));
+    }
+
     fn set_tint<P: pix::el::Pixel>(&mut self, shader: &Shader, tint: P)
     where
         pix::chan::Ch32: From<<P as pix::el::Pixel>::Chan>,
@@ -134,6 +193,10 @@ impl Canvas for Frame {
         cmds.push(GpuCmd::DrawGraphic(shader.0, group.0, graphic.0));
     }
 
+    fn draw_triangles(&mut self, vertices: &[f32]) {
+        push_triangles(vertices);
+    }
+
     fn elapsed(&self) -> std::time::Duration {
         self.elapsed
     }
@@ -150,6 +213,10 @@ impl Canvas for Frame {
 impl Drop for Frame {
     fn drop(&mut self) {
         let (lock, cvar) = &*self.pair;
+        // Nothing renders frames after `quit()`, so don't let them pile up.
+        if quit_frame().is_some() {
+            Internal::new_lazy().cmds.lock().unwrap().clear();
+        }
         let mut started = lock.lock().unwrap();
         *started = true;
         // We notify the condvar that the value has changed.